@@ -5,7 +5,7 @@ import logging
 from discord.ext import commands
 from hourai import utils
 from hourai.bot import cogs
-from hourai.db import proto
+from hourai.db import cases, proto
 from hourai import config as hourai_config
 from hourai.utils import embed as embed_utils
 from hourai.utils import invite, mentions, format
@@ -76,6 +76,16 @@ class MessageFilter(cogs.BaseCog):
             return guild.config.moderation
         return None
 
+    def open_cases(self, actions):
+        actions = [action for action in actions
+                   if not cases.is_opened_elsewhere(action)]
+        if not actions:
+            return
+        with self.bot.create_storage_session() as session:
+            for action in actions:
+                cases.open_case(session, action, moderator_id=self.bot.user.id)
+            session.commit()
+
     async def apply_rule(self, rule, message, reasons):
         if message.guild is None:
             return
@@ -118,6 +128,7 @@ class MessageFilter(cogs.BaseCog):
                 if not action.HasField('reason'):
                     action.reason = f"Triggered message filter: '{rule.name}'"
                 actions.append(action)
+            self.open_cases(actions)
             tasks.append(
                     self.bot.action_manager.sequentially_execute(actions))
 
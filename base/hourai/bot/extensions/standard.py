@@ -11,7 +11,6 @@ from hourai import utils
 from hourai.bot import cogs
 from hourai.db import proto, models
 from hourai.utils import embed, format, checks
-from sqlalchemy.orm.exc import NoResultFound
 
 
 DICE_REGEX = re.compile(r"(\d+)d(\d+)(.?)(\d*)")
@@ -175,65 +174,6 @@ class Standard(cogs.BaseCog):
                                 value=format.code_list(guild.features))
         await ctx.send(embed=msg_embed)
 
-    @commands.group(invoke_without_command=True)
-    @commands.guild_only()
-    async def tag(self, ctx, *, tag: str):
-        """Allows tagging text for later retreival.
-
-        If used without a subcomand, it will search the tag database for the
-        tag requested.
-        """
-        db_tag = ctx.session.query(models.Tag).get(
-                (ctx.guild.id, tag.casefold()))
-        await ctx.send(db_tag.response if db_tag else
-                       f"Tag `{tag}` does not exist.")
-
-    @tag.command(name="set")
-    @commands.guild_only()
-    @checks.is_moderator()
-    async def tag_set(self, ctx, tag: str, *, response: str = None):
-        """Sets a tag.
-
-        If the tag doesn't exist, a tag for the name will be created.
-        If the tag exists, it'll be updated:
-           ~tag set hi Hello World!
-
-        If the response is empty, the tag will be deleted.
-           ~tag set hi
-        """
-        lower_tag = tag.casefold()
-        query = ctx.session.query(models.Tag).filter_by(
-                guild_id=ctx.guild.id, tag=lower_tag)
-
-        if not response:
-            query.delete()
-            ctx.session.commit()
-            await ctx.send(f"Tag `{tag}` deleted")
-            return
-
-        try:
-            db_tag = query.one()
-            db_tag.response = response
-        except NoResultFound:
-            db_tag = models.Tag(guild_id=ctx.guild.id, tag=lower_tag,
-                                response=response)
-
-        ctx.session.add(db_tag)
-        ctx.session.commit()
-        await ctx.send(f"Tag `{tag}` set!")
-
-    @tag.command(name="list")
-    @commands.guild_only()
-    async def tag_list(self, ctx):
-        """Lists all available tags."""
-        db_tags = ctx.session.query(models.Tag.tag) \
-                             .filter_by(guild_id=ctx.guild.id) \
-                             .order_by(models.Tag.tag) \
-                             .all()
-        await ctx.send(
-            format.code_list(tag for tag, in db_tags) or
-            "No tags have been set! Use `~tag set` to make some.")
-
     # @commands.command()
     # async def convert(self, ctx, src_unit, dst_unit):
         # """ Converts units. (i.e. 2.54cm in -> 1 inch) """
@@ -18,6 +18,12 @@ PURGE_DM = ("You have been kicked from {} due to not being verified within "
             "mod regarding this.")
 BATCH_SIZE = 10
 MINIMUM_GUILD_SIZE = 150
+# Rejected members can appeal via DM. Appeals are handled by the logger, which
+# forwards them to the modlog.
+REJECTION_DM = ("You were not automatically verified when joining {}. If you "
+                "believe this was a mistake, reply with `~appeal <message>` "
+                "within 7 days to have the server's moderators review it.")
+MAX_OVERRIDE_REASON_LENGTH = 1024
 
 APPROVE_REACTION = '\u2705'
 KICK_REACTION = '\u274C'
@@ -283,25 +289,33 @@ class Verification(cogs.BaseCog):
         await ctx.send('Lockdown disabled.')
 
     @verification.command(name='verify')
-    async def verification_verify(self, ctx, member: discord.Member):
-        """Runs verification on a provided user.
+    @commands.bot_has_permissions(manage_roles=True)
+    async def verification_verify(self, ctx, member: discord.Member, *,
+                                  reason: str = None):
+        """Manually verifies a provided user, overriding the automatic
+        decision. The override is recorded along with who made it.
         Must be a moderator or owner of the server to use this command.
         Verification must be enabled on a server before running this. See
         "~help verification setup" for more information.
 
         Example Usage:
         ~validation verify @Bob
-        ~validation verify Alice
+        ~validation verify Alice Appealed, known to the mods.
         """
         config = ctx.guild.config.verification
         if not config.enabled:
             await ctx.send('Verification has not been setup. Please see '
                            '`~help verification` for more details.')
             return
+        if reason is not None and len(reason) > MAX_OVERRIDE_REASON_LENGTH:
+            await ctx.send(f'Reasons must be at most '
+                           f'{MAX_OVERRIDE_REASON_LENGTH} characters long.')
+            return
 
         verification_ctx = VerificationContext(ctx.bot, member, config)
-        await verification_ctx.verify_member(verifierS)
-        await verification_ctx.send_log_message(ctx, include_invite=False)
+        await self.verify_member(verification_ctx)
+        verification_ctx.record_result(moderator=ctx.author, reason=reason)
+        await ctx.send(f'Verified {member.mention}.')
 
     @verification.command(name="setup")
     async def verification_setup(self, ctx, role: discord.Role = None):
@@ -323,37 +337,6 @@ class Verification(cogs.BaseCog):
         await ctx.send('Verification disabled. To reenable, rerun `~verification '
                        'setup`.')
 
-    @verification.command(name="propagate")
-    @commands.bot_has_permissions(manage_roles=True)
-    async def verification_propagate(self, ctx):
-        config = ctx.guild.config.verification
-        if not config.HasField('role_id'):
-            await ctx.send('No verification config was found. Please run '
-                           '`~valdiation setup`')
-            return
-
-        role = ctx.guild.get_role(config.role_id)
-        if role is None:
-            await ctx.send("Verification role not found.")
-            config.ClearField('kick_unverifyd_users_after')
-            await ctx.guild.flush_config()
-            return
-
-        msg = await ctx.send('Propagating verification role...!')
-        last_update = float('-inf')
-        total_processed = 0
-        updated = 0
-        async for member in ctx.guild.fetch_members(limit=None):
-            total_processed += 1
-            if role not in member.roles:
-                await member.add_roles(role)
-                updated += 1
-            if updated > last_update + 10:
-                await msg.edit(
-                    content=f'Propagation Ongoing ({total_processed} done)...')
-                last_update = updated
-        await msg.edit(content='Propagation conplete!')
-
     @commands.Cog.listener()
     async def on_member_join(self, member):
         if not member.pending:
@@ -372,6 +355,13 @@ class Verification(cogs.BaseCog):
         ctx = VerificationContext(self.bot, member, config)
         await ctx.verify_member(verifierS)
         await self.verify_member(ctx)
+        ctx.record_result()
+        if not ctx.approved:
+            try:
+                await utils.send_dm(member,
+                                    REJECTION_DM.format(member.guild.name))
+            except discord.Forbidden:
+                pass
 
         msg = await ctx.send_modlog_message()
         try:
@@ -430,6 +420,7 @@ class Verification(cogs.BaseCog):
         ctx = VerificationContext(self.bot, target, guild.config.verification)
         try:
             await self.verify_member(ctx)
+            ctx.record_result(moderator=user, reason='Verified via reaction.')
             await guild.modlog.send(
                 f'{APPROVE_REACTION} **{user}** manually verified **{target}**'
                 f' via reaction.')
@@ -9,6 +9,21 @@ from hourai.db import models
 log = logging.getLogger('hourai.verification')
 Username = collections.namedtuple('Username', 'name discriminator timestamp')
 
+FNV_OFFSET_BASIS = 0xcbf29ce484222325
+FNV_PRIME = 0x100000001b3
+
+
+def config_version(config):
+    """Fingerprints a verification config, so that recorded decisions can be
+    traced back to the config they were made under. Uses 64-bit FNV-1a, as the
+    version must be stable across restarts, unlike hash().
+    """
+    version = FNV_OFFSET_BASIS
+    for byte in config.SerializeToString(deterministic=True):
+        version = ((version ^ byte) * FNV_PRIME) & 0xFFFFFFFFFFFFFFFF
+    # Stored in a signed bigint column.
+    return version - (1 << 64) if version >= (1 << 63) else version
+
 
 class VerificationContext:
 
@@ -97,6 +112,34 @@ class VerificationContext:
                 self.bot.dispatch('log_error', 'Verification', error)
         return self.approved
 
+    def record_result(self, moderator=None, reason=None):
+        """Records the decision made about the member. If a moderator is
+        provided, the decision is recorded as them overriding the verifiers.
+        """
+        if moderator is not None:
+            reasons = [reason or 'Verified by a moderator.']
+        else:
+            reasons = ([f'Approved: {r}' for r in self.approval_reasons] +
+                       [f'Rejected: {r}' for r in self.rejection_reasons])
+        with self.bot.create_storage_session() as session:
+            session.add(models.VerificationResult(
+                guild_id=self.guild.id,
+                user_id=self.member.id,
+                approved=self.approved,
+                reasons=reasons,
+                config_version=config_version(self.config),
+                moderator_id=moderator.id if moderator is not None else None))
+            if moderator is None:
+                column = ('verification_passes' if self.approved
+                          else 'verification_failures')
+                session.execute(f"""
+                INSERT INTO guild_daily_stats (guild_id, day, {column})
+                VALUES (:gi, (now() AT TIME ZONE 'UTC')::date, 1)
+                ON CONFLICT ON CONSTRAINT guild_daily_stats_pkey DO UPDATE
+                SET {column} = guild_daily_stats.{column} + 1
+                """, {"gi": self.guild.id})
+            session.commit()
+
     async def send_modlog_message(self):
         """Sends verification log to a the guild's modlog."""
         mention = None
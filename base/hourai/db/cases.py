@@ -0,0 +1,42 @@
+from hourai.db import proto
+
+# Allocates the guild's next case number and opens the case. Matches
+# `Case::create` in the Rust logger, which opens cases for the bans, unbans and
+# kicks it sees on the gateway.
+_OPEN_CASE = """
+WITH counter AS (
+    INSERT INTO case_counters (guild_id, last_case) VALUES (:guild_id, 1)
+    ON CONFLICT ON CONSTRAINT case_counters_pkey
+    DO UPDATE SET last_case = case_counters.last_case + 1
+    RETURNING last_case
+)
+INSERT INTO cases (guild_id, case_number, user_id, moderator_id, action,
+                   reason)
+SELECT :guild_id, last_case, :user_id, :moderator_id, :action, :reason
+FROM counter
+RETURNING case_number
+"""
+
+
+def open_case(session, action: proto.Action, moderator_id=None) -> int:
+    """Opens a new case for an action taken against a user. The session is
+    not committed.
+
+    Returns the case's number.
+    """
+    reason = action.reason if action.HasField('reason') else None
+    return session.execute(_OPEN_CASE, {
+        'guild_id': action.guild_id,
+        'user_id': action.user_id,
+        'moderator_id': moderator_id,
+        'action': action.SerializeToString(),
+        'reason': reason,
+    }).scalar()
+
+
+def is_opened_elsewhere(action: proto.Action) -> bool:
+    """Checks if a case for the action is opened when it is applied: bans and
+    kicks by the logger when it sees them, escalations by the escalation
+    history.
+    """
+    return action.WhichOneof('details') in ('ban', 'kick', 'escalate')
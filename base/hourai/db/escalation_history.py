@@ -1,5 +1,5 @@
 import collections
-from . import models
+from . import cases, models
 from datetime import datetime, timedelta
 from hourai.db import proto
 
@@ -89,6 +89,11 @@ class UserEscalationHistory:
         entry.action = actions
         self.session.add(entry)
 
+        case_action = proto.Action()
+        case_action.escalate.amount = diff
+        self.__setup_action(case_action, reason)
+        cases.open_case(self.session, case_action, moderator_id=authorizer.id)
+
         expiration = self.__schedule_deescalation(new_rung, entry)
 
         self.session.commit()
@@ -1,7 +1,7 @@
 import enum
 from . import proto
 from sqlalchemy import types
-from sqlalchemy import Column, UniqueConstraint
+from sqlalchemy import Column, UniqueConstraint, func
 from sqlalchemy.schema import Table, ForeignKey, Index
 from sqlalchemy.orm import relationship
 from sqlalchemy.ext.declarative import declarative_base
@@ -173,3 +173,19 @@ class Alias(Base):
     guild_id = Column(types.BigInteger, primary_key=True, autoincrement=False)
     name = Column(types.String(2000), primary_key=True)
     content = Column(types.String(2000))
+
+
+class VerificationResult(Base):
+    __tablename__ = 'verification_results'
+
+    id = Column(types.BigInteger, primary_key=True, autoincrement=True)
+    guild_id = Column(types.BigInteger, nullable=False)
+    user_id = Column(types.BigInteger, nullable=False)
+    created_at = Column(types.DateTime(timezone=True), nullable=False,
+                        server_default=func.now())
+    approved = Column(types.Boolean, nullable=False)
+    reasons = Column(postgresql.ARRAY(types.Text), nullable=False)
+    config_version = Column(types.BigInteger, nullable=False)
+    # The moderator that made the decision. None for automatic decisions.
+    moderator_id = Column(types.BigInteger)
+    appealed_at = Column(types.DateTime(timezone=True))
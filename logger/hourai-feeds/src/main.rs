@@ -14,7 +14,7 @@ async fn main() {
     let (tx, mut rx) = futures::channel::mpsc::unbounded();
     let client = Client {
         http: init::http_client(&config),
-        sql: hourai_sql::init(&config).await,
+        sql: hourai_sql::init(&config, "feeds").await,
         tx,
     };
 
@@ -0,0 +1,118 @@
+use crate::Client;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use hourai::models::id::GuildId;
+use hourai_sql::activity::{ActivityResolution, GuildActivity};
+use hourai_sql::stats::{DailyStat, GuildDailyStats};
+use hourai_sql::Member;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info};
+
+/// The resolution activity is sampled at.
+const SAMPLE_RESOLUTION: ActivityResolution = ActivityResolution::FiveMinutes;
+
+/// Counts the messages sent in each guild since the last activity sample was taken.
+#[derive(Clone, Default)]
+pub struct ActivityTracker(Arc<Mutex<HashMap<GuildId, i32>>>);
+
+impl ActivityTracker {
+    pub fn record_message(&self, guild_id: GuildId) {
+        *self.0.lock().unwrap().entry(guild_id).or_default() += 1;
+    }
+
+    fn take(&self) -> HashMap<GuildId, i32> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+/// Records the online count, member count, and message rate of every guild in five minute
+/// buckets, and periodically downsamples older buckets into coarser ones.
+pub async fn run_activity_sampling(client: Client) {
+    loop {
+        // Align samples with the bucket boundaries.
+        let now = Utc::now();
+        let next = SAMPLE_RESOLUTION.truncate(now)
+            + chrono::Duration::seconds(SAMPLE_RESOLUTION.seconds());
+        let delay = (next - now).to_std().unwrap_or_default();
+        tokio::time::sleep(delay).await;
+
+        // The sample covers the bucket that just ended.
+        let bucket = next - chrono::Duration::seconds(SAMPLE_RESOLUTION.seconds());
+        if let Err(err) = sample(&client, bucket).await {
+            error!("Error while sampling guild activity: {:?}", err);
+        }
+
+        let hourly = ActivityResolution::Hourly;
+        if hourly.truncate(next) == next {
+            if let Err(err) = downsample(&client, next).await {
+                error!("Error while downsampling guild activity: {:?}", err);
+            }
+        }
+    }
+}
+
+async fn sample(client: &Client, bucket: DateTime<Utc>) -> Result<()> {
+    let mut messages = client.activity.take();
+    if !messages.is_empty() {
+        let counts: Vec<(GuildId, i32)> = messages.iter().map(|(k, v)| (*k, *v)).collect();
+        GuildDailyStats::bulk_increment(DailyStat::Messages, bucket.date().naive_utc(), counts)
+            .execute(&client.sql)
+            .await?;
+    }
+    let guilds = client.cache.guilds();
+    let members: HashMap<i64, i64> = Member::count_present_by_guild(&guilds)
+        .fetch_all(client.sql.replica())
+        .await?
+        .into_iter()
+        .collect();
+
+    let samples: Vec<GuildActivity> = guilds
+        .into_iter()
+        .map(|guild_id| {
+            let mut activity = GuildActivity::new(guild_id, SAMPLE_RESOLUTION, bucket);
+            activity.online_count = client
+                .cache
+                .guild_online(guild_id)
+                .map(|online| online.len() as i32)
+                .unwrap_or(0);
+            activity.member_count = members.get(&activity.guild_id).cloned().unwrap_or(0) as i32;
+            activity.message_count = messages.remove(&guild_id).unwrap_or(0);
+            activity
+        })
+        .collect();
+
+    if !samples.is_empty() {
+        GuildActivity::bulk_insert(samples)
+            .execute(&client.sql)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn downsample(client: &Client, now: DateTime<Utc>) -> Result<()> {
+    for resolution in ActivityResolution::ALL.iter().cloned() {
+        let (coarser, retention) = match (resolution.coarser(), resolution.retention()) {
+            (Some(coarser), Some(retention)) => (coarser, retention),
+            _ => continue,
+        };
+        // Only downsample complete buckets of the coarser resolution.
+        let before = coarser.truncate(now - retention);
+        let mut txn = client.sql.begin().await?;
+        let result = GuildActivity::downsample(resolution, coarser, before)
+            .execute(&mut txn)
+            .await?;
+        GuildActivity::delete_before(resolution, before)
+            .execute(&mut txn)
+            .await?;
+        txn.commit().await?;
+        if result.rows_affected() > 0 {
+            info!(
+                "Downsampled {} guild activity buckets to {:?}",
+                result.rows_affected(),
+                coarser
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,273 @@
+use crate::Client;
+use anyhow::Result;
+use hourai::models::{
+    channel::GuildChannel,
+    guild::{PartialGuild, Role},
+    id::*,
+};
+use hourai_redis::{CachedGuild, CachedVoiceState, ToProto};
+use metrics::{counter, increment_counter};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, error, warn};
+
+/// The number of guilds checked on every pass of the auditor.
+const GUILDS_PER_PASS: usize = 10;
+
+/// The number of cached entries found to differ from Discord's copy for a single guild.
+#[derive(Default)]
+struct Drift {
+    guild: u64,
+    roles: u64,
+    channels: u64,
+    voice_states: u64,
+}
+
+impl Drift {
+    fn total(&self) -> u64 {
+        self.guild + self.roles + self.channels + self.voice_states
+    }
+}
+
+/// A cached resource that gateway events modify.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Resource {
+    /// Every resource in the guild, i.e. when the guild becomes available or is left.
+    All,
+    Guild,
+    Role(RoleId),
+    Channel(ChannelId),
+    VoiceState(UserId),
+}
+
+/// Tracks the cached resources that gateway events modify while their guild is being audited,
+/// so that the auditor does not overwrite them with the older copies it fetched.
+#[derive(Clone, Default)]
+pub struct AuditTracker(Arc<Mutex<HashMap<GuildId, HashSet<Resource>>>>);
+
+impl AuditTracker {
+    /// Records that a gateway event is modifying a cached resource. Should be called before
+    /// the cache is written to.
+    pub fn modify(&self, guild_id: GuildId, resource: Resource) {
+        if let Some(modified) = self.0.lock().unwrap().get_mut(&guild_id) {
+            modified.insert(resource);
+        }
+    }
+
+    fn begin(&self, guild_id: GuildId) {
+        self.0.lock().unwrap().insert(guild_id, HashSet::new());
+    }
+
+    fn end(&self, guild_id: GuildId) {
+        self.0.lock().unwrap().remove(&guild_id);
+    }
+
+    /// Checks if a resource was modified since the guild's audit began.
+    fn is_modified(&self, guild_id: GuildId, resource: Resource) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&guild_id)
+            .map(|modified| modified.contains(&Resource::All) || modified.contains(&resource))
+            .unwrap_or(false)
+    }
+}
+
+/// Periodically compares a rotating sample of the guilds in the Redis cache against the
+/// authoritative copy fetched over HTTP, repairing any entries that have drifted.
+///
+/// Missed or reordered gateway events are the usual culprit, so this is expected to find
+/// little to nothing under normal operation. Drift counts are exported as metrics so that any
+/// sustained increase is visible. Resources modified by gateway events while their guild is
+/// audited are left as is, as the fetched copies may predate the changes.
+pub async fn run_cache_audit(client: Client, interval: Duration) {
+    let mut offset = 0;
+    loop {
+        tokio::time::sleep(interval).await;
+        let mut guilds: Vec<GuildId> = client.cache.guilds().into_iter().collect();
+        if guilds.is_empty() {
+            continue;
+        }
+        guilds.sort_unstable();
+        offset %= guilds.len();
+        let sample: Vec<GuildId> = guilds
+            .iter()
+            .cycle()
+            .skip(offset)
+            .take(GUILDS_PER_PASS.min(guilds.len()))
+            .cloned()
+            .collect();
+        offset += sample.len();
+
+        for guild_id in sample {
+            client.audits.begin(guild_id);
+            let result = audit_guild(&client, guild_id).await;
+            client.audits.end(guild_id);
+            match result {
+                Ok(drift) => record_drift(guild_id, &drift),
+                Err(err) => error!(
+                    "Error while auditing cache for guild {}: {:?}",
+                    guild_id, err
+                ),
+            }
+        }
+    }
+}
+
+fn record_drift(guild_id: GuildId, drift: &Drift) {
+    increment_counter!("hourai_cache_audit_guilds_total");
+    counter!("hourai_cache_audit_drift_total", drift.guild, "resource" => "guild");
+    counter!("hourai_cache_audit_drift_total", drift.roles, "resource" => "role");
+    counter!("hourai_cache_audit_drift_total", drift.channels, "resource" => "channel");
+    counter!("hourai_cache_audit_drift_total", drift.voice_states, "resource" => "voice_state");
+    if drift.total() > 0 {
+        warn!(
+            "Repaired cache drift in guild {}: guild: {}, roles: {}, channels: {}, voice states: {}",
+            guild_id, drift.guild, drift.roles, drift.channels, drift.voice_states
+        );
+    } else {
+        debug!("No cache drift found in guild {}", guild_id);
+    }
+}
+
+async fn audit_guild(client: &Client, guild_id: GuildId) -> Result<Drift> {
+    let mut redis = client.redis.clone();
+    let mut drift = Drift::default();
+    let is_modified = |resource| client.audits.is_modified(guild_id, resource);
+
+    let guild = match client.http_client.guild(guild_id).await? {
+        Some(guild) => guild,
+        None => {
+            if is_modified(Resource::All) {
+                return Ok(drift);
+            }
+            // The bot is no longer in the guild, none of the cached data should exist.
+            CachedGuild::delete(guild_id, &mut redis).await?;
+            CachedVoiceState::clear_guild(guild_id, &mut redis).await?;
+            drift.guild += 1;
+            return Ok(drift);
+        }
+    };
+    let roles = client.http_client.roles(guild_id).await?;
+    let channels = client.http_client.guild_channels(guild_id).await?;
+
+    let cached =
+        CachedGuild::fetch_resource::<PartialGuild>(guild_id, guild_id, &mut redis).await?;
+    if cached.as_ref() != Some(&guild.to_proto()) && !is_modified(Resource::Guild) {
+        CachedGuild::update(&guild, &mut redis).await?;
+        drift.guild += 1;
+    }
+
+    let cached = CachedGuild::fetch_all_resources::<Role>(guild_id, &mut redis).await?;
+    let fresh: HashMap<u64, &Role> = roles.iter().map(|role| (role.id.0, role)).collect();
+    let (changed, removed) = diff(
+        cached.into_iter().map(|proto| (proto.get_role_id(), proto)),
+        fresh.iter().map(|(id, role)| (*id, role.to_proto())),
+    );
+    for id in changed {
+        if !is_modified(Resource::Role(RoleId(id))) {
+            CachedGuild::save_resource(guild_id, RoleId(id), fresh[&id], &mut redis).await?;
+            drift.roles += 1;
+        }
+    }
+    for id in removed {
+        if !is_modified(Resource::Role(RoleId(id))) {
+            CachedGuild::delete_resource::<Role>(guild_id, RoleId(id), &mut redis).await?;
+            drift.roles += 1;
+        }
+    }
+
+    let cached = CachedGuild::fetch_all_resources::<GuildChannel>(guild_id, &mut redis).await?;
+    let fresh: HashMap<u64, &GuildChannel> = channels.iter().map(|ch| (ch.id().0, ch)).collect();
+    let (changed, removed) = diff(
+        cached
+            .into_iter()
+            .map(|proto| (proto.get_channel_id(), proto)),
+        fresh.iter().map(|(id, ch)| (*id, ch.to_proto())),
+    );
+    for id in changed {
+        if !is_modified(Resource::Channel(ChannelId(id))) {
+            CachedGuild::save_resource(guild_id, ChannelId(id), fresh[&id], &mut redis).await?;
+            drift.channels += 1;
+        }
+    }
+    for id in removed {
+        if !is_modified(Resource::Channel(ChannelId(id))) {
+            CachedGuild::delete_resource::<GuildChannel>(guild_id, ChannelId(id), &mut redis)
+                .await?;
+            drift.channels += 1;
+        }
+    }
+
+    // Discord does not expose voice states over HTTP, so the best that can be done is to
+    // remove any that point at channels that no longer exist.
+    let voice = CachedVoiceState::get_channels(guild_id, &mut redis).await?;
+    for (user_id, channel_id) in voice {
+        let is_stale = !fresh.contains_key(&channel_id.0)
+            && !is_modified(Resource::Channel(channel_id))
+            && !is_modified(Resource::VoiceState(user_id));
+        if is_stale {
+            CachedVoiceState::remove(guild_id, user_id, &mut redis).await?;
+            drift.voice_states += 1;
+        }
+    }
+
+    Ok(drift)
+}
+
+/// Compares the cached and fresh copies of a set of resources.
+///
+/// Returns the IDs of the resources that are missing from the cache or differ from the fresh
+/// copy, and the IDs of the resources that are cached but no longer exist.
+fn diff<K: Eq + Hash + Copy, V: PartialEq>(
+    cached: impl Iterator<Item = (K, V)>,
+    fresh: impl Iterator<Item = (K, V)>,
+) -> (Vec<K>, Vec<K>) {
+    let mut cached: HashMap<K, V> = cached.collect();
+    let mut changed = Vec::new();
+    for (id, value) in fresh {
+        if cached.remove(&id).as_ref() != Some(&value) {
+            changed.push(id);
+        }
+    }
+    (changed, cached.into_iter().map(|(id, _)| id).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_modifications_are_tracked_during_audits() {
+        let audits = AuditTracker::default();
+        let (guild_id, role) = (GuildId(1), Resource::Role(RoleId(2)));
+        audits.modify(guild_id, role);
+        assert!(!audits.is_modified(guild_id, role));
+
+        audits.begin(guild_id);
+        assert!(!audits.is_modified(guild_id, role));
+        audits.modify(guild_id, role);
+        audits.modify(GuildId(3), role);
+        assert!(audits.is_modified(guild_id, role));
+        assert!(!audits.is_modified(guild_id, Resource::Guild));
+        assert!(!audits.is_modified(GuildId(3), role));
+
+        audits.modify(guild_id, Resource::All);
+        assert!(audits.is_modified(guild_id, Resource::Channel(ChannelId(4))));
+
+        audits.end(guild_id);
+        assert!(!audits.is_modified(guild_id, role));
+    }
+
+    #[test]
+    fn test_diff() {
+        let (changed, removed) = diff(
+            vec![(1, "a"), (2, "b"), (3, "c")].into_iter(),
+            vec![(1, "a"), (2, "B"), (4, "d")].into_iter(),
+        );
+        assert_eq!(changed, vec![2, 4]);
+        assert_eq!(removed, vec![3]);
+    }
+}
@@ -0,0 +1,217 @@
+//! Updates to the shared Redis cache from gateway events. These only depend on `Storage`, so
+//! they can be tested against `InMemoryStorage`. Each update is reported to the `AuditTracker`
+//! before it is written, so that a concurrent cache audit does not undo it.
+use crate::audit::{AuditTracker, Resource};
+use anyhow::Result;
+use hourai::models::{
+    channel::{Channel, GuildChannel},
+    guild::{Guild, PartialGuild, Role},
+    id::*,
+    voice::VoiceState,
+};
+use hourai_redis::{CachedGuild, CachedVoiceState, Storage};
+
+pub async fn on_guild_available(
+    guild: &Guild,
+    audits: &AuditTracker,
+    storage: &mut dyn Storage,
+) -> Result<()> {
+    audits.modify(guild.id, Resource::All);
+    CachedGuild::save(guild, storage).await?;
+    CachedVoiceState::update_guild(guild, storage).await?;
+    Ok(())
+}
+
+pub async fn on_guild_update(
+    guild: &PartialGuild,
+    audits: &AuditTracker,
+    storage: &mut dyn Storage,
+) -> Result<()> {
+    audits.modify(guild.id, Resource::Guild);
+    CachedGuild::update(guild, storage).await
+}
+
+pub async fn on_guild_leave(
+    guild_id: GuildId,
+    audits: &AuditTracker,
+    storage: &mut dyn Storage,
+) -> Result<()> {
+    audits.modify(guild_id, Resource::All);
+    CachedGuild::delete(guild_id, storage).await?;
+    CachedVoiceState::clear_guild(guild_id, storage).await?;
+    Ok(())
+}
+
+/// Saves a created or updated channel. Channels outside of guilds are not cached.
+pub async fn on_channel_update(
+    channel: &Channel,
+    audits: &AuditTracker,
+    storage: &mut dyn Storage,
+) -> Result<()> {
+    if let Channel::Guild(ref ch) = channel {
+        if let Some(guild_id) = ch.guild_id() {
+            audits.modify(guild_id, Resource::Channel(ch.id()));
+            CachedGuild::save_resource(guild_id, ch.id(), ch, storage).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn on_channel_delete(
+    channel: &Channel,
+    audits: &AuditTracker,
+    storage: &mut dyn Storage,
+) -> Result<()> {
+    if let Channel::Guild(ref ch) = channel {
+        if let Some(guild_id) = ch.guild_id() {
+            audits.modify(guild_id, Resource::Channel(ch.id()));
+            CachedGuild::delete_resource::<GuildChannel>(guild_id, ch.id(), storage).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Saves a created or updated role.
+pub async fn on_role_update(
+    guild_id: GuildId,
+    role: &Role,
+    audits: &AuditTracker,
+    storage: &mut dyn Storage,
+) -> Result<()> {
+    audits.modify(guild_id, Resource::Role(role.id));
+    CachedGuild::save_resource(guild_id, role.id, role, storage).await
+}
+
+pub async fn on_role_delete(
+    guild_id: GuildId,
+    role_id: RoleId,
+    audits: &AuditTracker,
+    storage: &mut dyn Storage,
+) -> Result<()> {
+    audits.modify(guild_id, Resource::Role(role_id));
+    CachedGuild::delete_resource::<Role>(guild_id, role_id, storage).await
+}
+
+/// Saves a user's new voice state, and returns the channel they were previously in, if any.
+pub async fn on_voice_state_update(
+    guild_id: GuildId,
+    state: &VoiceState,
+    audits: &AuditTracker,
+    storage: &mut dyn Storage,
+) -> Result<Option<ChannelId>> {
+    audits.modify(guild_id, Resource::VoiceState(state.user_id));
+    let previous = CachedVoiceState::get_channel(guild_id, state.user_id, storage).await?;
+    CachedVoiceState::save(state, storage).await?;
+    Ok(previous)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hourai::models::channel::{ChannelType, TextChannel};
+    use hourai_redis::InMemoryStorage;
+
+    fn text_channel(guild_id: GuildId, channel_id: ChannelId, name: &str) -> Channel {
+        Channel::Guild(GuildChannel::Text(TextChannel {
+            guild_id: Some(guild_id),
+            id: channel_id,
+            kind: ChannelType::GuildText,
+            last_message_id: None,
+            last_pin_timestamp: None,
+            name: name.to_owned(),
+            nsfw: false,
+            parent_id: None,
+            permission_overwrites: Vec::new(),
+            position: 3,
+            rate_limit_per_user: None,
+            topic: None,
+        }))
+    }
+
+    async fn fetch_channel_name(
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        storage: &mut dyn Storage,
+    ) -> Option<String> {
+        CachedGuild::fetch_resource::<GuildChannel>(guild_id, channel_id, storage)
+            .await
+            .unwrap()
+            .map(|channel| channel.get_name().to_owned())
+    }
+
+    #[tokio::test]
+    async fn test_channel_updates() {
+        let mut storage = InMemoryStorage::new();
+        let audits = AuditTracker::default();
+        let (guild_id, channel_id) = (GuildId(1), ChannelId(2));
+
+        on_channel_update(
+            &text_channel(guild_id, channel_id, "general"),
+            &audits,
+            &mut storage,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fetch_channel_name(guild_id, channel_id, &mut storage).await,
+            Some("general".to_owned())
+        );
+
+        on_channel_update(
+            &text_channel(guild_id, channel_id, "lobby"),
+            &audits,
+            &mut storage,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fetch_channel_name(guild_id, channel_id, &mut storage).await,
+            Some("lobby".to_owned())
+        );
+
+        on_channel_delete(
+            &text_channel(guild_id, channel_id, "lobby"),
+            &audits,
+            &mut storage,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fetch_channel_name(guild_id, channel_id, &mut storage).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guild_leave_clears_channels() {
+        let mut storage = InMemoryStorage::new();
+        let audits = AuditTracker::default();
+        let (guild_id, channel_id) = (GuildId(1), ChannelId(2));
+        on_channel_update(
+            &text_channel(guild_id, channel_id, "general"),
+            &audits,
+            &mut storage,
+        )
+        .await
+        .unwrap();
+        on_channel_update(
+            &text_channel(GuildId(3), ChannelId(4), "other"),
+            &audits,
+            &mut storage,
+        )
+        .await
+        .unwrap();
+
+        on_guild_leave(guild_id, &audits, &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(
+            fetch_channel_name(guild_id, channel_id, &mut storage).await,
+            None
+        );
+        assert_eq!(
+            fetch_channel_name(GuildId(3), ChannelId(4), &mut storage).await,
+            Some("other".to_owned())
+        );
+    }
+}
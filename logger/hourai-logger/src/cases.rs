@@ -0,0 +1,146 @@
+use crate::Client;
+use anyhow::Result;
+use hourai::{
+    models::{channel::embed::Embed, guild::Permissions, id::*},
+    proto::{
+        action::{Action, Action_oneof_details, BanMember_Type},
+        guild_configs::LoggingConfig,
+    },
+};
+use hourai_redis::GuildConfig;
+use hourai_sql::cases::Case;
+use tracing::error;
+use twilight_embed_builder::*;
+
+/// Gets a short, human readable name for the kind of action taken.
+pub fn action_name(action: &Action) -> &'static str {
+    match &action.details {
+        Some(Action_oneof_details::kick(_)) => "Kick",
+        Some(Action_oneof_details::ban(ban)) => match ban.get_field_type() {
+            BanMember_Type::BAN => "Ban",
+            BanMember_Type::UNBAN => "Unban",
+            BanMember_Type::SOFTBAN => "Softban",
+        },
+        Some(Action_oneof_details::escalate(escalate)) if escalate.get_amount() < 0 => {
+            "Deescalation"
+        }
+        Some(Action_oneof_details::escalate(_)) => "Escalation",
+        Some(Action_oneof_details::mute(_)) => "Mute",
+        Some(Action_oneof_details::deafen(_)) => "Deafen",
+        Some(Action_oneof_details::change_role(_)) => "Role Change",
+        Some(Action_oneof_details::direct_message(_)) => "Direct Message",
+        Some(Action_oneof_details::send_message(_)) => "Message",
+        Some(Action_oneof_details::command(_)) => "Command",
+        None => "Unknown",
+    }
+}
+
+fn action_color(action: &Action) -> u32 {
+    match &action.details {
+        // Dark red
+        Some(Action_oneof_details::ban(_)) => 0x992d22,
+        // Dark orange
+        Some(Action_oneof_details::kick(_)) => 0xa84300,
+        // Dark gold
+        Some(Action_oneof_details::escalate(_)) => 0xc27c0e,
+        // Grey
+        _ => 0x607d8b,
+    }
+}
+
+pub fn case_embed(case: &Case) -> Result<Embed> {
+    let moderator = match case.moderator_id() {
+        Some(id) => format!("<@{}> ({})", id, id),
+        None => "Unknown".to_owned(),
+    };
+    let reason = match &case.reason {
+        Some(reason) => reason.clone(),
+        None => format!(
+            "No reason given. A moderator can set one with `case reason {} <reason>`.",
+            case.case_number
+        ),
+    };
+    Ok(EmbedBuilder::new()
+        .title(format!(
+            "Case #{} | {}",
+            case.case_number,
+            action_name(case.action())
+        ))?
+        .color(action_color(case.action()))?
+        .field(
+            EmbedFieldBuilder::new("User", format!("<@{}> ({})", case.user_id, case.user_id))?
+                .inline(),
+        )
+        .field(EmbedFieldBuilder::new("Moderator", moderator)?.inline())
+        .field(EmbedFieldBuilder::new("Reason", reason)?)
+        .timestamp(case.created_at.to_rfc3339())
+        .build()?)
+}
+
+/// Opens a new case for an action and posts it to the guild's modlog, if it has one.
+pub async fn open_case(
+    client: &Client,
+    action: Action,
+    moderator: Option<UserId>,
+    reason: Option<String>,
+) -> Result<Case> {
+    let case = Case::create(action, moderator, reason)
+        .fetch_one(&client.sql)
+        .await?;
+    if let Err(err) = post_to_modlog(client, &case).await {
+        error!("Failed to post case to the modlog: {:?}", err);
+    }
+    Ok(case)
+}
+
+/// Changes the reason given for a case, and edits the case's modlog message to match. Returns
+/// None if the case does not exist.
+pub async fn update_reason(
+    client: &Client,
+    guild_id: GuildId,
+    case_number: i32,
+    reason: String,
+) -> Result<Option<Case>> {
+    let case = match Case::update_reason(guild_id, case_number, reason)
+        .fetch_optional(&client.sql)
+        .await?
+    {
+        Some(case) => case,
+        None => return Ok(None),
+    };
+    if let Some((channel_id, message_id)) = case.modlog_message() {
+        client
+            .http_client
+            .update_message(channel_id, message_id)
+            .embed(case_embed(&case)?)?
+            .await?;
+    }
+    Ok(Some(case))
+}
+
+async fn post_to_modlog(client: &Client, case: &Case) -> Result<()> {
+    let guild_id = case.guild_id();
+    let config =
+        GuildConfig::fetch_or_default::<LoggingConfig>(guild_id, &mut client.redis.clone()).await?;
+    if !config.has_modlog_channel_id() {
+        return Ok(());
+    }
+
+    let channel_id = ChannelId(config.get_modlog_channel_id());
+    let perms = client
+        .fetch_channel_permissions(guild_id, channel_id, client.user_id)
+        .await?;
+    if !perms.contains(Permissions::SEND_MESSAGES | Permissions::EMBED_LINKS) {
+        return Ok(());
+    }
+
+    let message = client
+        .http_client
+        .create_message(channel_id)
+        .embed(case_embed(case)?)?
+        .await?;
+    Case::set_modlog_message(guild_id, case.case_number, channel_id, message.id)
+        .execute(&client.sql)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,589 @@
+use crate::{cases, propagation, roles, verification, Client};
+use anyhow::{bail, Result};
+use hourai::{
+    commands::{self, precondition::*, CommandError},
+    models::{
+        channel::Message,
+        id::{GuildId, UserId},
+    },
+};
+use hourai_sql::{
+    cases::Case,
+    tags::{self, Alias, Tag},
+    SqlPool,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use twilight_command_parser::{Arguments, Command, CommandParserConfig, Parser};
+
+/// The built-in commands. Aliases cannot shadow these. None of these may share a name with one of
+/// the Python bot's commands, as both bots respond to the same prefix.
+const COMMANDS: &[&str] = &["tag", "alias", "case", "cases", "verification", "appeal"];
+
+/// Subcommands of `tag`, which cannot be used as tag names.
+const TAG_SUBCOMMANDS: &[&str] = &["set", "delete", "list"];
+
+/// How long a guild's aliases are cached for. Changes made through another process take up to
+/// this long to take effect.
+const ALIAS_CACHE_TTL: Duration = Duration::from_secs(60);
+
+type GuildAliases = Arc<HashMap<String, String>>;
+
+/// Caches each guild's aliases, so that messages starting with the command prefix do not each
+/// need a database query to check for an alias.
+#[derive(Clone, Default)]
+pub struct AliasCache(Arc<Mutex<HashMap<GuildId, (Instant, GuildAliases)>>>);
+
+impl AliasCache {
+    async fn get(&self, sql: &SqlPool, guild_id: GuildId) -> Result<GuildAliases> {
+        let cached = self
+            .0
+            .lock()
+            .unwrap()
+            .get(&guild_id)
+            .filter(|(loaded, _)| loaded.elapsed() < ALIAS_CACHE_TTL)
+            .map(|(_, aliases)| aliases.clone());
+        if let Some(aliases) = cached {
+            return Ok(aliases);
+        }
+
+        // Read from the primary, so that changes are seen as soon as the cache is invalidated.
+        let aliases: HashMap<String, String> = Alias::fetch_guild(guild_id)
+            .fetch_all(sql.primary())
+            .await?
+            .into_iter()
+            .map(|alias| (alias.name, alias.content))
+            .collect();
+        let aliases = Arc::new(aliases);
+        let mut cache = self.0.lock().unwrap();
+        cache.retain(|_, (loaded, _)| loaded.elapsed() < ALIAS_CACHE_TTL);
+        cache.insert(guild_id, (Instant::now(), aliases.clone()));
+        Ok(aliases)
+    }
+
+    fn invalidate(&self, guild_id: GuildId) {
+        self.0.lock().unwrap().remove(&guild_id);
+    }
+}
+
+pub fn parser(prefix: &str) -> Parser<'static> {
+    let mut config = CommandParserConfig::new();
+    config.add_prefix(prefix.to_owned());
+    for command in COMMANDS {
+        config.add_command(*command, false);
+    }
+    Parser::new(config)
+}
+
+pub async fn on_message_create(client: &Client, evt: &Message) -> Result<()> {
+    if evt.author.bot {
+        return Ok(());
+    }
+
+    let expanded = match evt.guild_id {
+        Some(guild_id) => expand_alias(client, guild_id, &evt.content).await?,
+        None => None,
+    };
+    let content = expanded.as_deref().unwrap_or(&evt.content);
+    let command = match client.parser.parse(content) {
+        Some(command) => command,
+        None => return Ok(()),
+    };
+
+    let ctx = commands::Context {
+        message: evt,
+        http: client.http_client.clone(),
+    };
+    let result = match command {
+        Command {
+            name: "tag",
+            arguments,
+            ..
+        } => tag(client, ctx, arguments).await,
+        Command {
+            name: "alias",
+            arguments,
+            ..
+        } => alias(client, ctx, arguments).await,
+        Command {
+            name: "case",
+            arguments,
+            ..
+        } => case(client, ctx, arguments).await,
+        Command {
+            name: "cases",
+            arguments,
+            ..
+        } => list_cases(client, ctx, arguments).await,
+        Command {
+            name: "verification",
+            arguments,
+            ..
+        } => verification_command(client, ctx, arguments).await,
+        Command {
+            name: "appeal",
+            arguments,
+            ..
+        } => appeal(client, ctx, arguments).await,
+        _ => Ok(()),
+    };
+
+    if let Err(err) = result {
+        match err.downcast::<CommandError>() {
+            Ok(command_error) => {
+                client
+                    .http_client
+                    .create_message(evt.channel_id)
+                    .reply(evt.id)
+                    .content(format!(":x: {}", command_error))?
+                    .await?;
+            }
+            Err(err) => bail!(err),
+        }
+    }
+    Ok(())
+}
+
+/// If the message invokes one of the guild's aliases, returns the message with the alias
+/// replaced by the command it expands to. Aliases only expand once, so they cannot recurse.
+async fn expand_alias(client: &Client, guild_id: GuildId, content: &str) -> Result<Option<String>> {
+    let prefix = client.command_prefix.as_str();
+    let rest = match content.strip_prefix(prefix) {
+        Some(rest) => rest,
+        None => return Ok(None),
+    };
+    let mut parts = rest.splitn(2, char::is_whitespace);
+    let name = tags::normalize_name(parts.next().unwrap_or_default());
+    if name.is_empty() || COMMANDS.contains(&name.as_str()) {
+        return Ok(None);
+    }
+
+    let aliases = client.aliases.get(&client.sql, guild_id).await?;
+    Ok(aliases.get(&name).map(|content| match parts.next() {
+        Some(args) => format!("{}{} {}", prefix, content, args),
+        None => format!("{}{}", prefix, content),
+    }))
+}
+
+/// Requires that the author is a moderator on the server to use the command.
+async fn require_moderator(client: &Client, ctx: &commands::Context<'_>) -> Result<GuildId> {
+    let guild_id = require_in_guild(ctx)?;
+    if let Some(member) = &ctx.message.member {
+        if roles::is_moderator(client, guild_id, ctx.message.author.id, &member.roles).await? {
+            return Ok(guild_id);
+        }
+    }
+    bail!(CommandError::FailedPrecondition(
+        "You must be a moderator of this server to use this command."
+    ))
+}
+
+fn parse_name(name: Option<&str>) -> Result<String> {
+    let name = tags::normalize_name(name.ok_or(CommandError::MissingArgument)?);
+    if name.is_empty() {
+        bail!(CommandError::MissingArgument);
+    } else if name.chars().count() > tags::MAX_NAME_LENGTH {
+        bail!(CommandError::InvalidArgument(format!(
+            "Names cannot be longer than {} characters.",
+            tags::MAX_NAME_LENGTH
+        )));
+    }
+    Ok(name)
+}
+
+/// Parses a user from either a mention or a raw ID.
+fn parse_user_id(arg: Option<&str>) -> Result<UserId> {
+    let arg = arg.ok_or(CommandError::MissingArgument)?;
+    let id = arg
+        .trim_start_matches("<@")
+        .trim_start_matches('!')
+        .trim_end_matches('>');
+    match id.parse() {
+        Ok(id) => Ok(UserId(id)),
+        Err(_) => bail!(CommandError::InvalidArgument(format!(
+            "`{}` is not a user.",
+            arg
+        ))),
+    }
+}
+
+fn parse_case_number(arg: Option<&str>) -> Result<i32> {
+    let arg = arg.ok_or(CommandError::MissingArgument)?;
+    match arg.trim_start_matches('#').parse() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => bail!(CommandError::InvalidArgument(format!(
+            "`{}` is not a case number.",
+            arg
+        ))),
+    }
+}
+
+fn require_length(value: &str, max: usize) -> Result<()> {
+    if value.chars().count() > max {
+        bail!(CommandError::InvalidArgument(format!(
+            "Cannot be longer than {} characters.",
+            max
+        )));
+    }
+    Ok(())
+}
+
+/// Formats a list of names to fit in a single message.
+fn format_names(names: impl Iterator<Item = String>) -> String {
+    // Leave room for the code block and truncation marker.
+    const MAX_LENGTH: usize = tags::MAX_RESPONSE_LENGTH - 16;
+    let mut list = String::new();
+    for name in names {
+        if list.len() + name.len() + 2 > MAX_LENGTH {
+            list.push_str(", ...");
+            break;
+        } else if !list.is_empty() {
+            list.push_str(", ");
+        }
+        list.push_str(&name);
+    }
+    format!("```\n{}\n```", list)
+}
+
+async fn tag(client: &Client, ctx: commands::Context<'_>, mut args: Arguments<'_>) -> Result<()> {
+    let guild_id = require_in_guild(&ctx)?;
+    let response = match args.next() {
+        Some("set") => {
+            require_moderator(client, &ctx).await?;
+            let name = parse_name(args.next())?;
+            if TAG_SUBCOMMANDS.contains(&name.as_str()) {
+                bail!(CommandError::InvalidArgument(format!(
+                    "`{}` cannot be used as a tag name.",
+                    name
+                )));
+            }
+            // Setting an empty response deletes the tag, as the Python bot's tags did.
+            let response = match args
+                .into_remainder()
+                .map(str::trim)
+                .filter(|response| !response.is_empty())
+            {
+                Some(response) => response,
+                None => return delete_tag(client, ctx, guild_id, name).await,
+            };
+            require_length(response, tags::MAX_RESPONSE_LENGTH)?;
+
+            let mut txn = client.sql.begin().await?;
+            let exists = Tag::fetch(guild_id, name.clone())
+                .fetch_optional(&mut txn)
+                .await?
+                .is_some();
+            let (count,) = Tag::count_guild(guild_id).fetch_one(&mut txn).await?;
+            if !exists && count >= tags::MAX_TAGS_PER_GUILD {
+                bail!(CommandError::InvalidArgument(format!(
+                    "Servers cannot have more than {} tags.",
+                    tags::MAX_TAGS_PER_GUILD
+                )));
+            }
+            Tag {
+                guild_id: guild_id.0 as i64,
+                tag: name.clone(),
+                response: response.to_owned(),
+            }
+            .insert()
+            .execute(&mut txn)
+            .await?;
+            txn.commit().await?;
+            format!("Tag `{}` set!", name)
+        }
+        Some("delete") => {
+            require_moderator(client, &ctx).await?;
+            let name = parse_name(args.next())?;
+            no_excess_arguments(&mut args)?;
+            return delete_tag(client, ctx, guild_id, name).await;
+        }
+        Some("list") => {
+            no_excess_arguments(&mut args)?;
+            let tags = Tag::fetch_guild(guild_id).fetch_all(&client.sql).await?;
+            if tags.is_empty() {
+                "No tags have been set! Use `tag set` to make some.".to_owned()
+            } else {
+                format_names(tags.into_iter().map(|tag| tag.tag))
+            }
+        }
+        name => {
+            let name = parse_name(name)?;
+            no_excess_arguments(&mut args)?;
+            match Tag::fetch(guild_id, name.clone())
+                .fetch_optional(&client.sql)
+                .await?
+            {
+                Some(tag) => tag.response,
+                None => format!("Tag `{}` does not exist.", name),
+            }
+        }
+    };
+
+    ctx.respond().content(response)?.await?;
+    Ok(())
+}
+
+async fn delete_tag(
+    client: &Client,
+    ctx: commands::Context<'_>,
+    guild_id: GuildId,
+    name: String,
+) -> Result<()> {
+    let result = Tag::delete(guild_id, name.clone())
+        .execute(&client.sql)
+        .await?;
+    let response = if result.rows_affected() > 0 {
+        format!("Tag `{}` deleted.", name)
+    } else {
+        format!("Tag `{}` does not exist.", name)
+    };
+    ctx.respond().content(response)?.await?;
+    Ok(())
+}
+
+async fn alias(client: &Client, ctx: commands::Context<'_>, mut args: Arguments<'_>) -> Result<()> {
+    let guild_id = require_in_guild(&ctx)?;
+    let response = match args.next() {
+        Some("set") => {
+            require_moderator(client, &ctx).await?;
+            let name = parse_name(args.next())?;
+            if COMMANDS.contains(&name.as_str()) {
+                bail!(CommandError::InvalidArgument(format!(
+                    "`{}` is already a command.",
+                    name
+                )));
+            }
+            let content = args
+                .into_remainder()
+                .map(str::trim)
+                .filter(|content| !content.is_empty())
+                .ok_or(CommandError::MissingArgument)?;
+            require_length(content, tags::MAX_ALIAS_CONTENT_LENGTH)?;
+
+            let mut txn = client.sql.begin().await?;
+            let exists = Alias::fetch(guild_id, name.clone())
+                .fetch_optional(&mut txn)
+                .await?
+                .is_some();
+            let (count,) = Alias::count_guild(guild_id).fetch_one(&mut txn).await?;
+            if !exists && count >= tags::MAX_ALIASES_PER_GUILD {
+                bail!(CommandError::InvalidArgument(format!(
+                    "Servers cannot have more than {} aliases.",
+                    tags::MAX_ALIASES_PER_GUILD
+                )));
+            }
+            Alias {
+                guild_id: guild_id.0 as i64,
+                name: name.clone(),
+                content: content.to_owned(),
+            }
+            .insert()
+            .execute(&mut txn)
+            .await?;
+            txn.commit().await?;
+            client.aliases.invalidate(guild_id);
+            format!("Alias `{}` set!", name)
+        }
+        Some("delete") => {
+            require_moderator(client, &ctx).await?;
+            let name = parse_name(args.next())?;
+            no_excess_arguments(&mut args)?;
+            let result = Alias::delete(guild_id, name.clone())
+                .execute(&client.sql)
+                .await?;
+            client.aliases.invalidate(guild_id);
+            if result.rows_affected() > 0 {
+                format!("Alias `{}` deleted.", name)
+            } else {
+                format!("Alias `{}` does not exist.", name)
+            }
+        }
+        Some("list") | None => {
+            no_excess_arguments(&mut args)?;
+            let aliases = Alias::fetch_guild(guild_id).fetch_all(&client.sql).await?;
+            if aliases.is_empty() {
+                "No aliases have been set! Use `alias set` to make some.".to_owned()
+            } else {
+                format_names(
+                    aliases
+                        .into_iter()
+                        .map(|alias| format!("{} -> {}", alias.name, alias.content)),
+                )
+            }
+        }
+        Some(_) => bail!(CommandError::InvalidArgument(
+            "Expected one of `set`, `delete`, or `list`.".to_owned()
+        )),
+    };
+
+    ctx.respond().content(response)?.await?;
+    Ok(())
+}
+
+async fn case(client: &Client, ctx: commands::Context<'_>, mut args: Arguments<'_>) -> Result<()> {
+    let guild_id = require_moderator(client, &ctx).await?;
+    match args.next() {
+        Some("reason") => {
+            let case_number = parse_case_number(args.next())?;
+            let reason = args
+                .into_remainder()
+                .map(str::trim)
+                .filter(|reason| !reason.is_empty())
+                .ok_or(CommandError::MissingArgument)?;
+            require_length(reason, hourai_sql::cases::MAX_REASON_LENGTH)?;
+            let response =
+                match cases::update_reason(client, guild_id, case_number, reason.to_owned()).await?
+                {
+                    Some(_) => format!("Case #{} updated.", case_number),
+                    None => format!("Case #{} does not exist.", case_number),
+                };
+            ctx.respond().content(response)?.await?;
+        }
+        number => {
+            let case_number = parse_case_number(number)?;
+            no_excess_arguments(&mut args)?;
+            match Case::fetch(guild_id, case_number)
+                .fetch_optional(&client.sql)
+                .await?
+            {
+                Some(case) => {
+                    ctx.respond().embed(cases::case_embed(&case)?)?.await?;
+                }
+                None => {
+                    ctx.respond()
+                        .content(format!("Case #{} does not exist.", case_number))?
+                        .await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn list_cases(
+    client: &Client,
+    ctx: commands::Context<'_>,
+    mut args: Arguments<'_>,
+) -> Result<()> {
+    let guild_id = require_moderator(client, &ctx).await?;
+    let user_id = parse_user_id(args.next())?;
+    no_excess_arguments(&mut args)?;
+
+    let cases = Case::fetch_guild_user(guild_id, user_id)
+        .fetch_all(&client.sql)
+        .await?;
+    let response = if cases.is_empty() {
+        format!("<@{}> has no cases.", user_id)
+    } else {
+        let mut response = format!("Cases for <@{}>:", user_id);
+        for case in cases.iter().rev() {
+            let line = format!(
+                "\n`#{}` {} {} - {}",
+                case.case_number,
+                case.created_at.format("%Y-%m-%d"),
+                cases::action_name(case.action()),
+                case.reason.as_deref().unwrap_or("No reason given.")
+            );
+            // Show the most recent cases that fit in one message.
+            if response.len() + line.len() > tags::MAX_RESPONSE_LENGTH {
+                break;
+            }
+            response.push_str(&line);
+        }
+        response
+    };
+    ctx.respond().content(response)?.await?;
+    Ok(())
+}
+
+async fn verification_command(
+    client: &Client,
+    ctx: commands::Context<'_>,
+    mut args: Arguments<'_>,
+) -> Result<()> {
+    let guild_id = require_moderator(client, &ctx).await?;
+    match args.next() {
+        Some("check") => {
+            let user_id = parse_user_id(args.next())?;
+            no_excess_arguments(&mut args)?;
+            match verification::check(client, guild_id, user_id).await? {
+                verification::CheckResult::Decided(member, decision) => {
+                    let embed = verification::decision_embed(&member, &decision)?;
+                    ctx.respond().embed(embed)?.await?;
+                }
+                verification::CheckResult::Disabled => {
+                    ctx.respond()
+                        .content("Verification is not enabled on this server.")?
+                        .await?;
+                }
+                verification::CheckResult::UnknownUser => {
+                    bail!(CommandError::InvalidArgument(format!(
+                        "`{}` is not a user.",
+                        user_id
+                    )));
+                }
+            }
+        }
+        Some("propagate") => match args.next() {
+            None => {
+                let response =
+                    match propagation::start(client, guild_id, ctx.message.channel_id).await? {
+                        // The job reports its own progress.
+                        propagation::StartResult::Started => return Ok(()),
+                        propagation::StartResult::AlreadyRunning => {
+                            "The verification role is already being propagated."
+                        }
+                        propagation::StartResult::NoRole => {
+                            "Verification is not enabled, or has no role configured."
+                        }
+                        propagation::StartResult::NothingToDo => {
+                            "Every member already has the verification role."
+                        }
+                    };
+                ctx.respond().content(response)?.await?;
+            }
+            Some("cancel") => {
+                no_excess_arguments(&mut args)?;
+                let response = if propagation::cancel(client, guild_id).await? {
+                    "Propagation cancelled."
+                } else {
+                    "The verification role is not being propagated."
+                };
+                ctx.respond().content(response)?.await?;
+            }
+            Some(_) => bail!(CommandError::InvalidArgument(
+                "Expected `cancel` or nothing.".to_owned()
+            )),
+        },
+        _ => bail!(CommandError::InvalidArgument(
+            "Expected `check` or `propagate`.".to_owned()
+        )),
+    }
+    Ok(())
+}
+
+async fn appeal(
+    client: &Client,
+    ctx: commands::Context<'_>,
+    mut args: Arguments<'_>,
+) -> Result<()> {
+    require_direct_message(&ctx)?;
+    let message = args
+        .into_remainder()
+        .map(str::trim)
+        .filter(|message| !message.is_empty())
+        .ok_or(CommandError::MissingArgument)?;
+    require_length(message, verification::MAX_APPEAL_LENGTH)?;
+    let response = match verification::appeal(client, &ctx.message.author, message).await? {
+        verification::AppealResult::Forwarded => {
+            "Your appeal has been forwarded to the server's moderators."
+        }
+        verification::AppealResult::NothingToAppeal => "You have no recent rejections to appeal.",
+        verification::AppealResult::NoModlog => {
+            "The server that rejected you does not accept appeals."
+        }
+    };
+    ctx.respond().content(response)?.await?;
+    Ok(())
+}
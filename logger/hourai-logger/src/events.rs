@@ -0,0 +1,248 @@
+use anyhow::Result;
+use hourai::{
+    gateway::Event,
+    models::{id::*, user::User},
+    proto::{cache::CachedMessageProto, event::*},
+};
+use hourai_redis::{CachedMessage, EventStream, RedisPool, ToProto};
+use metrics::{counter, increment_counter};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::error;
+
+/// How long events are buffered for before they are published.
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The maximum number of events published in a single round trip to Redis.
+const MAX_BATCH_SIZE: usize = 1000;
+
+/// The number of buffered events at which new events are dropped instead, so that a slow or
+/// unavailable Redis cannot use up the process's memory.
+const MAX_PENDING_EVENTS: usize = 100_000;
+
+type PendingEvents = Vec<(EventStream, hourai::proto::event::Event)>;
+
+/// Buffers normalized gateway events and publishes them to the Redis event streams in batches,
+/// so that event handling does not wait on a round trip to Redis for every event.
+#[derive(Clone, Default)]
+pub struct EventPublisher(Arc<Mutex<PendingEvents>>);
+
+impl EventPublisher {
+    /// Buffers an event to be published, if it is one that is published.
+    pub fn queue(&self, event: &Event) {
+        let normalized = match normalize(event) {
+            Some(normalized) => normalized,
+            None => return,
+        };
+        let mut pending = self.0.lock().unwrap();
+        if pending.len() >= MAX_PENDING_EVENTS {
+            increment_counter!("hourai_event_stream_dropped_total");
+            return;
+        }
+        pending.push(normalized);
+    }
+
+    /// Publishes the buffered events every interval, forever.
+    pub async fn run(self, mut redis: RedisPool) {
+        loop {
+            tokio::time::sleep(PUBLISH_INTERVAL).await;
+            if let Err(err) = self.flush(&mut redis).await {
+                error!("Error while publishing to the event streams: {:?}", err);
+            }
+        }
+    }
+
+    /// Publishes all buffered events. If publishing fails, the unpublished events are kept and
+    /// retried on the next flush, ahead of any queued since.
+    async fn flush(&self, redis: &mut RedisPool) -> Result<()> {
+        let events = std::mem::take(&mut *self.0.lock().unwrap());
+        for (idx, batch) in events.chunks(MAX_BATCH_SIZE).enumerate() {
+            if let Err(err) = EventStream::publish_all(batch, redis).await {
+                let mut pending = self.0.lock().unwrap();
+                let mut unpublished = events[idx * MAX_BATCH_SIZE..].to_vec();
+                unpublished.append(&mut pending);
+                unpublished.truncate(MAX_PENDING_EVENTS);
+                *pending = unpublished;
+                return Err(err);
+            }
+            counter!("hourai_event_stream_published_total", batch.len() as u64);
+        }
+        Ok(())
+    }
+}
+
+/// Converts a gateway event into the normalized form published to the Redis event streams.
+/// Returns None if the event is not one that is published.
+pub fn normalize(event: &Event) -> Option<(EventStream, hourai::proto::event::Event)> {
+    let mut gateway = GatewayEvent::new();
+    let (stream, guild_id, channel_id) = match event {
+        Event::MemberAdd(evt) => {
+            let member = &evt.0;
+            gateway.set_member_add(member_event(
+                &member.user,
+                &member.roles,
+                member.nick.as_ref(),
+            ));
+            (EventStream::Members, Some(member.guild_id), None)
+        }
+        Event::MemberUpdate(evt) => {
+            gateway.set_member_update(member_event(&evt.user, &evt.roles, evt.nick.as_ref()));
+            (EventStream::Members, Some(evt.guild_id), None)
+        }
+        Event::MemberRemove(evt) => {
+            gateway.set_member_remove(member_event(&evt.user, &[], None));
+            (EventStream::Members, Some(evt.guild_id), None)
+        }
+        Event::BanAdd(evt) => {
+            gateway.set_ban_add(ban_event(&evt.user));
+            (EventStream::Bans, Some(evt.guild_id), None)
+        }
+        Event::BanRemove(evt) => {
+            gateway.set_ban_remove(ban_event(&evt.user));
+            (EventStream::Bans, Some(evt.guild_id), None)
+        }
+        Event::MessageCreate(evt) => {
+            let message = &evt.0;
+            gateway.set_message_create(CachedMessage::new(message.clone()).into_proto());
+            (
+                EventStream::Messages,
+                message.guild_id,
+                Some(message.channel_id),
+            )
+        }
+        Event::MessageUpdate(evt) => {
+            // Updates are partial, only the fields that changed are present.
+            let mut msg = CachedMessageProto::new();
+            msg.set_id(evt.id.0);
+            msg.set_channel_id(evt.channel_id.0);
+            if let Some(guild_id) = evt.guild_id {
+                msg.set_guild_id(guild_id.0);
+            }
+            if let Some(ref content) = evt.content {
+                msg.set_content(content.clone());
+            }
+            if let Some(ref author) = evt.author {
+                msg.set_author(author.to_proto());
+            }
+            gateway.set_message_update(msg);
+            (EventStream::Messages, evt.guild_id, Some(evt.channel_id))
+        }
+        Event::MessageDelete(evt) => {
+            gateway.set_message_delete(message_delete_event(&[evt.id]));
+            (EventStream::Messages, evt.guild_id, Some(evt.channel_id))
+        }
+        Event::MessageDeleteBulk(evt) => {
+            gateway.set_message_delete(message_delete_event(&evt.ids));
+            (EventStream::Messages, evt.guild_id, Some(evt.channel_id))
+        }
+        _ => return None,
+    };
+
+    // Direct messages are not published.
+    let guild_id = guild_id?;
+
+    let mut proto = hourai::proto::event::Event::new();
+    let source = proto.mut_source();
+    source.set_guild_id(guild_id.0);
+    if let Some(channel_id) = channel_id {
+        source.set_channel_id(channel_id.0);
+    }
+    source.set_timestamp(chrono::Utc::now().timestamp_millis() as u64);
+    source.set_gateway(gateway);
+    Some((stream, proto))
+}
+
+fn member_event(user: &User, roles: &[RoleId], nickname: Option<&String>) -> MemberEvent {
+    let mut proto = MemberEvent::new();
+    proto.set_user(user.to_proto());
+    proto.set_role_ids(roles.iter().map(|id| id.0).collect());
+    if let Some(nickname) = nickname {
+        proto.set_nickname(nickname.clone());
+    }
+    proto
+}
+
+fn ban_event(user: &User) -> BanEvent {
+    let mut proto = BanEvent::new();
+    proto.set_user(user.to_proto());
+    proto
+}
+
+fn message_delete_event(ids: &[MessageId]) -> MessageDeleteEvent {
+    let mut proto = MessageDeleteEvent::new();
+    proto.set_message_ids(ids.iter().map(|id| id.0).collect());
+    proto
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hourai::models::gateway::payload::{MessageDelete, MessageDeleteBulk};
+
+    #[test]
+    fn test_normalize_message_delete() {
+        let event = Event::MessageDelete(MessageDelete {
+            channel_id: ChannelId(2),
+            guild_id: Some(GuildId(1)),
+            id: MessageId(3),
+        });
+        let (stream, proto) = normalize(&event).unwrap();
+        assert_eq!(stream, EventStream::Messages);
+        let source = proto.get_source();
+        assert_eq!(source.get_guild_id(), 1);
+        assert_eq!(source.get_channel_id(), 2);
+        assert_eq!(
+            source.get_gateway().get_message_delete().get_message_ids(),
+            &[3]
+        );
+    }
+
+    #[test]
+    fn test_normalize_message_delete_bulk() {
+        let event = Event::MessageDeleteBulk(MessageDeleteBulk {
+            channel_id: ChannelId(2),
+            guild_id: Some(GuildId(1)),
+            ids: vec![MessageId(3), MessageId(4)],
+        });
+        let (stream, proto) = normalize(&event).unwrap();
+        assert_eq!(stream, EventStream::Messages);
+        assert_eq!(
+            proto
+                .get_source()
+                .get_gateway()
+                .get_message_delete()
+                .get_message_ids(),
+            &[3, 4]
+        );
+    }
+
+    #[test]
+    fn test_normalize_skips_direct_messages() {
+        let event = Event::MessageDelete(MessageDelete {
+            channel_id: ChannelId(2),
+            guild_id: None,
+            id: MessageId(3),
+        });
+        assert!(normalize(&event).is_none());
+    }
+
+    #[test]
+    fn test_normalize_skips_unpublished_events() {
+        assert!(normalize(&Event::GatewayHeartbeatAck).is_none());
+    }
+
+    #[test]
+    fn test_queue_drops_events_when_full() {
+        let publisher = EventPublisher::default();
+        let event = Event::MessageDelete(MessageDelete {
+            channel_id: ChannelId(2),
+            guild_id: Some(GuildId(1)),
+            id: MessageId(3),
+        });
+        for _ in 0..MAX_PENDING_EVENTS + 10 {
+            publisher.queue(&event);
+        }
+        publisher.queue(&Event::GatewayHeartbeatAck);
+        assert_eq!(publisher.0.lock().unwrap().len(), MAX_PENDING_EVENTS);
+    }
+}
@@ -1,7 +1,16 @@
+mod activity;
 mod announcements;
+mod audit;
+mod caching;
+mod cases;
+mod commands;
+mod events;
 mod listings;
+mod member_writer;
 mod message_logging;
+mod propagation;
 mod roles;
+mod verification;
 
 use anyhow::Result;
 use core::time::Duration;
@@ -12,15 +21,33 @@ use hourai::{
     gateway::{cluster::*, Event, EventType, EventTypeFlags, Intents},
     init,
     models::{
-        channel::{Channel, GuildChannel, Message},
+        channel::Message,
         gateway::payload::*,
-        guild::{member::Member, GuildStatus, Permissions, Role},
+        guild::{
+            audit_log::{AuditLogEntry, AuditLogEvent},
+            member::Member,
+            GuildStatus, Permissions,
+        },
         id::*,
         user::User,
+        Snowflake,
     },
+    proto::action::{Action, BanMember, BanMember_Type, KickMember},
 };
 use hourai_redis::*;
-use hourai_sql::*;
+use hourai_sql::{
+    ban_history::BanHistory,
+    stats::{DailyStat, GuildDailyStats},
+    verification::VerificationPropagation,
+    *,
+};
+use hourai_validation::avatar::{self, HttpAvatarFetcher};
+use hourai_validation::builder::VerifierResources;
+use hourai_validation::normalize;
+use metrics::{counter, histogram};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::signal::unix::{signal, SignalKind};
 use tracing::{debug, error, info};
 
 const BOT_INTENTS: Intents = Intents::from_bits_truncate(
@@ -29,13 +56,17 @@ const BOT_INTENTS: Intents = Intents::from_bits_truncate(
         | Intents::GUILD_MESSAGES.bits()
         | Intents::GUILD_MEMBERS.bits()
         | Intents::GUILD_PRESENCES.bits()
-        | Intents::GUILD_VOICE_STATES.bits(),
+        | Intents::GUILD_VOICE_STATES.bits()
+        | Intents::DIRECT_MESSAGES.bits(),
 );
 
 const BOT_EVENTS: EventTypeFlags = EventTypeFlags::from_bits_truncate(
     EventTypeFlags::READY.bits()
         | EventTypeFlags::BAN_ADD.bits()
         | EventTypeFlags::BAN_REMOVE.bits()
+        | EventTypeFlags::CHANNEL_CREATE.bits()
+        | EventTypeFlags::CHANNEL_UPDATE.bits()
+        | EventTypeFlags::CHANNEL_DELETE.bits()
         | EventTypeFlags::MEMBER_ADD.bits()
         | EventTypeFlags::MEMBER_CHUNK.bits()
         | EventTypeFlags::MEMBER_REMOVE.bits()
@@ -54,6 +85,51 @@ const BOT_EVENTS: EventTypeFlags = EventTypeFlags::from_bits_truncate(
         | EventTypeFlags::ROLE_DELETE.bits(),
 );
 
+/// The number of recent audit log entries searched when looking for who performed an action.
+const AUDIT_LOG_SEARCH_LIMIT: u64 = 10;
+
+/// How many times the audit log is searched for an action before giving up. Discord may write
+/// an action's audit log entry after the gateway event for it is received.
+const AUDIT_LOG_ATTEMPTS: u32 = 3;
+
+/// The delay between searches of the audit log.
+const AUDIT_LOG_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How recent an audit log entry must be for an action to be attributed to it. Older entries
+/// are for earlier actions against the same user.
+const AUDIT_LOG_MAX_AGE_SECS: i64 = 30;
+
+/// How often changes to members' online statuses are written to Redis.
+const ONLINE_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Every this many flushes, every guild's online members are written out in full, in case any
+/// changes were missed.
+const ONLINE_FULL_RESYNC_FLUSHES: u64 = 10;
+
+/// The maximum number of banned users' avatars hashed every time bans are refreshed.
+const BAN_AVATAR_HASH_BATCH: i64 = 500;
+
+/// The maximum number of stored usernames, and of nicknames, normalized every time bans are
+/// refreshed.
+const NAME_NORMALIZE_BATCH: i64 = 10000;
+
+/// Why a member is being handled as having joined a guild.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum JoinKind {
+    /// The member just joined, and may still need to pass membership screening.
+    Joined,
+    /// A member that joined earlier passed membership screening.
+    PassedScreening,
+}
+
+impl JoinKind {
+    /// Whether the join is counted in the guild's daily stats. Members that pass membership
+    /// screening were already counted when they joined.
+    fn is_counted(self) -> bool {
+        self == Self::Joined
+    }
+}
+
 const CACHED_RESOURCES: ResourceType = ResourceType::from_bits_truncate(
     ResourceType::GUILD.bits() | ResourceType::MEMBER.bits() | ResourceType::PRESENCE.bits(),
 );
@@ -64,7 +140,7 @@ async fn main() {
 
     init::init(&config);
     let http_client = init::http_client(&config);
-    let sql = hourai_sql::init(&config).await;
+    let sql = hourai_sql::init(&config, "logger").await;
     let redis = hourai_redis::init(&config).await;
     let cache = InMemoryCache::builder()
         .resource_types(CACHED_RESOURCES)
@@ -76,6 +152,17 @@ async fn main() {
         .await
         .expect("Failed to connect to the Discord gateway");
 
+    let verifier_resources =
+        VerifierResources::load(&config, &http_client, sql.clone(), redis.clone())
+            .await
+            .expect("Failed to load verification resources");
+    tokio::spawn(
+        verifier_resources
+            .lists
+            .clone()
+            .watch(Duration::from_secs(60)),
+    );
+
     let client = {
         let user = http_client
             .current_user()
@@ -86,8 +173,17 @@ async fn main() {
             http_client,
             gateway: gateway.clone(),
             cache: cache.clone(),
-            sql,
+            sql: sql.clone(),
             redis: redis.clone(),
+            activity: activity::ActivityTracker::default(),
+            member_writer: member_writer::MemberWriter::new(sql.clone()),
+            audits: audit::AuditTracker::default(),
+            events: events::EventPublisher::default(),
+            aliases: commands::AliasCache::default(),
+            post_permissions: message_logging::PostPermissionCache::default(),
+            verifier_resources: Arc::new(verifier_resources),
+            parser: commands::parser(&config.command_prefix),
+            command_prefix: config.command_prefix.clone(),
         }
     };
 
@@ -103,7 +199,26 @@ async fn main() {
 
     // Setup background tasks
     tokio::spawn(client.clone().log_bans());
+    tokio::spawn(audit::run_cache_audit(
+        client.clone(),
+        Duration::from_secs(600),
+    ));
     tokio::spawn(flush_online(cache.clone(), redis.clone()));
+    tokio::spawn(activity::run_activity_sampling(client.clone()));
+    tokio::spawn(client.member_writer.clone().run());
+    tokio::spawn(client.events.clone().run(redis.clone()));
+    tokio::spawn(propagation::run_propagations(client.clone()));
+
+    let shutdown = gateway.clone();
+    tokio::spawn(async move {
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+        info!("Received shutdown signal.");
+        shutdown.down();
+    });
 
     let mut events = gateway.some_events(BOT_EVENTS);
     while let Some((shard_id, evt)) = events.next().await {
@@ -118,27 +233,72 @@ async fn main() {
 
     info!("Shutting down gateway...");
     gateway.down();
+    info!("Flushing pending member updates...");
+    if let Err(err) = client.member_writer.shutdown().await {
+        error!("Error while flushing member updates: {:?}", err);
+    }
     info!("Client stopped.");
 }
 
 async fn flush_online(cache: InMemoryCache, mut redis: RedisPool) {
+    let mut flushes = 0u64;
     loop {
+        let full = flushes % ONLINE_FULL_RESYNC_FLUSHES == 0;
+        let kind = if full { "full" } else { "incremental" };
+        let start = Instant::now();
+
+        // Changes are taken before reading the full sets so that none are missed. Applying a
+        // change that is already part of a full set is harmless.
+        let changes = cache.take_presence_changes();
         let mut pipeline = OnlineStatus::new();
-        for guild_id in cache.guilds() {
-            let presences = match cache.guild_online(guild_id) {
-                Some(p) => p,
-                _ => continue,
-            };
-            pipeline.set_online(guild_id, presences);
+        let mut members = 0;
+        if full {
+            for guild_id in cache.guilds() {
+                let presences = match cache.guild_online(guild_id) {
+                    Some(p) => p,
+                    _ => continue,
+                };
+                members += presences.len();
+                pipeline.set_online(guild_id, presences);
+            }
         }
+        for (&guild_id, change) in changes.iter() {
+            let online = cache.guild_online(guild_id);
+            if change.resync && online.is_none() {
+                // The guild was removed from the cache.
+                pipeline.set_online(guild_id, Vec::new());
+            } else if full {
+                continue;
+            } else if change.resync {
+                let online = online.unwrap_or_default();
+                members += online.len();
+                pipeline.set_online(guild_id, online);
+            } else {
+                members += change.online.len() + change.offline.len();
+                pipeline.update_online(
+                    guild_id,
+                    change.online.iter().copied(),
+                    change.offline.iter().copied(),
+                );
+            }
+        }
+
         let result = pipeline
             .build()
             .query_async::<RedisPool, ()>(&mut redis)
             .await;
-        if let Err(err) = result {
-            error!("Error while flushing statuses: {:?}", err);
+        histogram!("hourai_online_flush_seconds", start.elapsed().as_secs_f64(), "kind" => kind);
+        counter!("hourai_online_flush_members_total", members as u64, "kind" => kind);
+        match result {
+            Ok(()) => flushes += 1,
+            Err(err) => {
+                // The changes are written out with the next flush instead. Failed full flushes
+                // are retried as well.
+                error!("Error while flushing statuses: {:?}", err);
+                cache.restore_presence_changes(changes);
+            }
         }
-        tokio::time::sleep(Duration::from_secs(60u64)).await;
+        tokio::time::sleep(ONLINE_FLUSH_INTERVAL).await;
     }
 }
 
@@ -150,10 +310,20 @@ pub struct Client {
     pub cache: InMemoryCache,
     pub sql: SqlPool,
     pub redis: RedisPool,
+    pub activity: activity::ActivityTracker,
+    pub member_writer: member_writer::MemberWriter,
+    pub audits: audit::AuditTracker,
+    pub events: events::EventPublisher,
+    pub aliases: commands::AliasCache,
+    pub post_permissions: message_logging::PostPermissionCache,
+    pub verifier_resources: Arc<VerifierResources>,
+    pub parser: twilight_command_parser::Parser<'static>,
+    pub command_prefix: String,
 }
 
 impl Client {
     async fn log_bans(self) {
+        let avatars = HttpAvatarFetcher::new(reqwest::Client::new());
         loop {
             info!("Refreshing bans...");
             for guild_id in self.cache.guilds() {
@@ -161,10 +331,40 @@ impl Client {
                     error!("Error while logging bans: {:?}", err);
                 }
             }
+            self.hash_banned_avatars(&avatars).await;
+            self.normalize_names().await;
             tokio::time::sleep(Duration::from_secs(180u64)).await;
         }
     }
 
+    /// Hashes the avatars of newly banned users, so that users evading bans with slightly
+    /// altered copies of the same avatar can be found.
+    async fn hash_banned_avatars(&self, fetcher: &HttpAvatarFetcher) {
+        match avatar::hash_banned_avatars(&self.sql, fetcher, BAN_AVATAR_HASH_BATCH).await {
+            Ok((hashed, errors)) => {
+                counter!("hourai_ban_avatars_hashed_total", hashed as u64);
+                counter!("hourai_ban_avatar_hash_errors_total", errors.len() as u64);
+                for err in errors {
+                    debug!("Failed to hash banned user's avatar: {:?}", err);
+                }
+            }
+            Err(err) => error!("Error while hashing banned avatars: {:?}", err),
+        }
+    }
+
+    /// Normalizes usernames and nicknames logged before names were normalized on write, so that
+    /// they can be matched by name searches.
+    async fn normalize_names(&self) {
+        match normalize::normalize_stored_usernames(&self.sql, NAME_NORMALIZE_BATCH).await {
+            Ok(count) => counter!("hourai_usernames_normalized_total", count as u64),
+            Err(err) => error!("Error while normalizing usernames: {:?}", err),
+        }
+        match normalize::normalize_stored_nicknames(&self.sql, NAME_NORMALIZE_BATCH).await {
+            Ok(count) => counter!("hourai_nicknames_normalized_total", count as u64),
+            Err(err) => error!("Error while normalizing nicknames: {:?}", err),
+        }
+    }
+
     #[inline(always)]
     pub fn total_shards(&self) -> u64 {
         let shards = self.gateway.config().shard_config().shard()[1];
@@ -217,6 +417,39 @@ impl Client {
         }
     }
 
+    pub async fn fetch_channel_permissions(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        user_id: UserId,
+    ) -> Result<Permissions> {
+        let local_member = hourai_sql::Member::fetch(guild_id, user_id)
+            .fetch_one(&self.sql)
+            .await;
+        let mut redis = self.redis.clone();
+        if let Ok(member) = local_member {
+            hourai_redis::CachedGuild::channel_permissions(
+                guild_id,
+                channel_id,
+                user_id,
+                member.role_ids(),
+                &mut redis,
+            )
+            .await
+        } else {
+            let roles = self
+                .http_client
+                .guild_member(guild_id, user_id)
+                .await?
+                .into_iter()
+                .flat_map(|m| m.roles);
+            hourai_redis::CachedGuild::channel_permissions(
+                guild_id, channel_id, user_id, roles, &mut redis,
+            )
+            .await
+        }
+    }
+
     /// Handle events before the cache is updated.
     async fn pre_cache_event(&self, event: &Event) {
         let kind = event.kind();
@@ -237,7 +470,7 @@ impl Client {
                         deaf: false,
                         mute: false,
                     };
-                    self.on_member_add(member).await
+                    self.on_member_add(member, JoinKind::PassedScreening).await
                 } else {
                     Ok(())
                 }
@@ -252,6 +485,8 @@ impl Client {
 
     async fn consume_event(mut self, shard_id: u64, event: Event) {
         let kind = event.kind();
+        self.events.queue(&event);
+
         let result = match event {
             Event::Ready(evt) => self.on_shard_ready(shard_id, *evt).await,
             Event::BanAdd(evt) => self.on_ban_add(evt).await,
@@ -265,7 +500,7 @@ impl Client {
                     Ok(())
                 }
             }
-            Event::MemberAdd(evt) => self.on_member_add(evt.0).await,
+            Event::MemberAdd(evt) => self.on_member_add(evt.0, JoinKind::Joined).await,
             Event::MemberChunk(evt) => self.on_member_chunk(evt).await,
             Event::MemberRemove(evt) => self.on_member_remove(evt).await,
             Event::MemberUpdate(evt) => self.on_member_update(*evt).await,
@@ -303,12 +538,8 @@ impl Client {
 
         for guild in evt.guilds {
             if let GuildStatus::Online(g) = guild {
-                hourai_redis::CachedGuild::save(&g)
-                    .query_async(&mut self.redis)
-                    .await?;
-                hourai_redis::CachedVoiceState::update_guild(&g)
-                    .query_async(&mut self.redis)
-                    .await?;
+                hourai_redis::CachedGuild::save(&g, &mut self.redis).await?;
+                hourai_redis::CachedVoiceState::update_guild(&g, &mut self.redis).await?;
             }
         }
 
@@ -316,16 +547,19 @@ impl Client {
     }
 
     async fn on_ban_add(self, evt: BanAdd) -> Result<()> {
-        let (res1, res2) = futures::join!(
+        let (res1, res2, res3) = futures::join!(
             self.log_users(vec![evt.user.clone()]),
-            announcements::on_member_ban(&self, evt.clone())
+            announcements::on_member_ban(&self, evt.clone()),
+            GuildDailyStats::increment(evt.guild_id, DailyStat::Bans, 1).execute(&self.sql)
         );
 
         let perms = self
             .fetch_guild_permissions(evt.guild_id, self.user_id)
             .await?;
+        let mut reason = None;
         if perms.contains(Permissions::BAN_MEMBERS) {
             if let Some(ban) = self.http_client.ban(evt.guild_id, evt.user.id).await? {
+                reason = ban.reason.clone();
                 Ban::from(evt.guild_id, ban)
                     .insert()
                     .execute(&self.sql)
@@ -333,8 +567,27 @@ impl Client {
             }
         }
 
+        let entry = self
+            .find_audit_log_entry(evt.guild_id, AuditLogEvent::MemberBanAdd, evt.user.id)
+            .await;
+        let banned_by = entry.as_ref().and_then(|e| e.user_id);
+        let reason = reason.or_else(|| entry.and_then(|e| e.reason));
+        BanHistory::record_ban(evt.guild_id, evt.user.id, banned_by, reason.clone())
+            .execute(&self.sql)
+            .await?;
+
+        let mut action = Action::new();
+        action.set_guild_id(evt.guild_id.0);
+        action.set_user_id(evt.user.id.0);
+        action.set_ban(BanMember::new());
+        if let Some(reason) = reason.clone() {
+            action.set_reason(reason);
+        }
+        cases::open_case(&self, action, banned_by, reason).await?;
+
         res1?;
         res2?;
+        res3?;
         Ok(())
     }
 
@@ -344,12 +597,101 @@ impl Client {
             Ban::clear_ban(evt.guild_id, evt.user.id).execute(&self.sql)
         );
 
+        let entry = self
+            .find_audit_log_entry(evt.guild_id, AuditLogEvent::MemberBanRemove, evt.user.id)
+            .await;
+        let unbanned_by = entry.as_ref().and_then(|e| e.user_id);
+        let reason = entry.and_then(|e| e.reason);
+        BanHistory::record_unban(evt.guild_id, evt.user.id, unbanned_by)
+            .execute(&self.sql)
+            .await?;
+
+        let mut ban = BanMember::new();
+        ban.set_field_type(BanMember_Type::UNBAN);
+        let mut action = Action::new();
+        action.set_guild_id(evt.guild_id.0);
+        action.set_user_id(evt.user.id.0);
+        action.set_ban(ban);
+        if let Some(reason) = reason.clone() {
+            action.set_reason(reason);
+        }
+        cases::open_case(&self, action, unbanned_by, reason).await?;
+
         res1?;
         res2?;
         Ok(())
     }
 
-    async fn on_member_add(&self, member: Member) -> Result<()> {
+    /// Finds the most recent audit log entry of a given type that targets a user, retrying a
+    /// few times if it has not been written yet. Entries older than `AUDIT_LOG_MAX_AGE_SECS` are
+    /// ignored.
+    /// Returns None if there is none, or if the guild's audit log cannot be read, in which case
+    /// who performed the action is unknown.
+    async fn find_audit_log_entry(
+        &self,
+        guild_id: GuildId,
+        action: AuditLogEvent,
+        target: UserId,
+    ) -> Option<AuditLogEntry> {
+        let perms = match self.fetch_guild_permissions(guild_id, self.user_id).await {
+            Ok(perms) => perms,
+            Err(err) => {
+                error!("Failed to fetch permissions in {}: {:?}", guild_id, err);
+                return None;
+            }
+        };
+        if !perms.contains(Permissions::VIEW_AUDIT_LOG) {
+            return None;
+        }
+
+        for attempt in 0..AUDIT_LOG_ATTEMPTS {
+            if attempt > 0 {
+                tokio::time::sleep(AUDIT_LOG_RETRY_DELAY).await;
+            }
+            match self.search_audit_log(guild_id, action, target).await {
+                Ok(Some(entry)) => {
+                    let max_age = chrono::Duration::seconds(AUDIT_LOG_MAX_AGE_SECS);
+                    if chrono::Utc::now() - entry.created_at() < max_age {
+                        return Some(entry);
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    error!("Failed to search the audit log of {}: {:?}", guild_id, err);
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    async fn search_audit_log(
+        &self,
+        guild_id: GuildId,
+        action: AuditLogEvent,
+        target: UserId,
+    ) -> Result<Option<AuditLogEntry>> {
+        let target = target.to_string();
+        let entry = self
+            .http_client
+            .audit_log(guild_id)
+            .action_type(action)
+            .limit(AUDIT_LOG_SEARCH_LIMIT)?
+            .await?
+            .and_then(|log| {
+                log.entries
+                    .into_iter()
+                    .find(|entry| entry.target_id.as_ref() == Some(&target))
+            });
+        Ok(entry)
+    }
+
+    async fn on_member_add(&self, member: Member, kind: JoinKind) -> Result<()> {
+        if kind.is_counted() {
+            GuildDailyStats::increment(member.guild_id, DailyStat::Joins, 1)
+                .execute(&self.sql)
+                .await?;
+        }
         if !member.pending {
             let res = roles::on_member_join(&self, &member).await;
             let members = vec![member.clone()];
@@ -370,65 +712,72 @@ impl Client {
             return Ok(());
         }
 
-        hourai_sql::Member::from(&evt)
-            .insert()
-            .execute(&self.sql)
+        self.member_writer
+            .queue_members(vec![normalize::member(&evt)])
+            .await?;
+        self.member_writer
+            .queue_usernames(vec![normalize::username(&evt.user)])
             .await?;
-        Username::new(&evt.user).insert().execute(&self.sql).await?;
         Ok(())
     }
 
     async fn on_member_remove(&self, evt: MemberRemove) -> Result<()> {
-        let (res1, res2, res3) = futures::join!(
+        self.member_writer
+            .discard_member(evt.guild_id, evt.user.id)
+            .await;
+        let (res1, res2, res3, res4) = futures::join!(
             hourai_sql::Member::set_present(evt.guild_id, evt.user.id, false).execute(&self.sql),
             self.log_users(vec![evt.user.clone()]),
-            announcements::on_member_leave(&self, evt)
+            announcements::on_member_leave(&self, evt.clone()),
+            GuildDailyStats::increment(evt.guild_id, DailyStat::Leaves, 1).execute(&self.sql)
         );
+
+        // Kicks are recorded even if logging the member leaving failed.
+        let kick = self
+            .find_audit_log_entry(evt.guild_id, AuditLogEvent::MemberKick, evt.user.id)
+            .await;
+        if let Some(entry) = kick {
+            let mut action = Action::new();
+            action.set_guild_id(evt.guild_id.0);
+            action.set_user_id(evt.user.id.0);
+            action.set_kick(KickMember::new());
+            if let Some(reason) = entry.reason.clone() {
+                action.set_reason(reason);
+            }
+            cases::open_case(self, action, entry.user_id, entry.reason).await?;
+        }
+
         res1?;
         res2?;
         res3?;
+        res4?;
         Ok(())
     }
 
     async fn on_channel_create(&mut self, evt: ChannelCreate) -> Result<()> {
-        if let Channel::Guild(ref ch) = evt.0 {
-            if let Some(guild_id) = ch.guild_id() {
-                hourai_redis::CachedGuild::save_resource(guild_id, ch.id(), ch)
-                    .query_async(&mut self.redis)
-                    .await?;
-            }
-        }
-        Ok(())
+        caching::on_channel_update(&evt.0, &self.audits, &mut self.redis).await
     }
 
     async fn on_channel_update(&mut self, evt: ChannelUpdate) -> Result<()> {
-        if let Channel::Guild(ref ch) = evt.0 {
-            if let Some(guild_id) = ch.guild_id() {
-                hourai_redis::CachedGuild::save_resource(guild_id, ch.id(), ch)
-                    .query_async(&mut self.redis)
-                    .await?;
-            }
-        }
-        Ok(())
+        caching::on_channel_update(&evt.0, &self.audits, &mut self.redis).await
     }
 
     async fn on_channel_delete(mut self, evt: ChannelDelete) -> Result<()> {
-        if let Channel::Guild(ref ch) = evt.0 {
-            if let Some(guild_id) = ch.guild_id() {
-                hourai_redis::CachedGuild::delete_resource::<GuildChannel>(guild_id, ch.id())
-                    .query_async(&mut self.redis)
-                    .await?;
-            }
-        }
-        Ok(())
+        caching::on_channel_delete(&evt.0, &self.audits, &mut self.redis).await
     }
 
     async fn on_message_create(mut self, evt: Message) -> Result<()> {
         if !evt.author.bot {
-            CachedMessage::new(evt)
-                .flush()
-                .query_async(&mut self.redis)
-                .await?;
+            if let Some(guild_id) = evt.guild_id {
+                self.activity.record_message(guild_id);
+            }
+            if let Err(err) = commands::on_message_create(&self, &evt).await {
+                error!("Error while running command: {:?}", err);
+            }
+            // Direct messages are only received for commands, and are not logged.
+            if evt.guild_id.is_some() {
+                CachedMessage::new(evt).flush(&mut self.redis).await?;
+            }
         }
         Ok(())
     }
@@ -445,10 +794,7 @@ impl Client {
                     before.clone(),
                     msg,
                 ));
-                CachedMessage::new(before)
-                    .flush()
-                    .query_async(&mut self.redis)
-                    .await?;
+                CachedMessage::new(before).flush(&mut self.redis).await?;
             }
         }
 
@@ -457,9 +803,7 @@ impl Client {
 
     async fn on_message_delete(mut self, evt: MessageDelete) -> Result<()> {
         message_logging::on_message_delete(&mut self, &evt).await?;
-        CachedMessage::delete(evt.channel_id, evt.id)
-            .query_async(&mut self.redis)
-            .await?;
+        CachedMessage::delete(evt.channel_id, evt.id, &mut self.redis).await?;
         Ok(())
     }
 
@@ -468,9 +812,7 @@ impl Client {
             self.clone(),
             evt.clone(),
         ));
-        CachedMessage::bulk_delete(evt.channel_id, evt.ids)
-            .query_async(&mut self.redis)
-            .await?;
+        CachedMessage::bulk_delete(evt.channel_id, evt.ids, &mut self.redis).await?;
         Ok(())
     }
 
@@ -484,61 +826,46 @@ impl Client {
         }
 
         self.chunk_guild(guild.id).await?;
-        hourai_redis::CachedGuild::save(&guild)
-            .query_async(&mut self.redis)
-            .await?;
-        hourai_redis::CachedVoiceState::update_guild(&guild)
-            .query_async(&mut self.redis)
-            .await?;
+        caching::on_guild_available(&guild, &self.audits, &mut self.redis).await?;
 
         Ok(())
     }
 
     async fn on_guild_update(mut self, evt: GuildUpdate) -> Result<()> {
-        hourai_redis::CachedGuild::save_resource(evt.0.id, evt.0.id, &evt.0)
-            .query_async(&mut self.redis)
-            .await?;
-        Ok(())
+        caching::on_guild_update(&evt.0, &self.audits, &mut self.redis).await
     }
 
     async fn on_guild_leave(mut self, evt: GuildDelete) -> Result<()> {
         info!("Left guild {}", evt.id);
-        hourai_redis::CachedGuild::delete(evt.id)
-            .query_async(&mut self.redis)
-            .await?;
-        hourai_redis::CachedVoiceState::clear_guild(evt.id)
-            .query_async(&mut self.redis)
-            .await?;
-        let (res1, res2) = futures::join!(
+        caching::on_guild_leave(evt.id, &self.audits, &mut self.redis).await?;
+        self.member_writer.discard_guild(evt.id).await;
+        let (res1, res2, res3) = futures::join!(
             hourai_sql::Member::clear_guild(evt.id).execute(&self.sql),
             Ban::clear_guild(evt.id).execute(&self.sql),
+            VerificationPropagation::finish(evt.id).fetch_optional(&self.sql),
         );
         res1?;
         res2?;
+        res3?;
         Ok(())
     }
 
     async fn on_role_create(mut self, evt: RoleCreate) -> Result<()> {
-        hourai_redis::CachedGuild::save_resource(evt.guild_id, evt.role.id, &evt.role)
-            .query_async(&mut self.redis)
-            .await?;
-        Ok(())
+        caching::on_role_update(evt.guild_id, &evt.role, &self.audits, &mut self.redis).await
     }
 
     async fn on_role_update(mut self, evt: RoleUpdate) -> Result<()> {
-        hourai_redis::CachedGuild::save_resource(evt.guild_id, evt.role.id, &evt.role)
-            .query_async(&mut self.redis)
-            .await?;
-        Ok(())
+        caching::on_role_update(evt.guild_id, &evt.role, &self.audits, &mut self.redis).await
     }
 
     async fn on_role_delete(mut self, evt: RoleDelete) -> Result<()> {
+        // Write out pending updates first, so they cannot restore the deleted role.
+        self.member_writer.flush().await?;
         let res = hourai_sql::Member::clear_role(evt.guild_id, evt.role_id)
             .execute(&self.sql)
             .await;
-        let res2 = hourai_redis::CachedGuild::delete_resource::<Role>(evt.guild_id, evt.role_id)
-            .query_async(&mut self.redis)
-            .await;
+        let res2 =
+            caching::on_role_delete(evt.guild_id, evt.role_id, &self.audits, &mut self.redis).await;
         self.refresh_bans(evt.guild_id).await?;
         res?;
         res2?;
@@ -550,37 +877,22 @@ impl Client {
             Some(id) => id,
             None => return Ok(()),
         };
-        let channel_id: Option<u64> =
-            hourai_redis::CachedVoiceState::get_channel(guild_id, evt.0.user_id)
-                .query_async(&mut self.redis)
-                .await?;
-        let channel_id = channel_id.map(ChannelId);
+        let channel_id =
+            caching::on_voice_state_update(guild_id, &evt.0, &self.audits, &mut self.redis).await?;
         announcements::on_voice_update(&self, evt.0.clone(), channel_id).await?;
-        hourai_redis::CachedVoiceState::save(&evt.0)
-            .query_async(&mut self.redis)
-            .await?;
         Ok(())
     }
 
     async fn log_users(&self, users: Vec<User>) -> Result<()> {
-        let usernames = users.iter().map(|u| Username::new(u)).collect();
-        Username::bulk_insert(usernames).execute(&self.sql).await?;
-        Ok(())
+        let usernames = users.iter().map(|u| normalize::username(u));
+        self.member_writer.queue_usernames(usernames).await
     }
 
     async fn log_members(&self, members: &[Member]) -> Result<()> {
-        let usernames = members.iter().map(|m| Username::new(&m.user)).collect();
-
-        Username::bulk_insert(usernames).execute(&self.sql).await?;
-        let mut txn = self.sql.begin().await?;
-        for member in members {
-            hourai_sql::Member::from(member)
-                .insert()
-                .execute(&mut txn)
-                .await?;
-        }
-        txn.commit().await?;
-        Ok(())
+        let usernames = members.iter().map(|m| normalize::username(&m.user));
+        self.member_writer.queue_usernames(usernames).await?;
+        let members = members.iter().map(|m| normalize::member(m));
+        self.member_writer.queue_members(members).await
     }
 
     async fn refresh_bans(&self, guild_id: GuildId) -> Result<()> {
@@ -598,6 +910,12 @@ impl Client {
             debug!("Fetched {} bans from guild {}", bans.len(), guild_id);
             let mut txn = self.sql.begin().await?;
             Ban::clear_guild(guild_id).execute(&mut txn).await?;
+            BanHistory::reconcile_bans(guild_id, &bans)
+                .execute(&mut txn)
+                .await?;
+            BanHistory::reconcile_unbans(guild_id, &bans)
+                .execute(&mut txn)
+                .await?;
             Ban::bulk_insert(bans).execute(&mut txn).await?;
             txn.commit().await?;
         } else {
@@ -607,3 +925,18 @@ impl Client {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_screened_joins_are_counted_once() {
+        // A pending member is counted when they join, but not again once they pass membership
+        // screening and are handled as joining a second time.
+        let joins = [JoinKind::Joined, JoinKind::PassedScreening];
+        assert_eq!(joins.iter().filter(|kind| kind.is_counted()).count(), 1);
+        assert!(JoinKind::Joined.is_counted());
+        assert!(!JoinKind::PassedScreening.is_counted());
+    }
+}
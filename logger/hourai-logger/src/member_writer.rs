@@ -0,0 +1,230 @@
+use anyhow::{bail, Result};
+use hourai::models::id::*;
+use hourai_sql::{Member, SqlPool, Username};
+use metrics::{counter, gauge, histogram, increment_counter};
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::error;
+
+/// How long writes are buffered for before they are flushed.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The number of buffered writes that triggers a flush before the interval is up.
+const FLUSH_THRESHOLD: usize = 5000;
+
+/// The number of buffered writes at which callers wait for a flush to complete before queueing
+/// more.
+const MAX_PENDING_WRITES: usize = 50000;
+
+/// The maximum number of rows written by a single statement.
+const MAX_BATCH_SIZE: usize = 10000;
+
+#[derive(Default)]
+struct PendingWrites {
+    /// Keyed by guild and user ID. Later updates to the same member replace earlier ones.
+    members: HashMap<(i64, i64), Member>,
+    /// Keyed by user ID, name, and discriminator, the same as the usernames table's unique
+    /// constraint.
+    usernames: HashMap<(i64, String, Option<i32>), Username>,
+}
+
+impl PendingWrites {
+    fn len(&self) -> usize {
+        self.members.len() + self.usernames.len()
+    }
+
+    fn push_member(&mut self, member: Member) {
+        self.members
+            .insert((member.guild_id, member.user_id), member);
+    }
+
+    fn push_username(&mut self, username: Username) {
+        let key = (
+            username.user_id,
+            username.name.clone(),
+            username.discriminator,
+        );
+        self.usernames.entry(key).or_insert(username);
+    }
+
+    /// Adds back writes from a failed flush. Writes queued since the flush started are newer, so
+    /// they take precedence.
+    fn restore(&mut self, failed: PendingWrites) {
+        for (key, member) in failed.members {
+            self.members.entry(key).or_insert(member);
+        }
+        for (key, username) in failed.usernames {
+            self.usernames.entry(key).or_insert(username);
+        }
+    }
+}
+
+struct WriterState {
+    sql: SqlPool,
+    pending: Mutex<PendingWrites>,
+    /// Serializes flushes, so that batches are written in the order they were queued.
+    flush_lock: tokio::sync::Mutex<()>,
+    /// Wakes the background flusher early when enough writes are buffered.
+    notify: Notify,
+    /// Set once the writer is shut down, while holding the `pending` lock, so that no write can
+    /// be queued after the final flush takes the pending writes.
+    closed: AtomicBool,
+}
+
+/// Buffers member and username upserts for a short window and writes them to the database in
+/// bulk, coalescing repeated updates to the same member.
+///
+/// Callers are made to wait for a flush when too many writes are buffered. Once the writer is
+/// shut down, further writes are rejected.
+#[derive(Clone)]
+pub struct MemberWriter(Arc<WriterState>);
+
+impl MemberWriter {
+    pub fn new(sql: SqlPool) -> Self {
+        Self(Arc::new(WriterState {
+            sql,
+            pending: Mutex::new(PendingWrites::default()),
+            flush_lock: tokio::sync::Mutex::new(()),
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+        }))
+    }
+
+    pub async fn queue_members(&self, members: impl IntoIterator<Item = Member>) -> Result<()> {
+        let pending = {
+            let mut writes = self.0.pending.lock().unwrap();
+            if self.0.closed.load(Ordering::Acquire) {
+                bail!("The member writer has been shut down.");
+            }
+            members.into_iter().for_each(|m| writes.push_member(m));
+            writes.len()
+        };
+        self.on_queued(pending).await
+    }
+
+    pub async fn queue_usernames(
+        &self,
+        usernames: impl IntoIterator<Item = Username>,
+    ) -> Result<()> {
+        let pending = {
+            let mut writes = self.0.pending.lock().unwrap();
+            if self.0.closed.load(Ordering::Acquire) {
+                bail!("The member writer has been shut down.");
+            }
+            usernames.into_iter().for_each(|u| writes.push_username(u));
+            writes.len()
+        };
+        self.on_queued(pending).await
+    }
+
+    /// Drops any buffered update to a member, so that it does not overwrite a later change made
+    /// directly to the database.
+    ///
+    /// Waits for any flush in progress to finish first: its writes have already been taken from
+    /// the buffer, and would otherwise land after the change, or be restored if it failed.
+    pub async fn discard_member(&self, guild_id: GuildId, user_id: UserId) {
+        let key = (guild_id.0 as i64, user_id.0 as i64);
+        let _guard = self.0.flush_lock.lock().await;
+        self.0.pending.lock().unwrap().members.remove(&key);
+    }
+
+    /// Drops all buffered updates to a guild's members. Like `discard_member`, this waits for
+    /// any flush in progress to finish first.
+    pub async fn discard_guild(&self, guild_id: GuildId) {
+        let guild_id = guild_id.0 as i64;
+        let _guard = self.0.flush_lock.lock().await;
+        self.0
+            .pending
+            .lock()
+            .unwrap()
+            .members
+            .retain(|(guild, _), _| *guild != guild_id);
+    }
+
+    async fn on_queued(&self, pending: usize) -> Result<()> {
+        gauge!("hourai_member_writer_pending", pending as f64);
+        if pending >= MAX_PENDING_WRITES {
+            increment_counter!("hourai_member_writer_backpressure_total");
+            self.flush().await
+        } else {
+            if pending >= FLUSH_THRESHOLD {
+                self.0.notify.notify_one();
+            }
+            Ok(())
+        }
+    }
+
+    /// Writes all buffered updates to the database. If the write fails, the updates are kept
+    /// and retried on the next flush.
+    pub async fn flush(&self) -> Result<()> {
+        let _guard = self.0.flush_lock.lock().await;
+        let writes = std::mem::take(&mut *self.0.pending.lock().unwrap());
+        if writes.len() == 0 {
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        let result = self.write(&writes).await;
+        histogram!(
+            "hourai_member_writer_flush_seconds",
+            start.elapsed().as_secs_f64()
+        );
+        match result {
+            Ok(()) => {
+                counter!("hourai_member_writer_rows_total", writes.members.len() as u64, "table" => "members");
+                counter!("hourai_member_writer_rows_total", writes.usernames.len() as u64, "table" => "usernames");
+                Ok(())
+            }
+            Err(err) => {
+                increment_counter!("hourai_member_writer_errors_total");
+                self.0.pending.lock().unwrap().restore(writes);
+                Err(err)
+            }
+        }
+    }
+
+    async fn write(&self, writes: &PendingWrites) -> Result<()> {
+        let usernames: Vec<Username> = writes.usernames.values().cloned().collect();
+        for batch in usernames.chunks(MAX_BATCH_SIZE) {
+            Username::bulk_insert(batch.to_vec())
+                .execute(&self.0.sql)
+                .await?;
+        }
+        let members: Vec<Member> = writes.members.values().cloned().collect();
+        for batch in members.chunks(MAX_BATCH_SIZE) {
+            Member::bulk_insert(batch.to_vec())
+                .execute(&self.0.sql)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Flushes buffered writes until the writer is shut down.
+    pub async fn run(self) {
+        while !self.0.closed.load(Ordering::Acquire) {
+            tokio::select! {
+                _ = tokio::time::sleep(FLUSH_INTERVAL) => {}
+                _ = self.0.notify.notified() => {}
+            }
+            if let Err(err) = self.flush().await {
+                error!("Error while flushing member updates: {:?}", err);
+            }
+        }
+    }
+
+    /// Stops accepting writes and flushes everything that is still pending. Writes queued after
+    /// this fail.
+    pub async fn shutdown(&self) -> Result<()> {
+        {
+            let _pending = self.0.pending.lock().unwrap();
+            self.0.closed.store(true, Ordering::Release);
+        }
+        self.0.notify.notify_one();
+        self.flush().await
+    }
+}
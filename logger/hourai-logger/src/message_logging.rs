@@ -3,13 +3,43 @@ use anyhow::anyhow;
 use anyhow::Result;
 use chrono::Utc;
 use hourai::models::gateway::payload::{MessageDelete, MessageDeleteBulk};
+use hourai::models::guild::Permissions;
 use hourai::models::id::*;
 use hourai::models::{MessageLike, Snowflake, UserLike};
 use hourai::proto::guild_configs::*;
 use hourai::proto::util::IdFilter;
 use hourai_redis::{CachedMessage, GuildConfig};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
 use twilight_embed_builder::*;
 
+/// How long whether the bot can post to an output channel is cached for.
+const CAN_POST_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Caches whether the bot can post to each output channel, so that logging a message does not
+/// need to look up the bot's permissions every time.
+#[derive(Clone, Default)]
+pub struct PostPermissionCache(Arc<Mutex<HashMap<ChannelId, (Instant, bool)>>>);
+
+impl PostPermissionCache {
+    fn get(&self, channel_id: ChannelId) -> Option<bool> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&channel_id)
+            .filter(|(checked, _)| checked.elapsed() < CAN_POST_CACHE_TTL)
+            .map(|(_, can_post)| *can_post)
+    }
+
+    fn insert(&self, channel_id: ChannelId, can_post: bool) {
+        let mut cache = self.0.lock().unwrap();
+        cache.retain(|_, (checked, _)| checked.elapsed() < CAN_POST_CACHE_TTL);
+        cache.insert(channel_id, (Instant::now(), can_post));
+    }
+}
+
 fn message_base_embed(message: &impl MessageLike) -> Result<EmbedBuilder> {
     let author = message.author();
     Ok(EmbedBuilder::new()
@@ -64,6 +94,31 @@ fn get_output_channel(
     Some(ChannelId(id))
 }
 
+/// Checks if the bot is able to post logs to the output channel. If the bot's permissions
+/// cannot be looked up, the post is attempted anyway.
+async fn can_post(client: &Client, guild_id: GuildId, channel_id: ChannelId) -> bool {
+    if let Some(can_post) = client.post_permissions.get(channel_id) {
+        return can_post;
+    }
+    match client
+        .fetch_channel_permissions(guild_id, channel_id, client.user_id)
+        .await
+    {
+        Ok(perms) => {
+            let can_post = perms.contains(Permissions::SEND_MESSAGES | Permissions::EMBED_LINKS);
+            client.post_permissions.insert(channel_id, can_post);
+            can_post
+        }
+        Err(err) => {
+            debug!(
+                "Failed to check permissions to post in {}: {:?}",
+                channel_id, err
+            );
+            true
+        }
+    }
+}
+
 pub(super) async fn on_message_update(
     mut client: Client,
     before: impl MessageLike,
@@ -77,6 +132,9 @@ pub(super) async fn on_message_update(
     let type_config = config.get_edited_messages();
     let output_channel = get_output_channel(&config, type_config);
     if output_channel.is_some() && should_log(type_config, before.channel_id()) {
+        if !can_post(&client, guild_id, output_channel.unwrap()).await {
+            return Ok(());
+        }
         client
             .http_client
             .create_message(output_channel.unwrap())
@@ -103,7 +161,7 @@ pub(super) async fn on_message_delete(client: &mut Client, evt: &MessageDelete)
     if output_channel.is_some() && should_log(type_config, evt.channel_id) {
         let cached = CachedMessage::fetch(evt.channel_id, evt.id, &mut client.redis).await?;
         if let Some(msg) = cached {
-            if msg.author().bot() {
+            if msg.author().bot() || !can_post(client, guild_id, output_channel.unwrap()).await {
                 return Ok(());
             }
             client
@@ -134,6 +192,9 @@ pub(super) async fn on_message_bulk_delete(
     let type_config = config.get_deleted_messages();
     let output_channel = get_output_channel(&config, type_config);
     if output_channel.is_some() && should_log(type_config, evt.channel_id) {
+        if !can_post(&client, guild_id, output_channel.unwrap()).await {
+            return Ok(());
+        }
         client
             .http_client
             .create_message(output_channel.unwrap())
@@ -0,0 +1,219 @@
+use crate::Client;
+use anyhow::Result;
+use hourai::{models::id::*, proto::guild_configs::VerificationConfig};
+use hourai_redis::GuildConfig;
+use hourai_sql::{verification::VerificationPropagation, Member};
+use metrics::counter;
+use std::time::Duration;
+use tracing::{debug, error};
+
+/// The number of members given the role between progress reports. Cancelling a job takes
+/// effect at the end of the current batch.
+const BATCH_SIZE: i64 = 20;
+
+/// The delay between giving members the role, leaving room in the guild's rate limits for
+/// other requests.
+const GRANT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often to check for new jobs while idle.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The outcome of starting a job.
+pub enum StartResult {
+    Started,
+    AlreadyRunning,
+    NoRole,
+    NothingToDo,
+}
+
+/// Starts giving a guild's verification role to the existing members that have completed
+/// membership screening, reporting progress in the given channel.
+pub async fn start(
+    client: &Client,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Result<StartResult> {
+    let config =
+        GuildConfig::fetch_or_default::<VerificationConfig>(guild_id, &mut client.redis.clone())
+            .await?;
+    if !config.get_enabled() || !config.has_role_id() {
+        return Ok(StartResult::NoRole);
+    }
+    let role_id = RoleId(config.get_role_id());
+
+    let running = VerificationPropagation::fetch_guilds(&[guild_id])
+        .fetch_optional(client.sql.primary())
+        .await?;
+    if running.is_some() {
+        return Ok(StartResult::AlreadyRunning);
+    }
+    let (total,) = Member::count_missing_role(guild_id, role_id)
+        .fetch_one(&client.sql)
+        .await?;
+    if total == 0 {
+        return Ok(StartResult::NothingToDo);
+    }
+
+    let message = client
+        .http_client
+        .create_message(channel_id)
+        .content(progress_message(0, total))?
+        .await?;
+    let job = VerificationPropagation::start(guild_id, role_id, channel_id, message.id, total)
+        .fetch_optional(&client.sql)
+        .await?;
+    Ok(match job {
+        Some(_) => StartResult::Started,
+        None => {
+            // Another job was started in the meantime.
+            client
+                .http_client
+                .delete_message(channel_id, message.id)
+                .await?;
+            StartResult::AlreadyRunning
+        }
+    })
+}
+
+/// Cancels a guild's job. Returns false if there was no job in progress.
+pub async fn cancel(client: &Client, guild_id: GuildId) -> Result<bool> {
+    let job = match VerificationPropagation::finish(guild_id)
+        .fetch_optional(&client.sql)
+        .await?
+    {
+        Some(job) => job,
+        None => return Ok(false),
+    };
+    report(
+        client,
+        &job,
+        format!(
+            "Propagation cancelled. Gave the verification role to {} of {} members.",
+            job.granted, job.total
+        ),
+    )
+    .await;
+    Ok(true)
+}
+
+/// Runs the jobs of every guild this process serves, a batch at a time. Progress is recorded
+/// after each batch, so jobs interrupted by a restart resume where they left off.
+pub async fn run_propagations(client: Client) {
+    loop {
+        match run_batches(&client).await {
+            Ok(true) => {}
+            Ok(false) => tokio::time::sleep(POLL_INTERVAL).await,
+            Err(err) => {
+                error!("Error while propagating verification roles: {:?}", err);
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Runs a single batch of every job in progress. Returns false if there were none.
+async fn run_batches(client: &Client) -> Result<bool> {
+    // Jobs are read from the primary, as a replica lagging behind the progress recorded by the
+    // last batch would see finished jobs as running, and repeat batches.
+    let jobs = VerificationPropagation::fetch_guilds(&client.cache.guilds())
+        .fetch_all(client.sql.primary())
+        .await?;
+    for job in jobs.iter() {
+        if let Err(err) = run_batch(client, job).await {
+            error!(
+                "Error while propagating the verification role in guild {}: {:?}",
+                job.guild_id(),
+                err
+            );
+        }
+    }
+    Ok(!jobs.is_empty())
+}
+
+async fn run_batch(client: &Client, job: &VerificationPropagation) -> Result<()> {
+    let guild_id = job.guild_id();
+    let batch =
+        Member::fetch_missing_role(guild_id, job.role_id(), job.last_member_id(), BATCH_SIZE)
+            .fetch_all(&client.sql)
+            .await?;
+
+    if batch.is_empty() {
+        if let Some(job) = VerificationPropagation::finish(guild_id)
+            .fetch_optional(&client.sql)
+            .await?
+        {
+            report(
+                client,
+                &job,
+                format!(
+                    "Propagation complete! Gave the verification role to {} of {} members.",
+                    job.granted, job.total
+                ),
+            )
+            .await;
+        }
+        return Ok(());
+    }
+
+    let mut granted = 0;
+    let mut last_member_id = job.last_member_id();
+    for (user_id,) in batch {
+        let user_id = UserId(user_id as u64);
+        let result = client
+            .http_client
+            .add_guild_member_role(guild_id, user_id, job.role_id())
+            .await;
+        match result {
+            Ok(_) => granted += 1,
+            // Members may have left, or the bot may no longer be able to give out the role.
+            Err(err) => debug!(
+                "Failed to give {} the verification role in {}: {:?}",
+                user_id, guild_id, err
+            ),
+        }
+        last_member_id = user_id;
+        tokio::time::sleep(GRANT_INTERVAL).await;
+    }
+    counter!("hourai_verification_roles_propagated_total", granted as u64);
+
+    let job = VerificationPropagation::record_progress(guild_id, last_member_id, granted)
+        .fetch_optional(&client.sql)
+        .await?;
+    // The job is gone if it was cancelled mid-batch.
+    if let Some(job) = job {
+        report(client, &job, progress_message(job.granted, job.total)).await;
+    }
+    Ok(())
+}
+
+fn progress_message(granted: i64, total: i64) -> String {
+    format!(
+        "Propagating the verification role... ({}/{} members)",
+        granted, total
+    )
+}
+
+/// Edits a job's progress message. The message may have been deleted, so failures are only
+/// logged.
+async fn report(client: &Client, job: &VerificationPropagation, content: String) {
+    if let Err(err) = update_message(client, job, content).await {
+        debug!(
+            "Failed to report verification role propagation progress in {}: {:?}",
+            job.guild_id(),
+            err
+        );
+    }
+}
+
+async fn update_message(
+    client: &Client,
+    job: &VerificationPropagation,
+    content: String,
+) -> Result<()> {
+    client
+        .http_client
+        .update_message(job.channel_id(), job.message_id())
+        .content(content)?
+        .await?;
+    Ok(())
+}
@@ -44,6 +44,18 @@ async fn get_verification_role(client: &Client, guild_id: GuildId) -> Result<Opt
     }
 }
 
+/// Checks if a member is a moderator: either an administrator, or holding a role flagged as a
+/// moderator role in the guild's role config.
+pub async fn is_moderator(
+    client: &Client,
+    guild_id: GuildId,
+    user_id: UserId,
+    roles: &[RoleId],
+) -> Result<bool> {
+    hourai_redis::CachedGuild::is_moderator(guild_id, user_id, roles, &mut client.redis.clone())
+        .await
+}
+
 pub async fn on_member_join(client: &Client, member: &Member) -> Result<()> {
     let guild_id = member.guild_id;
     let user_id = member.user.id;
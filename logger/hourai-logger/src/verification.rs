@@ -0,0 +1,184 @@
+use crate::Client;
+use anyhow::Result;
+use hourai::{
+    models::{
+        channel::embed::Embed,
+        guild::{Member, Permissions},
+        id::*,
+        user::User,
+        UserLike,
+    },
+    proto::guild_configs::{LoggingConfig, VerificationConfig},
+};
+use hourai_redis::GuildConfig;
+use hourai_sql::verification::VerificationResult;
+use hourai_validation::{context::VerificationDecision, dry_run};
+use tracing::error;
+use twilight_embed_builder::*;
+
+/// The longest description an embed can have.
+const MAX_DESCRIPTION_LENGTH: usize = 2048;
+
+/// The longest value an embed field can have.
+const MAX_FIELD_LENGTH: usize = 1024;
+
+/// The longest appeal that can be forwarded to a guild's modlog.
+pub const MAX_APPEAL_LENGTH: usize = MAX_DESCRIPTION_LENGTH;
+
+/// How long rejected members have to appeal the decision.
+const APPEAL_WINDOW_DAYS: i64 = 7;
+
+/// The outcome of checking how a member would be verified.
+pub enum CheckResult {
+    Decided(Member, VerificationDecision),
+    Disabled,
+    UnknownUser,
+}
+
+/// Checks how a member, or a user that is not yet a member, would be verified by the guild's
+/// current configuration. Nothing is recorded about the member, and no roles are changed.
+pub async fn check(client: &Client, guild_id: GuildId, user_id: UserId) -> Result<CheckResult> {
+    let config =
+        GuildConfig::fetch_or_default::<VerificationConfig>(guild_id, &mut client.redis.clone())
+            .await?;
+    if !config.get_enabled() {
+        return Ok(CheckResult::Disabled);
+    }
+    let member = match dry_run::fetch_member(&client.http_client, guild_id, user_id).await? {
+        Some(member) => member,
+        None => return Ok(CheckResult::UnknownUser),
+    };
+    match dry_run::run(&config, &client.verifier_resources, member.clone()).await? {
+        Some(decision) => Ok(CheckResult::Decided(member, decision)),
+        None => Ok(CheckResult::Disabled),
+    }
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_owned();
+    }
+    let mut truncated: String = text.chars().take(max - 3).collect();
+    truncated.push_str("...");
+    truncated
+}
+
+pub fn decision_embed(member: &Member, decision: &VerificationDecision) -> Result<Embed> {
+    let user = &member.user;
+    let (outcome, color) = if decision.approved {
+        // Green
+        ("Approved", 0x2ecc71)
+    } else {
+        // Red
+        ("Rejected", 0xe74c3c)
+    };
+    Ok(EmbedBuilder::new()
+        .title(format!(
+            "Verification {} | {}",
+            outcome,
+            user.display_name()
+        ))?
+        .color(color)?
+        .description(truncate(&decision.explain(), MAX_DESCRIPTION_LENGTH))?
+        .field(EmbedFieldBuilder::new("User", format!("<@{}> ({})", user.id, user.id))?.inline())
+        .field(EmbedFieldBuilder::new("Score", decision.score.to_string())?.inline())
+        .field(EmbedFieldBuilder::new("Threshold", decision.threshold.to_string())?.inline())
+        .footer(EmbedFooterBuilder::new(
+            "Dry run: the member was not verified and their roles were not changed.",
+        )?)
+        .build()?)
+}
+
+/// The outcome of a member appealing their rejection.
+pub enum AppealResult {
+    /// The appeal was forwarded to the modlog of the guild that rejected them.
+    Forwarded,
+    /// There are no recent rejections left to appeal.
+    NothingToAppeal,
+    /// The guild that rejected them has no modlog to forward appeals to.
+    NoModlog,
+}
+
+/// Forwards a user's appeal against their most recent rejection to the modlog of the guild that
+/// rejected them. Each rejection can only be appealed once.
+pub async fn appeal(client: &Client, user: &User, message: &str) -> Result<AppealResult> {
+    let since = chrono::Utc::now() - chrono::Duration::days(APPEAL_WINDOW_DAYS);
+    let rejection = match VerificationResult::fetch_appealable(user.id, since)
+        .fetch_all(&client.sql)
+        .await?
+        .into_iter()
+        .next()
+    {
+        Some(rejection) => rejection,
+        None => return Ok(AppealResult::NothingToAppeal),
+    };
+
+    let guild_id = rejection.guild_id();
+    let channel_id = match modlog_channel(client, guild_id).await? {
+        Some(channel_id) => channel_id,
+        None => return Ok(AppealResult::NoModlog),
+    };
+    let rejection = match VerificationResult::mark_appealed(rejection.id)
+        .fetch_optional(&client.sql)
+        .await?
+    {
+        Some(rejection) => rejection,
+        // Already appealed in the meantime.
+        None => return Ok(AppealResult::NothingToAppeal),
+    };
+
+    let embed = appeal_embed(client, user, &rejection, message)?;
+    if let Err(err) = client
+        .http_client
+        .create_message(channel_id)
+        .embed(embed)?
+        .await
+    {
+        error!("Failed to post appeal to the modlog: {:?}", err);
+    }
+    Ok(AppealResult::Forwarded)
+}
+
+/// Gets the guild's modlog channel, if it has one the bot can post embeds in.
+async fn modlog_channel(client: &Client, guild_id: GuildId) -> Result<Option<ChannelId>> {
+    let config =
+        GuildConfig::fetch_or_default::<LoggingConfig>(guild_id, &mut client.redis.clone()).await?;
+    if !config.has_modlog_channel_id() {
+        return Ok(None);
+    }
+
+    let channel_id = ChannelId(config.get_modlog_channel_id());
+    let perms = client
+        .fetch_channel_permissions(guild_id, channel_id, client.user_id)
+        .await?;
+    if !perms.contains(Permissions::SEND_MESSAGES | Permissions::EMBED_LINKS) {
+        return Ok(None);
+    }
+    Ok(Some(channel_id))
+}
+
+fn appeal_embed(
+    client: &Client,
+    user: &User,
+    rejection: &VerificationResult,
+    message: &str,
+) -> Result<Embed> {
+    let reasons = if rejection.reasons.is_empty() {
+        "No reasons given.".to_owned()
+    } else {
+        truncate(&rejection.reasons.join("\n"), MAX_FIELD_LENGTH)
+    };
+    Ok(EmbedBuilder::new()
+        .title(format!("Verification Appeal | {}", user.display_name()))?
+        // Yellow
+        .color(0xf1c40f)?
+        .description(truncate(message, MAX_DESCRIPTION_LENGTH))?
+        .field(EmbedFieldBuilder::new("User", format!("<@{}> ({})", user.id, user.id))?.inline())
+        .field(EmbedFieldBuilder::new("Rejected", rejection.created_at.to_rfc2822())?.inline())
+        .field(EmbedFieldBuilder::new("Reasons", reasons)?)
+        .footer(EmbedFooterBuilder::new(format!(
+            "Use {}validation verify {} to verify them.",
+            client.command_prefix, user.id
+        ))?)
+        .build()?)
+}
@@ -31,7 +31,7 @@ use hyper::{
     service::Service,
     Body, Request,
 };
-use std::{convert::TryFrom, str::FromStr, collections::HashMap};
+use std::{convert::TryFrom, str::FromStr};
 use twilight_command_parser::{CommandParserConfig, Parser};
 use twilight_lavalink::{model::*, Lavalink};
 
@@ -255,9 +255,7 @@ impl Client<'static> {
     /// Sets the music config for the sever.
     pub async fn set_config(&self, guild_id: GuildId, config: MusicConfig) -> Result<()> {
         let mut conn = self.redis.clone();
-        GuildConfig::set::<MusicConfig>(guild_id, config)
-            .query_async(&mut conn)
-            .await?;
+        GuildConfig::set::<MusicConfig>(guild_id, config, &mut conn).await?;
         Ok(())
     }
 
@@ -291,10 +289,8 @@ impl Client<'static> {
     pub async fn count_listeners(&self, guild_id: GuildId) -> Result<usize> {
         Ok(if let Some(channel_id) = self.get_channel(guild_id) {
             let mut redis = self.redis.clone();
-            let states: HashMap<u64, u64> = hourai_redis::CachedVoiceState::get_channels(guild_id)
-                .query_async(&mut redis)
-                .await?;
-            states.into_iter().filter(|(_, v)| *v == channel_id.0).count()
+            let states = hourai_redis::CachedVoiceState::get_channels(guild_id, &mut redis).await?;
+            states.values().filter(|v| **v == channel_id).count()
         } else {
             0
         })
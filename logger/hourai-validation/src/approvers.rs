@@ -1,40 +1,62 @@
 use super::{context, *};
+use crate::context::weights;
 use async_trait::async_trait;
-use hourai::cache::InMemoryCache;
 use hourai::models::id::UserId;
 use hourai::models::user::{User, UserFlags};
+use hourai_redis::{CachedGuild, RedisPool};
 use std::collections::HashSet;
 
 const VERIFIED_FEATURE: &str = "VERIFIED";
+const PARTNERED_FEATURE: &str = "PARTNERED";
 
-struct DistinguishedUserVerifier(InMemoryCache);
+/// Outweighs any number of suspicious account characteristics, but nothing more severe.
+const NITRO_WEIGHT: i32 = 5 * weights::SUSPICIOUS;
+
+/// Outweighs bans from other servers, but not a lockdown.
+const DISTINGUISHED_WEIGHT: i32 = 2 * weights::MALICIOUS;
+
+struct DistinguishedUserVerifier(RedisPool);
 
 #[async_trait]
 impl Verifier for DistinguishedUserVerifier {
     async fn verify(&self, ctx: &mut context::VerificationContext) -> Result<()> {
         let flags = ctx.member().user.flags.unwrap_or_else(UserFlags::empty);
+        let category = SignalCategory::Distinguished;
         if flags.contains(UserFlags::DISCORD_EMPLOYEE) {
-            ctx.add_approval_reason("User is Discord Staff.");
+            ctx.approve(category, DISTINGUISHED_WEIGHT, "User is Discord Staff.");
         }
         if flags.contains(UserFlags::DISCORD_PARTNER) {
-            ctx.add_approval_reason("User is a Discord Partner.");
+            ctx.approve(category, DISTINGUISHED_WEIGHT, "User is a Discord Partner.");
         }
         if flags.contains(UserFlags::VERIFIED_BOT_DEVELOPER) {
-            ctx.add_approval_reason("User is a verified bot developer.");
+            ctx.approve(
+                category,
+                DISTINGUISHED_WEIGHT,
+                "User is a verified bot developer.",
+            );
+        }
+
+        let owned =
+            CachedGuild::fetch_owned_guilds(ctx.member().user.id, &mut self.0.clone()).await?;
+        let owns = |feature: &str| {
+            owned
+                .values()
+                .any(|features| features.iter().any(|f| f == feature))
+        };
+        if owns(VERIFIED_FEATURE) {
+            ctx.approve(
+                category,
+                DISTINGUISHED_WEIGHT,
+                "User is the owner of a verified server.",
+            );
+        }
+        if owns(PARTNERED_FEATURE) {
+            ctx.approve(
+                category,
+                DISTINGUISHED_WEIGHT,
+                "User is the owner of a partnered server.",
+            );
         }
-        // TODO(james7123): This will not scale to multiple processes
-        //let member_id = ctx.member().user.id;
-        //for guild_id in self.0.guilds() {
-        //if let Some(guild) = self.0.guild(guild_id) {
-        //let verified = guild
-        //.features
-        //.iter()
-        //.any(|feat| feat.as_ref() == VERIFIED_FEATURE);
-        //if guild.owner_id == member_id && verified {
-        //ctx.add_approval_reason("User is the owner of a verified server.");
-        //}
-        //}
-        //}
         Ok(())
     }
 }
@@ -54,6 +76,8 @@ pub fn user_has_nitro(user: &User) -> bool {
 
 pub(super) fn nitro() -> BoxedVerifier {
     GenericVerifier::new_approver(
+        SignalCategory::Account,
+        NITRO_WEIGHT,
         "User currently has or has had Nitro. Probably not a user bot.",
         |ctx| Ok(user_has_nitro(&ctx.member().user)),
     )
@@ -61,18 +85,23 @@ pub(super) fn nitro() -> BoxedVerifier {
 
 pub(super) fn bot_owners(owners: impl IntoIterator<Item = UserId>) -> BoxedVerifier {
     let owner_ids: HashSet<UserId> = owners.into_iter().collect();
-    GenericVerifier::new_approver("User is an owner of this bot.", move |ctx| {
-        Ok(owner_ids.contains(&ctx.member().user.id))
-    })
+    GenericVerifier::new_approver(
+        SignalCategory::Override,
+        weights::OVERRIDE,
+        "User is an owner of this bot.",
+        move |ctx| Ok(owner_ids.contains(&ctx.member().user.id)),
+    )
 }
 
 pub(super) fn bot() -> BoxedVerifier {
     GenericVerifier::new_approver(
+        SignalCategory::Override,
+        weights::OVERRIDE,
         "User is an OAuth2 bot that can only be manually added by moderators.",
         |ctx| Ok(ctx.member().user.bot),
     )
 }
 
-pub(super) fn distinguished_user(cache: InMemoryCache) -> BoxedVerifier {
-    Box::new(DistinguishedUserVerifier(cache))
+pub(super) fn distinguished_user(redis: RedisPool) -> BoxedVerifier {
+    Box::new(DistinguishedUserVerifier(redis))
 }
@@ -0,0 +1,239 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use hourai::models::id::UserId;
+use hourai_sql::{AvatarHash, SqlPool};
+use image::imageops::FilterType;
+
+const DISCORD_CDN_URL: &str = "https://cdn.discordapp.com";
+
+/// The size avatars are downloaded at. They are shrunk to 9x8 for hashing, so anything larger
+/// only costs bandwidth.
+const AVATAR_SIZE: u32 = 64;
+
+/// The default maximum number of bits two avatar hashes can differ by to be considered the
+/// same image.
+pub const DEFAULT_MAX_DISTANCE: u32 = 8;
+
+/// Computes a 64-bit difference hash (dHash) of an image.
+///
+/// The image is shrunk to 9x8 grayscale pixels, and each bit records whether a pixel is
+/// brighter than its right neighbor. Re-encoding, resizing, or making small edits to an image
+/// only flips a few bits, so the hashes of near-identical images have a small Hamming distance.
+pub fn dhash(image: &[u8]) -> Result<u64> {
+    let image = image::load_from_memory(image)?
+        .resize_exact(9, 8, FilterType::Triangle)
+        .to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if image.get_pixel(x, y)[0] > image.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Ok(hash)
+}
+
+/// The number of bits that differ between two hashes.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Downloads user avatars.
+#[async_trait]
+pub trait AvatarFetcher: Send + Sync {
+    async fn fetch(&self, user_id: UserId, avatar: &str) -> Result<Vec<u8>>;
+}
+
+/// Downloads avatars from Discord's CDN, or any server that serves the same paths.
+pub struct HttpAvatarFetcher {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl HttpAvatarFetcher {
+    pub fn new(http: reqwest::Client) -> Self {
+        Self::with_base_url(http, DISCORD_CDN_URL)
+    }
+
+    pub fn with_base_url(http: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Self {
+            http,
+            base_url: base_url.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl AvatarFetcher for HttpAvatarFetcher {
+    async fn fetch(&self, user_id: UserId, avatar: &str) -> Result<Vec<u8>> {
+        // Animated avatars are requested as PNGs as well, which only returns the first frame.
+        let url = format!(
+            "{}/avatars/{}/{}.png?size={}",
+            self.base_url, user_id, avatar, AVATAR_SIZE
+        );
+        let response = self.http.get(url).send().await?.error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+/// Gets the hash of a user's avatar. Avatars are only downloaded and hashed the first time they
+/// are seen, the hash is stored for later calls.
+pub async fn hash_avatar(
+    sql: &SqlPool,
+    fetcher: &dyn AvatarFetcher,
+    user_id: UserId,
+    avatar: &str,
+) -> Result<u64> {
+    if let Some(hash) = stored_hash(sql, user_id, avatar).await? {
+        return Ok(hash);
+    }
+
+    let hash = dhash(&fetcher.fetch(user_id, avatar).await?)?;
+    AvatarHash::new(user_id, avatar, hash)
+        .insert()
+        .execute(sql)
+        .await?;
+    Ok(hash)
+}
+
+/// Like `hash_avatar`, but does not store the hash if the avatar has not been hashed before.
+pub async fn peek_avatar_hash(
+    sql: &SqlPool,
+    fetcher: &dyn AvatarFetcher,
+    user_id: UserId,
+    avatar: &str,
+) -> Result<u64> {
+    match stored_hash(sql, user_id, avatar).await? {
+        Some(hash) => Ok(hash),
+        None => dhash(&fetcher.fetch(user_id, avatar).await?),
+    }
+}
+
+async fn stored_hash(sql: &SqlPool, user_id: UserId, avatar: &str) -> Result<Option<u64>> {
+    Ok(AvatarHash::fetch(user_id, avatar)
+        .fetch_optional(sql)
+        .await?
+        .map(|stored| stored.hash()))
+}
+
+/// Hashes the avatars of up to `limit` banned users that have not been hashed yet, so that they
+/// can be matched by `VerificationBan::fetch_by_avatar_hash`. Avatars that fail to download or
+/// decode are skipped and retried on a later call.
+///
+/// Returns the number of avatars hashed, and the errors for any that were skipped.
+pub async fn hash_banned_avatars(
+    sql: &SqlPool,
+    fetcher: &dyn AvatarFetcher,
+    limit: i64,
+) -> Result<(usize, Vec<anyhow::Error>)> {
+    let unhashed = AvatarHash::fetch_unhashed_bans(limit)
+        .fetch_all(sql)
+        .await?;
+    let mut hashed = 0;
+    let mut errors = Vec::new();
+    for (user_id, avatar) in unhashed {
+        match hash_avatar(sql, fetcher, UserId(user_id as u64), &avatar).await {
+            Ok(_) => hashed += 1,
+            Err(err) => errors.push(err),
+        }
+    }
+    Ok((hashed, errors))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{ImageBuffer, ImageOutputFormat, Luma};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn encode_png(image: &ImageBuffer<Luma<u8>, Vec<u8>>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image::DynamicImage::ImageLuma8(image.clone())
+            .write_to(&mut bytes, ImageOutputFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    /// A gradient overlaid with a checkerboard, so that the image has detail at the scale the
+    /// hash samples it at.
+    fn gradient(size: u32) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+        ImageBuffer::from_fn(size, size, |x, y| {
+            Luma([((x * 7 + y * 3) % 256) as u8 ^ if (x / 8 + y / 8) % 2 == 0 { 0 } else { 0xFF }])
+        })
+    }
+
+    #[test]
+    fn test_similar_images_have_close_hashes() {
+        let original = gradient(128);
+        let hash = dhash(&encode_png(&original)).unwrap();
+
+        // Resized and slightly brightened.
+        let mut edited = image::imageops::resize(&original, 96, 96, FilterType::Nearest);
+        edited
+            .pixels_mut()
+            .for_each(|p| p[0] = p[0].saturating_add(4));
+        let edited_hash = dhash(&encode_png(&edited)).unwrap();
+        assert!(hamming_distance(hash, edited_hash) <= DEFAULT_MAX_DISTANCE);
+
+        let inverted = ImageBuffer::from_fn(128, 128, |x, y| Luma([255 - original[(x, y)][0]]));
+        let inverted_hash = dhash(&encode_png(&inverted)).unwrap();
+        assert!(hamming_distance(hash, inverted_hash) > DEFAULT_MAX_DISTANCE);
+    }
+
+    #[test]
+    fn test_invalid_images_fail_to_hash() {
+        assert!(dhash(b"not an image").is_err());
+    }
+
+    #[test]
+    fn test_hamming_distance() {
+        assert_eq!(hamming_distance(0, 0), 0);
+        assert_eq!(hamming_distance(0b1010, 0b0110), 2);
+        assert_eq!(hamming_distance(0, u64::MAX), 64);
+    }
+
+    /// Serves a single HTTP response, and returns the path that was requested.
+    async fn serve_once(
+        status: &'static str,
+        body: Vec<u8>,
+    ) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 4096];
+            let len = socket.read(&mut request).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..len]).into_owned();
+            let header = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            );
+            socket.write_all(header.as_bytes()).await.unwrap();
+            socket.write_all(&body).await.unwrap();
+            request.split_whitespace().nth(1).unwrap().to_owned()
+        });
+        (base_url, server)
+    }
+
+    #[tokio::test]
+    async fn test_http_fetcher() {
+        let png = encode_png(&gradient(64));
+        let (base_url, server) = serve_once("200 OK", png.clone()).await;
+        let fetcher = HttpAvatarFetcher::with_base_url(reqwest::Client::new(), base_url);
+
+        let avatar = fetcher.fetch(UserId(1000), "a_abcdef").await.unwrap();
+        assert_eq!(avatar, png);
+        assert_eq!(server.await.unwrap(), "/avatars/1000/a_abcdef.png?size=64");
+    }
+
+    #[tokio::test]
+    async fn test_http_fetcher_errors() {
+        let (base_url, _server) = serve_once("404 Not Found", Vec::new()).await;
+        let fetcher = HttpAvatarFetcher::with_base_url(reqwest::Client::new(), base_url);
+        assert!(fetcher.fetch(UserId(1000), "abcdef").await.is_err());
+    }
+}
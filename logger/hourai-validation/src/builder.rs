@@ -0,0 +1,364 @@
+use crate::avatar::{self, AvatarFetcher, HttpAvatarFetcher};
+use crate::context::weights;
+use crate::lists::{ListKind, WordLists};
+use crate::user_bot::{self, JoinTracker};
+use crate::{approvers, rejectors, BoxedVerifier};
+use anyhow::Result;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hourai::config::HouraiConfig;
+use hourai::models::id::UserId;
+use hourai::proto::guild_configs::VerificationConfig;
+use hourai::proto::util::FilterSettings;
+use hourai_redis::RedisPool;
+use hourai_sql::SqlPool;
+use std::sync::Arc;
+
+/// The resources shared by the verifiers of every guild.
+pub struct VerifierResources {
+    pub sql: SqlPool,
+    pub redis: RedisPool,
+    pub avatars: Arc<dyn AvatarFetcher>,
+    pub bot_owners: Vec<UserId>,
+    pub lists: WordLists,
+    pub joins: JoinTracker,
+    /// The maximum Hamming distance between the hashes of two avatars for them to be
+    /// considered the same image.
+    pub max_avatar_distance: u32,
+}
+
+impl VerifierResources {
+    /// Loads the resources for verifying users with the bot's configuration. The word lists are
+    /// loaded once, see `WordLists::watch` to keep them up to date.
+    pub async fn load(
+        config: &HouraiConfig,
+        http: &hourai::http::Client,
+        sql: SqlPool,
+        redis: RedisPool,
+    ) -> Result<Self> {
+        Ok(Self {
+            sql,
+            redis,
+            avatars: Arc::new(HttpAvatarFetcher::new(reqwest::Client::new())),
+            bot_owners: fetch_bot_owners(http).await?,
+            lists: WordLists::load(&config.list_directory)?,
+            joins: JoinTracker::default(),
+            max_avatar_distance: avatar::DEFAULT_MAX_DISTANCE,
+        })
+    }
+}
+
+/// Gets the owners of the bot's application: its owner, or every member of the team that owns
+/// it.
+pub async fn fetch_bot_owners(http: &hourai::http::Client) -> Result<Vec<UserId>> {
+    let application = http.current_user_application().await?;
+    Ok(match application.team {
+        Some(team) => team.members.into_iter().map(|m| m.user.id).collect(),
+        None => vec![application.owner.id],
+    })
+}
+
+/// A single verifier to run, and its settings. See `plan` for how these are derived from a
+/// guild's configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VerifierSpec {
+    NewAccount(Duration),
+    NoAvatar,
+    DeletedUser,
+    LikelyUserBot,
+    UserBotHeuristic,
+    Nitro,
+    UsernameFilter(FilterSettings),
+    SexualUsername,
+    OffensiveUsername,
+    BannedUser { min_guild_size: u64 },
+    BannedUsername,
+    BannedAvatar,
+    Lockdown(DateTime<Utc>),
+    DistinguishedUser,
+    BotOwners,
+    Bot,
+}
+
+/// Lists the verifiers a guild's configuration calls for, in the order they are run. Returns
+/// an empty list if verification is disabled.
+///
+/// Verifiers are ordered from the least to the most severe, roughly following the tiers in
+/// `context::weights`. As signals are weighed together, the order does not change the outcome,
+/// only the order reasons are listed in.
+pub fn plan(config: &VerificationConfig) -> Vec<VerifierSpec> {
+    let mut specs = Vec::new();
+    if !config.get_enabled() {
+        return specs;
+    }
+    let avatar = config.get_avatar();
+    let username = config.get_username();
+    let cross_server = config.get_cross_server();
+
+    // Suspicious
+    if config.get_minimum_account_age() > 0 {
+        let age = Duration::seconds(config.get_minimum_account_age() as i64);
+        specs.push(VerifierSpec::NewAccount(age));
+    }
+    if avatar.get_reject_default_avatars() {
+        specs.push(VerifierSpec::NoAvatar);
+    }
+    specs.push(VerifierSpec::DeletedUser);
+    if username.get_reject_likely_user_bots() {
+        specs.push(VerifierSpec::LikelyUserBot);
+        specs.push(VerifierSpec::UserBotHeuristic);
+    }
+    specs.push(VerifierSpec::Nitro);
+
+    // Questionable
+    if !username.get_username_filter().get_blacklist().is_empty() {
+        let filter = username.get_username_filter().clone();
+        specs.push(VerifierSpec::UsernameFilter(filter));
+    }
+    if username.get_reject_sexual_usernames() {
+        specs.push(VerifierSpec::SexualUsername);
+    }
+    if username.get_reject_offensive_usernames() {
+        specs.push(VerifierSpec::OffensiveUsername);
+    }
+
+    // Malicious
+    if cross_server.get_reject_banned_users() {
+        specs.push(VerifierSpec::BannedUser {
+            min_guild_size: cross_server.get_minimum_guild_size(),
+        });
+    }
+    specs.push(VerifierSpec::BannedUsername);
+    specs.push(VerifierSpec::BannedAvatar);
+
+    // Lockdown
+    // Expirations that are out of range for a timestamp are ignored.
+    if config.has_lockdown_expiration() {
+        let expiration = Utc
+            .timestamp_opt(config.get_lockdown_expiration() as i64, 0)
+            .single();
+        if let Some(expiration) = expiration.filter(|exp| *exp > Utc::now()) {
+            specs.push(VerifierSpec::Lockdown(expiration));
+        }
+    }
+
+    // Overrides
+    specs.push(VerifierSpec::DistinguishedUser);
+    specs.push(VerifierSpec::BotOwners);
+    specs.push(VerifierSpec::Bot);
+    specs
+}
+
+impl VerifierSpec {
+    pub fn build(&self, resources: &VerifierResources) -> Result<BoxedVerifier> {
+        let sql = || resources.sql.clone();
+        Ok(match self {
+            Self::NewAccount(age) => rejectors::new_account(*age),
+            Self::NoAvatar => rejectors::no_avatar(),
+            Self::DeletedUser => rejectors::deleted_user(sql()),
+            Self::LikelyUserBot => rejectors::username_match(
+                sql(),
+                "Likely user bot.",
+                weights::SUSPICIOUS,
+                resources.lists.clone(),
+                &[ListKind::UserBotNames, ListKind::UserBotNamesFullMatch],
+            ),
+            Self::UserBotHeuristic => user_bot::user_bot(sql(), resources.joins.clone()),
+            Self::Nitro => approvers::nitro(),
+            Self::UsernameFilter(filter) => rejectors::username_filter(sql(), filter)?,
+            Self::SexualUsername => rejectors::username_match(
+                sql(),
+                "Sexually inappropriate username.",
+                weights::QUESTIONABLE,
+                resources.lists.clone(),
+                &[ListKind::SexualUsernames],
+            ),
+            Self::OffensiveUsername => rejectors::username_match(
+                sql(),
+                "Offensive username.",
+                weights::QUESTIONABLE,
+                resources.lists.clone(),
+                &[ListKind::OffensiveUsernames],
+            ),
+            Self::BannedUser { min_guild_size } => rejectors::banned_user(sql(), *min_guild_size),
+            Self::BannedUsername => rejectors::banned_username(sql()),
+            Self::BannedAvatar => rejectors::banned_avatar(
+                sql(),
+                resources.avatars.clone(),
+                resources.max_avatar_distance,
+            ),
+            Self::Lockdown(expiration) => rejectors::lockdown(*expiration),
+            Self::DistinguishedUser => approvers::distinguished_user(resources.redis.clone()),
+            Self::BotOwners => approvers::bot_owners(resources.bot_owners.iter().cloned()),
+            Self::Bot => approvers::bot(),
+        })
+    }
+}
+
+/// Builds the verifiers for a guild's configuration. See `plan`.
+pub fn build(
+    config: &VerificationConfig,
+    resources: &VerifierResources,
+) -> Result<Vec<BoxedVerifier>> {
+    plan(config)
+        .iter()
+        .map(|spec| spec.build(resources))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use VerifierSpec::*;
+
+    const DEFAULT_MIN_GUILD_SIZE: u64 = 150;
+
+    fn enabled() -> VerificationConfig {
+        let mut config = VerificationConfig::new();
+        config.set_enabled(true);
+        config
+    }
+
+    fn without(specs: Vec<VerifierSpec>, removed: &[VerifierSpec]) -> Vec<VerifierSpec> {
+        specs
+            .into_iter()
+            .filter(|spec| !removed.contains(spec))
+            .collect()
+    }
+
+    fn default_plan() -> Vec<VerifierSpec> {
+        vec![
+            NewAccount(Duration::days(30)),
+            NoAvatar,
+            DeletedUser,
+            LikelyUserBot,
+            UserBotHeuristic,
+            Nitro,
+            SexualUsername,
+            OffensiveUsername,
+            BannedUser {
+                min_guild_size: DEFAULT_MIN_GUILD_SIZE,
+            },
+            BannedUsername,
+            BannedAvatar,
+            DistinguishedUser,
+            BotOwners,
+            Bot,
+        ]
+    }
+
+    #[test]
+    fn test_disabled_config_has_no_verifiers() {
+        assert!(plan(&VerificationConfig::new()).is_empty());
+        let mut config = enabled();
+        config.set_enabled(false);
+        assert!(plan(&config).is_empty());
+    }
+
+    #[test]
+    fn test_default_config() {
+        assert_eq!(plan(&enabled()), default_plan());
+    }
+
+    #[test]
+    fn test_minimum_account_age() {
+        let mut config = enabled();
+        config.set_minimum_account_age(3600);
+        assert_eq!(plan(&config)[0], NewAccount(Duration::hours(1)));
+
+        config.set_minimum_account_age(0);
+        let expected = without(default_plan(), &[NewAccount(Duration::days(30))]);
+        assert_eq!(plan(&config), expected);
+    }
+
+    #[test]
+    fn test_reject_default_avatars() {
+        let mut config = enabled();
+        config.mut_avatar().set_reject_default_avatars(false);
+        assert_eq!(plan(&config), without(default_plan(), &[NoAvatar]));
+    }
+
+    #[test]
+    fn test_username_flags() {
+        let cases: Vec<(fn(&mut VerificationConfig), Vec<VerifierSpec>)> = vec![
+            (
+                |c| c.mut_username().set_reject_likely_user_bots(false),
+                vec![LikelyUserBot, UserBotHeuristic],
+            ),
+            (
+                |c| c.mut_username().set_reject_sexual_usernames(false),
+                vec![SexualUsername],
+            ),
+            (
+                |c| c.mut_username().set_reject_offensive_usernames(false),
+                vec![OffensiveUsername],
+            ),
+        ];
+        for (disable, specs) in cases {
+            let mut config = enabled();
+            disable(&mut config);
+            assert_eq!(plan(&config), without(default_plan(), &specs));
+        }
+    }
+
+    #[test]
+    fn test_username_filter() {
+        let mut config = enabled();
+        config
+            .mut_username()
+            .mut_username_filter()
+            .mut_whitelist()
+            .push("allowed".to_owned());
+        // A whitelist without a blacklist filters nothing.
+        assert_eq!(plan(&config), default_plan());
+
+        config
+            .mut_username()
+            .mut_username_filter()
+            .mut_blacklist()
+            .push("(?i)nitro".to_owned());
+        let filter = config.get_username().get_username_filter().clone();
+        let specs = plan(&config);
+        let position = specs.iter().position(|spec| spec == &Nitro).unwrap();
+        assert_eq!(specs[position + 1], UsernameFilter(filter));
+        assert_eq!(specs.len(), default_plan().len() + 1);
+    }
+
+    #[test]
+    fn test_cross_server_config() {
+        let mut config = enabled();
+        config.mut_cross_server().set_minimum_guild_size(1000);
+        assert!(plan(&config).contains(&BannedUser {
+            min_guild_size: 1000
+        }));
+
+        config.mut_cross_server().set_reject_banned_users(false);
+        let expected = without(
+            default_plan(),
+            &[BannedUser {
+                min_guild_size: DEFAULT_MIN_GUILD_SIZE,
+            }],
+        );
+        assert_eq!(plan(&config), expected);
+    }
+
+    #[test]
+    fn test_lockdown() {
+        let mut config = enabled();
+        config.set_lockdown_expiration((Utc::now() - Duration::hours(1)).timestamp() as u64);
+        assert_eq!(plan(&config), default_plan());
+
+        let expiration = Utc.timestamp((Utc::now() + Duration::hours(1)).timestamp(), 0);
+        config.set_lockdown_expiration(expiration.timestamp() as u64);
+        let specs = plan(&config);
+        let position = specs.iter().position(|spec| spec == &BannedAvatar).unwrap();
+        assert_eq!(specs[position + 1], Lockdown(expiration));
+        assert_eq!(specs.len(), default_plan().len() + 1);
+    }
+
+    #[test]
+    fn test_lockdown_out_of_range() {
+        let mut config = enabled();
+        config.set_lockdown_expiration(i64::MAX as u64);
+        assert_eq!(plan(&config), default_plan());
+    }
+}
@@ -1,45 +1,199 @@
 use hourai::models::guild::Member;
+use hourai::proto::guild_configs::VerificationScoringConfig;
+use std::fmt::Write;
 
+/// Standard magnitudes for signal weights. Each tier is meant to outweigh any realistic number
+/// of signals from the tier below it.
+pub mod weights {
+    /// Suspicious characteristics that are also common among legitimate users. Checks at this
+    /// tier have high recall but low precision.
+    pub const SUSPICIOUS: i32 = 10;
+    /// Red flags for unruly or potentially troublesome users.
+    pub const QUESTIONABLE: i32 = 60;
+    /// Marks of known offenders. False positives are far less likely at this tier.
+    pub const MALICIOUS: i32 = 200;
+    /// Applied to everyone while a guild is locked down.
+    pub const LOCKDOWN: i32 = 1000;
+    /// Explicitly overrides every other check. Reserved for a small, specific set of users.
+    pub const OVERRIDE: i32 = 10000;
+}
+
+/// The kind of evidence a signal is based on. Guilds can scale each category's weight, see
+/// `VerificationScoringConfig`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SignalCategory {
+    /// Properties of the account itself, like its age or Nitro status.
+    Account,
+    /// The user's current or past usernames.
+    Username,
+    /// The user's avatar.
+    Avatar,
+    /// Bans of the user from this or other servers.
+    BanHistory,
+    /// Recognition from Discord, like being staff or a partner.
+    Distinguished,
+    /// The guild is locked down. Cannot be scaled.
+    Lockdown,
+    /// Explicit overrides, like being a bot owner. Cannot be scaled.
+    Override,
+}
+
+impl SignalCategory {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Account => "Account",
+            Self::Username => "Username",
+            Self::Avatar => "Avatar",
+            Self::BanHistory => "Ban History",
+            Self::Distinguished => "Distinguished",
+            Self::Lockdown => "Lockdown",
+            Self::Override => "Override",
+        }
+    }
+
+    /// Gets the multiplier for the category's weights, as a percentage.
+    fn multiplier(&self, config: &VerificationScoringConfig) -> u32 {
+        match self {
+            Self::Account => config.get_account_weight(),
+            Self::Username => config.get_username_weight(),
+            Self::Avatar => config.get_avatar_weight(),
+            Self::BanHistory => config.get_ban_history_weight(),
+            Self::Distinguished => config.get_distinguished_weight(),
+            Self::Lockdown | Self::Override => 100,
+        }
+    }
+}
+
+/// A single piece of evidence for or against verifying a user. Positive weights count towards
+/// approval, negative weights towards rejection.
 #[derive(Debug, Clone)]
-pub enum VerificationReason {
-    Approval(String),
-    Rejection(String),
+pub struct VerificationSignal {
+    pub category: SignalCategory,
+    pub weight: i32,
+    pub reason: String,
 }
 
-impl VerificationReason {
+impl VerificationSignal {
+    pub fn approval(category: SignalCategory, weight: i32, reason: impl Into<String>) -> Self {
+        Self {
+            category,
+            weight: weight.abs(),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn rejection(category: SignalCategory, weight: i32, reason: impl Into<String>) -> Self {
+        Self {
+            category,
+            weight: -weight.abs(),
+            reason: reason.into(),
+        }
+    }
+
     pub fn is_approval(&self) -> bool {
-        self.approval_reason().is_some()
+        self.weight > 0
     }
 
     pub fn is_rejection(&self) -> bool {
-        self.rejection_reason().is_some()
+        self.weight < 0
     }
+}
 
-    pub fn approval_reason(&self) -> Option<&str> {
-        match self {
-            VerificationReason::Approval(reason) => Some(reason.as_str()),
-            _ => None,
+/// A signal with its weight scaled by the guild's configuration.
+#[derive(Debug, Clone)]
+pub struct WeightedSignal {
+    pub signal: VerificationSignal,
+    pub weight: i64,
+}
+
+/// The outcome of weighing every signal gathered about a user.
+#[derive(Debug, Clone)]
+pub struct VerificationDecision {
+    pub approved: bool,
+    pub score: i64,
+    pub threshold: i64,
+    pub signals: Vec<WeightedSignal>,
+}
+
+impl VerificationDecision {
+    pub fn new(signals: &[VerificationSignal], config: &VerificationScoringConfig) -> Self {
+        let signals: Vec<WeightedSignal> = signals
+            .iter()
+            .map(|signal| WeightedSignal {
+                weight: signal.weight as i64 * signal.category.multiplier(config) as i64 / 100,
+                signal: signal.clone(),
+            })
+            .collect();
+        let score = signals.iter().map(|s| s.weight).sum();
+        let threshold = config.get_approval_threshold() as i64;
+        Self {
+            approved: score >= threshold,
+            score,
+            threshold,
+            signals,
         }
     }
 
-    pub fn rejection_reason(&self) -> Option<&str> {
-        match self {
-            VerificationReason::Rejection(reason) => Some(reason.as_str()),
-            _ => None,
+    /// Lists every signal that affected the score, one line each, with its weight and category.
+    pub fn reasons(&self) -> Vec<String> {
+        self.signals
+            .iter()
+            .filter(|s| s.weight != 0)
+            .map(|signal| {
+                format!(
+                    "{:+} ({}): {}",
+                    signal.weight,
+                    signal.signal.category.name(),
+                    signal.signal.reason
+                )
+            })
+            .collect()
+    }
+
+    /// Lists every signal that contributed to the decision and its weight, followed by the
+    /// final score.
+    pub fn explain(&self) -> String {
+        let mut explanation = String::new();
+        for reason in self.reasons() {
+            writeln!(explanation, "{}", reason).unwrap();
         }
+        write!(
+            explanation,
+            "{}: score {} against a threshold of {}.",
+            if self.approved {
+                "Approved"
+            } else {
+                "Rejected"
+            },
+            self.score,
+            self.threshold
+        )
+        .unwrap();
+        explanation
     }
 }
 
 pub struct VerificationContext {
     member: Member,
-    reasons: Vec<VerificationReason>,
+    signals: Vec<VerificationSignal>,
+    dry_run: bool,
 }
 
 impl VerificationContext {
     pub fn new(member: Member) -> Self {
         Self {
             member,
-            reasons: Vec::new(),
+            signals: Vec::new(),
+            dry_run: false,
+        }
+    }
+
+    /// Creates a context for checking how a member would be verified, without the run being
+    /// observable. Verifiers must not record anything about the member while in a dry run.
+    pub fn dry_run(member: Member) -> Self {
+        Self {
+            dry_run: true,
+            ..Self::new(member)
         }
     }
 
@@ -47,29 +201,130 @@ impl VerificationContext {
         &self.member
     }
 
-    pub fn add_approval_reason(&mut self, reason: impl Into<String>) {
-        self.reasons
-            .push(VerificationReason::Approval(reason.into()));
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
     }
 
-    pub fn add_rejection_reason(&mut self, reason: impl Into<String>) {
-        self.reasons
-            .push(VerificationReason::Rejection(reason.into()));
+    pub fn approve(&mut self, category: SignalCategory, weight: i32, reason: impl Into<String>) {
+        self.add_signal(VerificationSignal::approval(category, weight, reason));
     }
 
-    pub fn add_reason(&mut self, reason: VerificationReason) {
-        self.reasons.push(reason);
+    pub fn reject(&mut self, category: SignalCategory, weight: i32, reason: impl Into<String>) {
+        self.add_signal(VerificationSignal::rejection(category, weight, reason));
     }
 
-    pub fn is_approved(&self) -> bool {
-        self.reasons.last().map(|r| r.is_approval()).unwrap_or(true)
+    pub fn add_signal(&mut self, signal: VerificationSignal) {
+        self.signals.push(signal);
+    }
+
+    pub fn signals(&self) -> &[VerificationSignal] {
+        &self.signals
+    }
+
+    /// Weighs every signal gathered so far. The result does not depend on the order the
+    /// signals were added in.
+    pub fn decide(&self, config: &VerificationScoringConfig) -> VerificationDecision {
+        VerificationDecision::new(&self.signals, config)
     }
 
     pub fn approval_reasons(&self) -> impl Iterator<Item = &str> {
-        self.reasons.iter().filter_map(|r| r.approval_reason())
+        self.signals
+            .iter()
+            .filter(|s| s.is_approval())
+            .map(|s| s.reason.as_str())
     }
 
     pub fn rejection_reasons(&self) -> impl Iterator<Item = &str> {
-        self.reasons.iter().filter_map(|r| r.approval_reason())
+        self.signals
+            .iter()
+            .filter(|s| s.is_rejection())
+            .map(|s| s.reason.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decide(signals: &[VerificationSignal]) -> VerificationDecision {
+        VerificationDecision::new(signals, &VerificationScoringConfig::new())
+    }
+
+    #[test]
+    fn test_no_signals_are_approved() {
+        let decision = decide(&[]);
+        assert!(decision.approved);
+        assert_eq!(decision.score, 0);
+    }
+
+    #[test]
+    fn test_decision_does_not_depend_on_order() {
+        let nitro = VerificationSignal::approval(SignalCategory::Account, 50, "Nitro");
+        let avatar = VerificationSignal::rejection(
+            SignalCategory::Account,
+            weights::SUSPICIOUS,
+            "No avatar",
+        );
+        let banned =
+            VerificationSignal::rejection(SignalCategory::BanHistory, weights::MALICIOUS, "Banned");
+
+        let forward = decide(&[nitro.clone(), avatar.clone(), banned.clone()]);
+        let backward = decide(&[banned, avatar, nitro]);
+        assert!(!forward.approved);
+        assert!(!backward.approved);
+        assert_eq!(forward.score, backward.score);
+        assert_eq!(
+            forward.score,
+            50 - weights::SUSPICIOUS as i64 - weights::MALICIOUS as i64
+        );
+    }
+
+    #[test]
+    fn test_overrides_outweigh_rejections() {
+        let decision = decide(&[
+            VerificationSignal::approval(SignalCategory::Override, weights::OVERRIDE, "Owner"),
+            VerificationSignal::rejection(SignalCategory::Lockdown, weights::LOCKDOWN, "Lockdown"),
+            VerificationSignal::rejection(SignalCategory::BanHistory, weights::MALICIOUS, "Banned"),
+        ]);
+        assert!(decision.approved);
+    }
+
+    #[test]
+    fn test_scoring_config() {
+        let signals = [VerificationSignal::rejection(
+            SignalCategory::Account,
+            weights::SUSPICIOUS,
+            "No avatar",
+        )];
+        assert!(!decide(&signals).approved);
+
+        let mut config = VerificationScoringConfig::new();
+        config.set_account_weight(0);
+        let decision = VerificationDecision::new(&signals, &config);
+        assert!(decision.approved);
+        assert_eq!(decision.score, 0);
+
+        let mut config = VerificationScoringConfig::new();
+        config.set_approval_threshold(-weights::QUESTIONABLE);
+        assert!(VerificationDecision::new(&signals, &config).approved);
+
+        let mut config = VerificationScoringConfig::new();
+        config.set_account_weight(250);
+        let decision = VerificationDecision::new(&signals, &config);
+        assert_eq!(decision.score, -25);
+    }
+
+    #[test]
+    fn test_explain_lists_signals() {
+        let decision = decide(&[
+            VerificationSignal::approval(SignalCategory::Account, 50, "Has Nitro."),
+            VerificationSignal::rejection(SignalCategory::Username, 60, "Offensive username."),
+        ]);
+        assert_eq!(
+            decision.explain(),
+            "+50 (Account): Has Nitro.\n\
+             -60 (Username): Offensive username.\n\
+             Rejected: score -10 against a threshold of 0."
+        );
     }
 }
@@ -0,0 +1,49 @@
+use crate::builder::{self, VerifierResources};
+use crate::context::{VerificationContext, VerificationDecision};
+use crate::Verifier;
+use anyhow::Result;
+use hourai::models::guild::Member;
+use hourai::models::id::{GuildId, UserId};
+use hourai::proto::guild_configs::VerificationConfig;
+
+/// Gets the member to check verification for. Users that are not in the guild are checked as if
+/// they had just joined it. Returns None if the user does not exist.
+pub async fn fetch_member(
+    http: &hourai::http::Client,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<Option<Member>> {
+    if let Some(member) = http.guild_member(guild_id, user_id).await? {
+        return Ok(Some(member));
+    }
+    Ok(http.user(user_id).await?.map(|user| Member {
+        guild_id,
+        user,
+        nick: None,
+        pending: true,
+        premium_since: None,
+        roles: Vec::new(),
+        joined_at: None,
+
+        // Unknown/dummy fields.
+        hoisted_role: None,
+        deaf: false,
+        mute: false,
+    }))
+}
+
+/// Runs a guild's verifiers against a member and weighs the results, without verifying the
+/// member or recording anything about them. Returns None if verification is disabled.
+pub async fn run(
+    config: &VerificationConfig,
+    resources: &VerifierResources,
+    member: Member,
+) -> Result<Option<VerificationDecision>> {
+    if !config.get_enabled() {
+        return Ok(None);
+    }
+    let verifiers = builder::build(config, resources)?;
+    let mut ctx = VerificationContext::dry_run(member);
+    verifiers.verify(&mut ctx).await?;
+    Ok(Some(ctx.decide(config.get_scoring())))
+}
@@ -2,10 +2,16 @@
 extern crate lazy_static;
 
 mod approvers;
-mod context;
+pub mod avatar;
+pub mod builder;
+pub mod context;
+pub mod dry_run;
+pub mod lists;
+pub mod normalize;
 mod rejectors;
+pub mod user_bot;
 
-use self::context::VerificationContext;
+use self::context::{SignalCategory, VerificationContext, VerificationSignal};
 use anyhow::Result;
 use async_trait::async_trait;
 
@@ -26,39 +32,44 @@ impl Verifier for Vec<BoxedVerifier> {
     }
 }
 
+/// Emits a fixed signal for every user that matches a predicate.
 pub struct GenericVerifier {
-    pub reason: context::VerificationReason,
+    pub signal: VerificationSignal,
     pub pred: Box<dyn Fn(&VerificationContext) -> Result<bool> + Sync + 'static>,
 }
 
 impl GenericVerifier {
     pub fn new_approver<T: Fn(&VerificationContext) -> Result<bool> + Sync + 'static>(
+        category: SignalCategory,
+        weight: i32,
         reason: impl Into<String>,
         approver: T,
     ) -> BoxedVerifier {
         Self::new(
-            context::VerificationReason::Approval(reason.into()),
+            VerificationSignal::approval(category, weight, reason),
             approver,
         )
     }
 
     pub fn new_rejector<T: Fn(&VerificationContext) -> Result<bool> + Sync + 'static>(
+        category: SignalCategory,
+        weight: i32,
         reason: impl Into<String>,
-        approver: T,
+        rejector: T,
     ) -> BoxedVerifier {
         Self::new(
-            context::VerificationReason::Rejection(reason.into()),
-            approver,
+            VerificationSignal::rejection(category, weight, reason),
+            rejector,
         )
     }
 
     fn new<T: Fn(&VerificationContext) -> Result<bool> + Sync + 'static>(
-        reason: context::VerificationReason,
-        approver: T,
+        signal: VerificationSignal,
+        pred: T,
     ) -> BoxedVerifier {
         Box::new(GenericVerifier {
-            reason,
-            pred: Box::new(approver),
+            signal,
+            pred: Box::new(pred),
         })
     }
 }
@@ -68,7 +79,7 @@ impl Verifier for GenericVerifier {
     async fn verify(&self, ctx: &mut context::VerificationContext) -> Result<()> {
         let pred = &self.pred;
         if pred(ctx)? {
-            ctx.add_reason(self.reason.clone());
+            ctx.add_signal(self.signal.clone());
         }
         Ok(())
     }
@@ -0,0 +1,346 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{error, info};
+
+/// The globally managed word lists. Each is loaded from a JSON array of strings in the list
+/// directory (see `config.list_directory`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ListKind {
+    UserBotNames,
+    /// Names that are entirely a match, i.e. only digits. See `is_full_match`.
+    UserBotNamesFullMatch,
+    SexualUsernames,
+    OffensiveUsernames,
+}
+
+impl ListKind {
+    pub const ALL: [ListKind; 4] = [
+        Self::UserBotNames,
+        Self::UserBotNamesFullMatch,
+        Self::SexualUsernames,
+        Self::OffensiveUsernames,
+    ];
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Self::UserBotNames => "user_bot_names.json",
+            Self::UserBotNamesFullMatch => "user_bot_names_fullmatch.json",
+            Self::SexualUsernames => "sexually_inappropriate_usernames.json",
+            Self::OffensiveUsernames => "offensive_usernames.json",
+        }
+    }
+
+    /// If true, the list's entries must match the whole text instead of any part of it.
+    pub fn is_full_match(&self) -> bool {
+        matches!(self, Self::UserBotNamesFullMatch)
+    }
+}
+
+/// Loosens a pattern to also match repeated characters, i.e. "cat" also matches "caaat".
+pub fn generalize(pattern: &str) -> String {
+    pattern
+        .chars()
+        .flat_map(|ch| {
+            if ch.is_alphanumeric() {
+                vec![ch, '+']
+            } else {
+                vec![ch]
+            }
+        })
+        .collect()
+}
+
+/// A compiled snapshot of a word list.
+#[derive(Debug, Default)]
+pub struct WordList {
+    version: u64,
+    entries: Vec<(String, Regex)>,
+}
+
+impl WordList {
+    /// Compiles a list's entries. Each entry is a regex, and is generalized before being
+    /// compiled, see `generalize`. If `full_match` is set, entries are instead compiled as is,
+    /// and only match whole texts.
+    pub fn compile(version: u64, entries: Vec<String>, full_match: bool) -> Result<Self> {
+        let entries = entries
+            .into_iter()
+            .map(|entry| {
+                let pattern = if full_match {
+                    format!("^(?:{})$", entry)
+                } else {
+                    generalize(&entry)
+                };
+                let regex = Regex::new(&pattern)
+                    .with_context(|| format!("Invalid list entry: {}", entry))?;
+                Ok((entry, regex))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { version, entries })
+    }
+
+    /// The number of times the list has been loaded. Starts at 1, 0 if the list's file does not
+    /// exist.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[(String, Regex)] {
+        &self.entries
+    }
+
+    /// Gets every entry that matches the text.
+    pub fn matches<'a>(&'a self, text: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(_, regex)| regex.is_match(text))
+            .map(|(entry, _)| entry.as_str())
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.matches(text).next().is_some()
+    }
+}
+
+struct LoadedList {
+    list: Arc<WordList>,
+    /// The contents of the file the list was loaded from, used to check for changes.
+    source: Option<String>,
+}
+
+struct ListState {
+    directory: PathBuf,
+    lists: RwLock<HashMap<ListKind, LoadedList>>,
+}
+
+/// The word lists loaded from a directory. Lists are reloaded when their files change, and
+/// clones share the same lists.
+#[derive(Clone)]
+pub struct WordLists(Arc<ListState>);
+
+impl WordLists {
+    /// Loads every list from a directory. Missing files are loaded as empty lists.
+    pub fn load(directory: impl AsRef<Path>) -> Result<Self> {
+        let lists = Self(Arc::new(ListState {
+            directory: directory.as_ref().to_owned(),
+            lists: RwLock::new(HashMap::new()),
+        }));
+        lists.reload()?;
+        Ok(lists)
+    }
+
+    /// Creates empty lists that are never reloaded.
+    pub fn empty() -> Self {
+        Self(Arc::new(ListState {
+            directory: PathBuf::new(),
+            lists: RwLock::new(HashMap::new()),
+        }))
+    }
+
+    /// Gets the current version of a list.
+    pub fn get(&self, kind: ListKind) -> Arc<WordList> {
+        self.0
+            .lists
+            .read()
+            .unwrap()
+            .get(&kind)
+            .map(|loaded| loaded.list.clone())
+            .unwrap_or_default()
+    }
+
+    /// Reloads any list whose file has changed, and returns the lists that were reloaded. If a
+    /// file cannot be read or compiled, the previous version of the list is kept and the first
+    /// such error is returned after every other list is reloaded.
+    pub fn reload(&self) -> Result<Vec<ListKind>> {
+        let mut reloaded = Vec::new();
+        let mut first_error = None;
+        for kind in ListKind::ALL.iter().cloned() {
+            match self.reload_list(kind) {
+                Ok(true) => reloaded.push(kind),
+                Ok(false) => {}
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(reloaded),
+        }
+    }
+
+    fn reload_list(&self, kind: ListKind) -> Result<bool> {
+        let path = self.0.directory.join(kind.file_name());
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => Some(source),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()))
+            }
+        };
+
+        let version = match self.0.lists.read().unwrap().get(&kind) {
+            Some(current) if current.source == source => return Ok(false),
+            Some(current) => current.list.version() + 1,
+            None => 1,
+        };
+
+        let list = match &source {
+            Some(source) => {
+                let entries: Vec<String> = serde_json::from_str(source)
+                    .with_context(|| format!("Failed to parse {}", path.display()))?;
+                WordList::compile(version, entries, kind.is_full_match())
+                    .with_context(|| format!("Failed to compile {}", path.display()))?
+            }
+            None => WordList::default(),
+        };
+        let loaded = LoadedList {
+            list: Arc::new(list),
+            source,
+        };
+        self.0.lists.write().unwrap().insert(kind, loaded);
+        Ok(true)
+    }
+
+    /// Checks for changes to the lists' files every interval, forever.
+    pub async fn watch(self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            match self.reload() {
+                Ok(reloaded) if !reloaded.is_empty() => {
+                    info!("Reloaded word lists: {:?}", reloaded);
+                }
+                Ok(_) => {}
+                Err(err) => error!("Error while reloading word lists: {:?}", err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("hourai-lists-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn write(&self, kind: ListKind, contents: &str) {
+            std::fs::write(self.0.join(kind.file_name()), contents).unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_generalize() {
+        assert_eq!(generalize("cat"), "c+a+t+");
+        assert_eq!(generalize("discord\\.gg"), "d+i+s+c+o+r+d+\\.g+g+");
+    }
+
+    #[test]
+    fn test_word_list_matches() {
+        let list = WordList::compile(1, vec!["cat".to_owned(), "dog".to_owned()], false).unwrap();
+        assert!(list.is_match("caaat"));
+        assert!(list.is_match("hotdog"));
+        assert!(!list.is_match("bird"));
+        assert_eq!(
+            list.matches("catdog").collect::<Vec<_>>(),
+            vec!["cat", "dog"]
+        );
+    }
+
+    #[test]
+    fn test_word_list_full_match() {
+        let list =
+            WordList::compile(1, vec!["\\d+".to_owned(), "cat|dog".to_owned()], true).unwrap();
+        assert!(list.is_match("1234"));
+        assert!(list.is_match("dog"));
+        assert!(!list.is_match("user1234"));
+        assert!(!list.is_match("hotdog"));
+    }
+
+    #[test]
+    fn test_load_lists() {
+        let dir = TempDir::new("load");
+        dir.write(ListKind::OffensiveUsernames, r#"["cat"]"#);
+
+        let lists = WordLists::load(&dir.0).unwrap();
+        let offensive = lists.get(ListKind::OffensiveUsernames);
+        assert_eq!(offensive.version(), 1);
+        assert!(offensive.is_match("caaat"));
+
+        // Missing files are loaded as empty lists.
+        let full_match = lists.get(ListKind::UserBotNamesFullMatch);
+        assert!(full_match.is_empty());
+        assert!(!full_match.is_match("cat"));
+    }
+
+    #[test]
+    fn test_reload_lists() {
+        let dir = TempDir::new("reload");
+        dir.write(ListKind::SexualUsernames, r#"["cat"]"#);
+        let lists = WordLists::load(&dir.0).unwrap();
+        let shared = lists.clone();
+
+        // Nothing changed.
+        assert!(lists.reload().unwrap().is_empty());
+        assert_eq!(lists.get(ListKind::SexualUsernames).version(), 1);
+
+        dir.write(ListKind::SexualUsernames, r#"["dog"]"#);
+        dir.write(ListKind::OffensiveUsernames, r#"["bird"]"#);
+        let reloaded = lists.reload().unwrap();
+        assert_eq!(
+            reloaded,
+            vec![ListKind::SexualUsernames, ListKind::OffensiveUsernames]
+        );
+
+        let sexual = shared.get(ListKind::SexualUsernames);
+        assert_eq!(sexual.version(), 2);
+        assert!(sexual.is_match("dog"));
+        assert!(!sexual.is_match("cat"));
+        assert!(shared.get(ListKind::OffensiveUsernames).is_match("bird"));
+    }
+
+    #[test]
+    fn test_invalid_lists_keep_previous_version() {
+        let dir = TempDir::new("invalid");
+        dir.write(ListKind::UserBotNames, r#"["cat"]"#);
+        let lists = WordLists::load(&dir.0).unwrap();
+
+        dir.write(ListKind::UserBotNames, r#"["(unclosed"]"#);
+        assert!(lists.reload().is_err());
+        dir.write(ListKind::UserBotNames, "not json");
+        assert!(lists.reload().is_err());
+
+        let list = lists.get(ListKind::UserBotNames);
+        assert_eq!(list.version(), 1);
+        assert!(list.is_match("cat"));
+    }
+
+    #[test]
+    fn test_example_lists_compile() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../config-example/lists");
+        let lists = WordLists::load(dir).unwrap();
+        for kind in ListKind::ALL.iter() {
+            assert!(!lists.get(*kind).is_empty(), "{:?} is empty", kind);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::Result;
+use hourai::models::UserLike;
+use hourai_sql::{Member, SqlPool, Username};
+use unicode_normalization::UnicodeNormalization;
+
+/// Checks if a character is invisible when rendered, i.e. zero-width spaces and joiners, bidi
+/// controls, and variation selectors. Follows the Default_Ignorable_Code_Point property, plus
+/// blank characters that render as empty space, like the Hangul fillers.
+pub fn is_invisible(ch: char) -> bool {
+    matches!(ch,
+        '\u{00AD}'
+        | '\u{034F}'
+        | '\u{061C}'
+        | '\u{115F}'..='\u{1160}'
+        | '\u{17B4}'..='\u{17B5}'
+        | '\u{180B}'..='\u{180F}'
+        | '\u{200B}'..='\u{200F}'
+        | '\u{202A}'..='\u{202E}'
+        | '\u{2060}'..='\u{206F}'
+        | '\u{2800}'
+        | '\u{3164}'
+        | '\u{FE00}'..='\u{FE0F}'
+        | '\u{FEFF}'
+        | '\u{FFA0}'
+        | '\u{FFF0}'..='\u{FFF8}'
+        | '\u{1BCA0}'..='\u{1BCA3}'
+        | '\u{1D173}'..='\u{1D17A}'
+        | '\u{E0000}'..='\u{E0FFF}')
+}
+
+/// Folds the digits and symbols commonly used in place of letters. Applied after confusables
+/// are mapped, which already folds "0" into "o" and "1" into "l".
+fn fold_leetspeak(ch: char) -> char {
+    match ch {
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        '8' => 'b',
+        '9' => 'g',
+        '!' => 'i',
+        _ => ch,
+    }
+}
+
+/// Normalizes a name so that names that look alike compare equal, i.e. "Ꭰiscord",
+/// "d\u{200B}iscord", and "DISC0RD" all become "discord".
+///
+/// Invisible characters are removed, the name is NFKC normalized, and then reduced to its
+/// confusable skeleton as defined by Unicode TR39. Case and common leetspeak are folded, and
+/// whitespace is collapsed.
+///
+/// Stored names are normalized with the same function (see `Username::normalized_name` and
+/// `Member::normalized_nickname`), so changes to this must be followed by clearing the stored forms for them to be renormalized.
+pub fn name(name: &str) -> String {
+    // ASCII letters are lowercased before finding the skeleton, as TR39 considers some
+    // uppercase letters confusable with other lowercase ones (i.e. "I" with "l"). Other scripts
+    // are left as is, their uppercase forms often have confusables when their lowercase forms do
+    // not (i.e. Cherokee "Ꭰ" and "D").
+    let visible: String = name
+        .chars()
+        .filter(|ch| !is_invisible(*ch))
+        .nfkc()
+        .map(|ch| ch.to_ascii_lowercase())
+        .collect();
+    let folded: String = unicode_security::skeleton(&visible)
+        .flat_map(char::to_lowercase)
+        .map(fold_leetspeak)
+        .collect();
+    folded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Creates a `Username` for logging a user's current name, with its normalized form set.
+pub fn username(user: &impl UserLike) -> Username {
+    let mut username = Username::new(user);
+    username.normalized_name = Some(name(&username.name));
+    username
+}
+
+/// Creates a `Member` for logging a member, with the normalized form of their nickname set.
+pub fn member<'a, T>(member: &'a T) -> Member
+where
+    Member: From<&'a T>,
+{
+    let mut member = Member::from(member);
+    member.normalized_nickname = member.nickname.as_deref().map(name);
+    member
+}
+
+/// Normalizes up to `limit` distinct stored names that were logged before they were normalized
+/// on write. Returns the number of names normalized, zero once every name is.
+pub async fn normalize_stored_usernames(sql: &SqlPool, limit: i64) -> Result<usize> {
+    let names: Vec<(String,)> = Username::fetch_unnormalized(limit).fetch_all(sql).await?;
+    let normalized: Vec<(String, String)> = names
+        .into_iter()
+        .map(|(raw,)| {
+            let normalized = name(&raw);
+            (raw, normalized)
+        })
+        .collect();
+    let count = normalized.len();
+    if count > 0 {
+        Username::bulk_set_normalized(normalized)
+            .execute(sql)
+            .await?;
+    }
+    Ok(count)
+}
+
+/// Like `normalize_stored_usernames`, but for members' nicknames.
+pub async fn normalize_stored_nicknames(sql: &SqlPool, limit: i64) -> Result<usize> {
+    let nicknames: Vec<(String,)> = Member::fetch_unnormalized_nicknames(limit)
+        .fetch_all(sql)
+        .await?;
+    let normalized: Vec<(String, String)> = nicknames
+        .into_iter()
+        .map(|(raw,)| {
+            let normalized = name(&raw);
+            (raw, normalized)
+        })
+        .collect();
+    let count = normalized.len();
+    if count > 0 {
+        Member::bulk_set_normalized_nicknames(normalized)
+            .execute(sql)
+            .await?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plain_names_are_lowercased() {
+        assert_eq!(name("Hourai"), "hourai");
+        assert_eq!(name("discord"), "discord");
+        assert_eq!(name("DISCORD"), "discord");
+    }
+
+    #[test]
+    fn test_invisible_characters_are_removed() {
+        assert_eq!(name("d\u{200B}isc\u{200D}ord"), "discord");
+        assert_eq!(name("\u{FEFF}discord\u{E0041}"), "discord");
+        assert_eq!(name("\u{3164}"), "");
+    }
+
+    #[test]
+    fn test_confusables_are_folded() {
+        // Cherokee, Cyrillic, and fullwidth letters.
+        assert_eq!(name("\u{13A0}iscord"), "discord");
+        assert_eq!(name("disc\u{043E}rd"), "discord");
+        assert_eq!(name("ｄｉｓｃｏｒｄ"), "discord");
+        assert_eq!(name("𝐝𝐢𝐬𝐜𝐨𝐫𝐝"), "discord");
+    }
+
+    #[test]
+    fn test_leetspeak_is_folded() {
+        assert_eq!(name("D1SC0RD"), name("dlscord"));
+        assert_eq!(name("h0ura!"), "hourai");
+        assert_eq!(name("$pamm3r"), name("spammer"));
+    }
+
+    #[test]
+    fn test_whitespace_is_collapsed() {
+        assert_eq!(name("  Deleted \u{3000} User  "), "deleted user");
+    }
+
+    #[test]
+    fn test_normalization_is_idempotent() {
+        for input in &[
+            "Ꭰiscord",
+            "D1SC0RD",
+            "Deleted User 1a2b3c4d",
+            "ｈｏｕｒａｉ",
+        ] {
+            let once = name(input);
+            assert_eq!(name(&once), once);
+        }
+    }
+}
@@ -1,11 +1,17 @@
+use crate::avatar::{self, AvatarFetcher};
+use crate::context::weights;
+use crate::lists::{ListKind, WordLists};
+use crate::normalize;
 use crate::{context, *};
 use async_trait::async_trait;
-use chrono::offset::Utc;
-use chrono::Duration;
-use dashmap::DashMap;
+use chrono::{offset::Utc, DateTime, Duration};
 use hourai::models::{user::User, Snowflake};
+use hourai::proto::util::FilterSettings;
 use hourai_sql::{Ban, SqlPool, Username, VerificationBan};
 use regex::Regex;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::warn;
 
 lazy_static! {
     static ref DELETED_USERNAME_MATCH: Regex = Regex::new("Deleted User [0-9a-fA-F]{8}").unwrap();
@@ -22,7 +28,9 @@ struct DeletedUserRejector(SqlPool);
 impl Verifier for DeletedUserRejector {
     async fn verify(&self, ctx: &mut context::VerificationContext) -> Result<()> {
         if is_user_deleted(&ctx.member().user) {
-            ctx.add_rejection_reason(
+            ctx.reject(
+                SignalCategory::Account,
+                weights::SUSPICIOUS,
                 "Deleted users cannot be active on Discord. User has been \
                 deleted by Discord of their own accord or for Trust and \
                 Safety reasons, or is faking account deletion.",
@@ -36,20 +44,31 @@ impl Verifier for DeletedUserRejector {
         for username in usernames {
             let name = username.name.as_str();
             let is_deleted = DELETED_USERNAME_MATCH.is_match(name);
-            if !is_deleted && LOOSE_DELETED_USERNAME_MATCH.is_match(name) {
-                ctx.add_rejection_reason(format!(
-                    "\"{}\" does not match Discord\'s deletion patterns. User may have \
+            // Discord's own pattern is matched exactly, but imitations of it may use confusable
+            // or invisible characters to avoid being matched.
+            let is_loose_match = LOOSE_DELETED_USERNAME_MATCH.is_match(&normalize::name(name));
+            if !is_deleted && is_loose_match {
+                ctx.reject(
+                    SignalCategory::Account,
+                    weights::SUSPICIOUS,
+                    format!(
+                        "\"{}\" does not match Discord\'s deletion patterns. User may have \
                              attemtped to fake account deletion.",
-                    name
-                ));
+                        name
+                    ),
+                );
             } else if is_deleted && username.discriminator.map(|d| d < 100).unwrap_or(false) {
-                ctx.add_rejection_reason(format!(
-                    "\"{}#{:04}\" has a unusual discriminator for a deleted user. These \
+                ctx.reject(
+                    SignalCategory::Account,
+                    weights::SUSPICIOUS,
+                    format!(
+                        "\"{}#{:04}\" has a unusual discriminator for a deleted user. These \
                              are randomly generated. User may have attemtped to fake account \
                              deletion.",
-                    name,
-                    username.discriminator.unwrap()
-                ));
+                        name,
+                        username.discriminator.unwrap()
+                    ),
+                );
             }
         }
 
@@ -93,7 +112,7 @@ impl Verifier for BannedUserRejector {
                 reason.push_str(" for the following reasons\n");
                 reason.push_str(list.as_str());
             }
-            ctx.add_rejection_reason(reason);
+            ctx.reject(SignalCategory::BanHistory, weights::MALICIOUS, reason);
         }
 
         Ok(())
@@ -105,49 +124,105 @@ struct BannedUsernameRejector(SqlPool);
 #[async_trait]
 impl Verifier for BannedUsernameRejector {
     async fn verify(&self, ctx: &mut context::VerificationContext) -> Result<()> {
-        let name_bans =
-            VerificationBan::fetch_by_name(ctx.member().guild_id, ctx.member().user.name.as_str())
-                .fetch_all(&self.0)
-                .await?;
-        for ban in name_bans {
-            let mut reason = format!(
-                "Exact username match with banned user: {}#{}",
-                ban.name, ban.discriminator
-            );
-            if let Some(ban_reason) = ban.reason {
-                reason.push_str(format!(" (Ban Reason: {})", ban_reason).as_str());
-            }
-            ctx.add_rejection_reason(reason);
+        let guild_id = ctx.member().guild_id;
+        let name = ctx.member().user.name.clone();
+
+        let exact_bans = VerificationBan::fetch_by_name(guild_id, name.as_str())
+            .fetch_all(&self.0)
+            .await?;
+        let mut matched = HashSet::new();
+        for ban in exact_bans {
+            matched.insert(ban.user_id);
+            let reason = ban_reason("Exact username match with banned user", &ban);
+            ctx.reject(SignalCategory::BanHistory, weights::MALICIOUS, reason);
         }
 
-        if ctx.member().user.avatar.is_none() {
+        let normalized = normalize::name(&name);
+        if normalized.is_empty() {
             return Ok(());
         }
+        let normalized_bans = VerificationBan::fetch_by_normalized_name(guild_id, normalized)
+            .fetch_all(&self.0)
+            .await?;
+        for ban in normalized_bans {
+            if matched.insert(ban.user_id) {
+                let reason = ban_reason("Username looks like banned user", &ban);
+                ctx.reject(SignalCategory::BanHistory, weights::MALICIOUS, reason);
+            }
+        }
 
-        let avatar_bans = VerificationBan::fetch_by_name(
-            ctx.member().guild_id,
-            ctx.member().user.avatar.as_ref().unwrap(),
-        )
-        .fetch_all(&self.0)
-        .await?;
-        for ban in avatar_bans {
-            let mut reason = format!(
-                "Exact avatar match with banned user: {}#{}",
-                ban.name, ban.discriminator
-            );
-            if let Some(ban_reason) = ban.reason {
-                reason.push_str(format!(" (Ban Reason: {})", ban_reason).as_str());
+        Ok(())
+    }
+}
+
+struct BannedAvatarRejector {
+    sql: SqlPool,
+    fetcher: Arc<dyn AvatarFetcher>,
+    max_distance: u32,
+}
+
+#[async_trait]
+impl Verifier for BannedAvatarRejector {
+    async fn verify(&self, ctx: &mut context::VerificationContext) -> Result<()> {
+        let avatar = match ctx.member().user.avatar.clone() {
+            Some(avatar) => avatar,
+            None => return Ok(()),
+        };
+        let guild_id = ctx.member().guild_id;
+
+        let exact_bans = VerificationBan::fetch_by_avatar(guild_id, avatar.as_str())
+            .fetch_all(&self.sql)
+            .await?;
+        let mut matched = HashSet::new();
+        for ban in exact_bans {
+            matched.insert(ban.user_id);
+            let reason = ban_reason("Exact avatar match", &ban);
+            ctx.reject(SignalCategory::Avatar, weights::MALICIOUS, reason);
+        }
+
+        let user_id = ctx.member().user.id;
+        let fetcher = self.fetcher.as_ref();
+        let hash = if ctx.is_dry_run() {
+            avatar::peek_avatar_hash(&self.sql, fetcher, user_id, &avatar).await
+        } else {
+            avatar::hash_avatar(&self.sql, fetcher, user_id, &avatar).await
+        };
+        // A failed download or an undecodable avatar should not stop the rest of the pipeline
+        // from running, only this signal.
+        let hash = match hash {
+            Ok(hash) => hash,
+            Err(err) => {
+                warn!("Failed to hash the avatar of {}: {:?}", user_id, err);
+                return Ok(());
+            }
+        };
+        let similar_bans = VerificationBan::fetch_by_avatar_hash(guild_id, hash, self.max_distance)
+            .fetch_all(&self.sql)
+            .await?;
+        for ban in similar_bans {
+            if matched.insert(ban.user_id) {
+                let reason = ban_reason("Avatar similar to banned user", &ban);
+                ctx.reject(SignalCategory::Avatar, weights::MALICIOUS, reason);
             }
-            ctx.add_rejection_reason(reason);
         }
 
         Ok(())
     }
 }
 
+fn ban_reason(prefix: &str, ban: &VerificationBan) -> String {
+    let mut reason = format!("{}: {}#{}", prefix, ban.name, ban.discriminator);
+    if let Some(ban_reason) = &ban.reason {
+        reason.push_str(format!(" (Ban Reason: {})", ban_reason).as_str());
+    }
+    reason
+}
+
 #[async_trait]
 pub trait StringMatchRejector: Sync {
     type Key;
+    /// The weight of the signal emitted for each match.
+    fn weight(&self) -> i32;
     fn regexes(&self) -> Vec<(Self::Key, Regex)>;
     async fn criteria(&self, ctx: &context::VerificationContext) -> Result<Vec<String>>;
     fn reason(&self, key: &Self::Key, matched: &str) -> String;
@@ -162,7 +237,7 @@ impl<T: StringMatchRejector> Verifier for T {
             for (key, regex) in &regexes {
                 if regex.find(check.as_str()).is_some() {
                     let reason = self.reason(&key, check.as_str());
-                    ctx.add_rejection_reason(reason);
+                    ctx.reject(SignalCategory::Username, self.weight(), reason);
                 }
             }
         }
@@ -171,42 +246,32 @@ impl<T: StringMatchRejector> Verifier for T {
     }
 }
 
+/// Rejects users whose recent usernames match an entry of a managed word list. The latest
+/// version of the list is used, so changes to it apply without rebuilding the rejector. Names
+/// are checked both as is and normalized, see `normalize::name`.
 pub struct UsernameMatchRejector {
     sql: SqlPool,
-    matches: DashMap<String, Regex>,
+    lists: WordLists,
+    kinds: Vec<ListKind>,
     prefix: String,
+    weight: i32,
 }
 
 impl UsernameMatchRejector {
-    pub fn new(sql: SqlPool, prefix: impl Into<String>, matches: Vec<String>) -> Result<Self> {
-        Ok(Self {
+    pub fn new(
+        sql: SqlPool,
+        prefix: impl Into<String>,
+        weight: i32,
+        lists: WordLists,
+        kinds: &[ListKind],
+    ) -> Self {
+        Self {
             sql,
-            matches: Self::compile(matches)?,
+            lists,
+            kinds: kinds.to_vec(),
             prefix: prefix.into(),
-        })
-    }
-
-    fn compile(base: Vec<String>) -> Result<DashMap<String, Regex>> {
-        let matches: DashMap<String, Regex> = DashMap::new();
-        for input in base {
-            matches.insert(
-                input.clone(),
-                Regex::new(Self::generalize_filter(input).as_str())?,
-            );
+            weight,
         }
-        Ok(matches)
-    }
-
-    fn generalize_filter(base: String) -> String {
-        base.chars()
-            .flat_map(|ch| {
-                if ch.is_alphanumeric() {
-                    vec![ch, '+']
-                } else {
-                    vec![ch]
-                }
-            })
-            .collect()
     }
 }
 
@@ -214,20 +279,31 @@ impl UsernameMatchRejector {
 impl StringMatchRejector for UsernameMatchRejector {
     type Key = String;
 
+    fn weight(&self) -> i32 {
+        self.weight
+    }
+
     fn regexes(&self) -> Vec<(Self::Key, Regex)> {
-        self.matches
+        self.kinds
             .iter()
-            .map(|kv| (kv.key().clone(), kv.value().clone()))
+            .flat_map(|kind| self.lists.get(*kind).entries().to_vec())
             .collect()
     }
 
     async fn criteria(&self, ctx: &context::VerificationContext) -> Result<Vec<String>> {
-        Ok(Username::fetch(ctx.member().user.id, Some(20))
+        let usernames = Username::fetch(ctx.member().user.id, Some(20))
             .fetch_all(&self.sql)
-            .await?
-            .into_iter()
-            .map(|un| un.name)
-            .collect())
+            .await?;
+        let mut criteria = Vec::with_capacity(usernames.len());
+        for username in usernames {
+            let normalized = normalize::name(&username.name);
+            let differs = normalized != username.name;
+            criteria.push(username.name);
+            if differs {
+                criteria.push(normalized);
+            }
+        }
+        Ok(criteria)
     }
 
     fn reason(&self, key: &Self::Key, matched: &str) -> String {
@@ -235,18 +311,75 @@ impl StringMatchRejector for UsernameMatchRejector {
     }
 }
 
+/// A compiled `FilterSettings`. Names are filtered if they match any pattern in the blacklist,
+/// unless they also match one in the whitelist.
+struct NameFilter {
+    blacklist: Vec<Regex>,
+    whitelist: Vec<Regex>,
+}
+
+impl NameFilter {
+    fn new(settings: &FilterSettings) -> Result<Self> {
+        let compile = |patterns: &[String]| -> Result<Vec<Regex>> {
+            Ok(patterns
+                .iter()
+                .map(|pattern| Regex::new(pattern))
+                .collect::<std::result::Result<_, _>>()?)
+        };
+        Ok(Self {
+            blacklist: compile(settings.get_blacklist())?,
+            whitelist: compile(settings.get_whitelist())?,
+        })
+    }
+
+    fn is_filtered(&self, name: &str) -> bool {
+        self.blacklist.iter().any(|regex| regex.is_match(name))
+            && !self.whitelist.iter().any(|regex| regex.is_match(name))
+    }
+}
+
+struct UsernameFilterRejector {
+    sql: SqlPool,
+    filter: NameFilter,
+}
+
+#[async_trait]
+impl Verifier for UsernameFilterRejector {
+    async fn verify(&self, ctx: &mut context::VerificationContext) -> Result<()> {
+        let usernames = Username::fetch(ctx.member().user.id, Some(20))
+            .fetch_all(&self.sql)
+            .await?;
+        for username in usernames {
+            if self.filter.is_filtered(username.name.as_str()) {
+                ctx.reject(
+                    SignalCategory::Username,
+                    weights::QUESTIONABLE,
+                    format!("Username matches the server's filter: {}", username.name),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
 pub(super) fn new_account(lookback: Duration) -> BoxedVerifier {
     let human_lookback = humantime::format_duration(lookback.to_std().unwrap());
     GenericVerifier::new_rejector(
+        SignalCategory::Account,
+        weights::SUSPICIOUS,
         format!("Account created less than {} ago.", human_lookback),
-        move |ctx| Ok(ctx.member().created_at() - Utc::now() < lookback),
+        move |ctx| Ok(Utc::now() - ctx.member().created_at() < lookback),
     )
 }
 
 pub(super) fn no_avatar() -> BoxedVerifier {
-    GenericVerifier::new_rejector("User has no avatar.", move |ctx| {
-        Ok(ctx.member().user.avatar.is_none())
-    })
+    GenericVerifier::new_rejector(
+        SignalCategory::Account,
+        weights::SUSPICIOUS,
+        "User has no avatar.",
+        move |ctx| Ok(ctx.member().user.avatar.is_none()),
+    )
 }
 
 pub(super) fn banned_user(sql: SqlPool, min_guild_size: u64) -> BoxedVerifier {
@@ -260,6 +393,85 @@ pub(super) fn banned_username(sql: SqlPool) -> BoxedVerifier {
     Box::new(BannedUsernameRejector(sql))
 }
 
+pub(super) fn banned_avatar(
+    sql: SqlPool,
+    fetcher: Arc<dyn AvatarFetcher>,
+    max_distance: u32,
+) -> BoxedVerifier {
+    Box::new(BannedAvatarRejector {
+        sql,
+        fetcher,
+        max_distance,
+    })
+}
+
 pub(super) fn deleted_user(sql: SqlPool) -> BoxedVerifier {
     Box::new(DeletedUserRejector(sql))
 }
+
+pub(super) fn lockdown(expiration: DateTime<Utc>) -> BoxedVerifier {
+    GenericVerifier::new_rejector(
+        SignalCategory::Lockdown,
+        weights::LOCKDOWN,
+        format!(
+            "Server is under lockdown until {}. All new joins must be manually verified.",
+            expiration.format("%Y-%m-%d %H:%M:%S UTC")
+        ),
+        move |_| Ok(Utc::now() < expiration),
+    )
+}
+
+pub(super) fn username_match(
+    sql: SqlPool,
+    prefix: &str,
+    weight: i32,
+    lists: WordLists,
+    kinds: &[ListKind],
+) -> BoxedVerifier {
+    Box::new(UsernameMatchRejector::new(
+        sql, prefix, weight, lists, kinds,
+    ))
+}
+
+pub(super) fn username_filter(sql: SqlPool, settings: &FilterSettings) -> Result<BoxedVerifier> {
+    Ok(Box::new(UsernameFilterRejector {
+        sql,
+        filter: NameFilter::new(settings)?,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filter(blacklist: &[&str], whitelist: &[&str]) -> NameFilter {
+        let mut settings = FilterSettings::new();
+        let to_vec =
+            |patterns: &[&str]| -> Vec<String> { patterns.iter().map(|s| s.to_string()).collect() };
+        settings.set_blacklist(to_vec(blacklist).into());
+        settings.set_whitelist(to_vec(whitelist).into());
+        NameFilter::new(&settings).unwrap()
+    }
+
+    #[test]
+    fn test_name_filter() {
+        let filter = filter(&["(?i)discord", "^free"], &["(?i)discord fan"]);
+        assert!(filter.is_filtered("Discord Staff"));
+        assert!(filter.is_filtered("free nitro"));
+        assert!(!filter.is_filtered("not free"));
+        assert!(!filter.is_filtered("Discord Fan"));
+        assert!(!filter.is_filtered("Hourai"));
+    }
+
+    #[test]
+    fn test_empty_name_filter() {
+        assert!(!filter(&[], &[]).is_filtered("anything"));
+    }
+
+    #[test]
+    fn test_invalid_name_filter() {
+        let mut settings = FilterSettings::new();
+        settings.set_blacklist(vec!["(unclosed".to_owned()].into());
+        assert!(NameFilter::new(&settings).is_err());
+    }
+}
@@ -0,0 +1,382 @@
+use crate::context::{self, weights, SignalCategory};
+use crate::Verifier;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{offset::Utc, DateTime, Duration};
+use hourai::models::{id::GuildId, Snowflake};
+use hourai_sql::{SqlPool, Username};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Users are flagged once the heuristics are at least this confident that they are a user bot.
+const MIN_CONFIDENCE: f64 = 0.5;
+
+/// How far back joins are considered when looking for bursts of similar names.
+const BURST_WINDOW: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// The number of recent joins with similar names, not counting the user, that counts as a
+/// burst.
+const BURST_SIZE: usize = 3;
+
+/// The most recent joins kept per guild.
+const MAX_TRACKED_JOINS: usize = 1000;
+
+/// The facts about a user that the heuristics are based on.
+#[derive(Clone, Debug)]
+pub struct UserBotEvidence {
+    /// The time between the account's creation and it joining the guild.
+    pub account_age: Duration,
+    pub has_avatar: bool,
+    pub name: String,
+    /// The number of distinct usernames the user is known to have had.
+    pub known_usernames: usize,
+    /// The number of other users with similar names that recently joined the same guild.
+    pub similar_recent_joins: usize,
+}
+
+/// How likely it is that a user is a user bot, and why.
+#[derive(Clone, Debug, Default)]
+pub struct UserBotAssessment {
+    /// Between 0 and 1.
+    pub confidence: f64,
+    pub reasons: Vec<String>,
+}
+
+impl UserBotAssessment {
+    /// Adds evidence that is right with the given probability. Independent pieces of evidence
+    /// are combined with a noisy-OR, so that each one raises the confidence, but never past 1.
+    fn add(&mut self, probability: f64, reason: impl Into<String>) {
+        self.confidence = 1.0 - (1.0 - self.confidence) * (1.0 - probability);
+        self.reasons.push(reason.into());
+    }
+
+    pub fn is_likely(&self) -> bool {
+        self.confidence >= MIN_CONFIDENCE
+    }
+}
+
+/// Scores how likely a user is to be a user bot. No single heuristic is enough to reach
+/// `MIN_CONFIDENCE` on its own, except for a burst of similarly named joins.
+pub fn assess(evidence: &UserBotEvidence) -> UserBotAssessment {
+    let mut assessment = UserBotAssessment::default();
+
+    if evidence.account_age < Duration::hours(1) {
+        assessment.add(0.35, "Account joined within an hour of being created.");
+    } else if evidence.account_age < Duration::days(1) {
+        assessment.add(0.2, "Account joined within a day of being created.");
+    }
+
+    if !evidence.has_avatar {
+        assessment.add(0.15, "Account has the default avatar.");
+    }
+
+    let name = evidence.name.as_str();
+    let digits = longest_digit_run(name);
+    if digits >= 4 {
+        assessment.add(
+            0.25,
+            format!(
+                "Username has a run of {} digits, like generated names.",
+                digits
+            ),
+        );
+    } else if name.chars().count() >= 8 && char_entropy(name) >= 3.0 && !has_word_shape(name) {
+        assessment.add(0.25, "Username looks randomly generated.");
+    }
+
+    if evidence.known_usernames <= 1 {
+        assessment.add(0.1, "Account has no history of other usernames.");
+    }
+
+    if evidence.similar_recent_joins >= BURST_SIZE {
+        assessment.add(
+            0.6,
+            format!(
+                "{} other users with similar names joined in the last {} minutes.",
+                evidence.similar_recent_joins,
+                BURST_WINDOW.as_secs() / 60
+            ),
+        );
+    }
+
+    assessment
+}
+
+fn longest_digit_run(name: &str) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    for ch in name.chars() {
+        if ch.is_ascii_digit() {
+            current += 1;
+            longest = longest.max(current);
+        } else {
+            current = 0;
+        }
+    }
+    longest
+}
+
+/// The Shannon entropy of the name's characters, in bits per character.
+fn char_entropy(name: &str) -> f64 {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    let mut total = 0;
+    for ch in name.chars() {
+        *counts.entry(ch).or_default() += 1;
+        total += 1;
+    }
+    counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Checks if a name reads like words, rather than a random string of characters: i.e. it has
+/// spaces, or its letters are at least a quarter vowels.
+fn has_word_shape(name: &str) -> bool {
+    let letters: Vec<char> = name
+        .chars()
+        .filter(|c| c.is_alphabetic())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    let vowels = letters.iter().filter(|c| "aeiouy".contains(**c)).count();
+    name.contains(' ') || (!letters.is_empty() && vowels * 4 >= letters.len())
+}
+
+/// The time between an account's creation and it joining a guild. Members without a join time,
+/// i.e. users outside of the guild that are dry run, are treated as joining now.
+fn account_age(created_at: DateTime<Utc>, joined_at: Option<&str>, now: DateTime<Utc>) -> Duration {
+    let joined_at = joined_at
+        .and_then(|joined| joined.parse::<DateTime<Utc>>().ok())
+        .unwrap_or(now);
+    joined_at - created_at
+}
+
+/// Reduces a name to the part a batch of generated names would share, i.e. "CoolUser1234" and
+/// "cooluser_99" both become "cooluser".
+fn name_stem(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphabetic())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Tracks recent joins to each guild, to find bursts of users with similar names.
+#[derive(Clone, Default)]
+pub struct JoinTracker(Arc<Mutex<HashMap<GuildId, VecDeque<(Instant, String)>>>>);
+
+impl JoinTracker {
+    /// Records a join, and returns the number of other recent joins with a similar name.
+    pub fn record(&self, guild_id: GuildId, name: &str, now: Instant) -> usize {
+        let stem = name_stem(name);
+        let mut guilds = self.0.lock().unwrap();
+        let joins = guilds.entry(guild_id).or_default();
+        while let Some((time, _)) = joins.front() {
+            if now.duration_since(*time) > BURST_WINDOW || joins.len() >= MAX_TRACKED_JOINS {
+                joins.pop_front();
+            } else {
+                break;
+            }
+        }
+        let similar = Self::count_similar(joins, &stem, now);
+        joins.push_back((now, stem));
+        similar
+    }
+
+    /// Counts the recent joins with a name similar to the given one, without recording a join.
+    pub fn similar(&self, guild_id: GuildId, name: &str, now: Instant) -> usize {
+        let guilds = self.0.lock().unwrap();
+        guilds
+            .get(&guild_id)
+            .map(|joins| Self::count_similar(joins, &name_stem(name), now))
+            .unwrap_or(0)
+    }
+
+    fn count_similar(joins: &VecDeque<(Instant, String)>, stem: &str, now: Instant) -> usize {
+        // Names without letters would all be considered similar.
+        if stem.is_empty() {
+            return 0;
+        }
+        joins
+            .iter()
+            .filter(|(time, other)| now.duration_since(*time) <= BURST_WINDOW && other == stem)
+            .count()
+    }
+}
+
+struct UserBotRejector {
+    sql: SqlPool,
+    joins: JoinTracker,
+}
+
+#[async_trait]
+impl Verifier for UserBotRejector {
+    async fn verify(&self, ctx: &mut context::VerificationContext) -> Result<()> {
+        let member = ctx.member();
+        let known_usernames = Username::fetch(member.user.id, Some(20))
+            .fetch_all(&self.sql)
+            .await?
+            .len();
+        let evidence = UserBotEvidence {
+            account_age: account_age(member.created_at(), member.joined_at.as_deref(), Utc::now()),
+            has_avatar: member.user.avatar.is_some(),
+            name: member.user.name.clone(),
+            known_usernames,
+            similar_recent_joins: if ctx.is_dry_run() {
+                self.joins
+                    .similar(member.guild_id, &member.user.name, Instant::now())
+            } else {
+                self.joins
+                    .record(member.guild_id, &member.user.name, Instant::now())
+            },
+        };
+
+        let assessment = assess(&evidence);
+        if assessment.is_likely() {
+            // Scaled so that near certain assessments weigh as much as a questionable signal.
+            let weight = (assessment.confidence * weights::QUESTIONABLE as f64).round() as i32;
+            let reason = format!(
+                "Likely user bot ({:.0}% confidence): {}",
+                assessment.confidence * 100.0,
+                assessment.reasons.join(" ")
+            );
+            ctx.reject(SignalCategory::Account, weight, reason);
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn user_bot(sql: SqlPool, joins: JoinTracker) -> crate::BoxedVerifier {
+    Box::new(UserBotRejector { sql, joins })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn evidence(name: &str) -> UserBotEvidence {
+        UserBotEvidence {
+            account_age: Duration::days(365),
+            has_avatar: true,
+            name: name.to_owned(),
+            known_usernames: 5,
+            similar_recent_joins: 0,
+        }
+    }
+
+    #[test]
+    fn test_established_user_is_not_flagged() {
+        let assessment = assess(&evidence("Hourai"));
+        assert_eq!(assessment.confidence, 0.0);
+        assert!(assessment.reasons.is_empty());
+        assert!(!assessment.is_likely());
+    }
+
+    #[test]
+    fn test_fresh_account_with_generated_name_is_flagged() {
+        let assessment = assess(&UserBotEvidence {
+            account_age: Duration::minutes(5),
+            has_avatar: false,
+            known_usernames: 1,
+            ..evidence("user84729")
+        });
+        assert!(assessment.is_likely());
+        assert_eq!(assessment.reasons.len(), 4);
+        // 1 - 0.65 * 0.85 * 0.75 * 0.9
+        assert!((assessment.confidence - 0.627).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_single_heuristic_is_not_enough() {
+        let cases = vec![
+            UserBotEvidence {
+                account_age: Duration::minutes(5),
+                ..evidence("Hourai")
+            },
+            UserBotEvidence {
+                has_avatar: false,
+                ..evidence("Hourai")
+            },
+            evidence("xkqzjwvbtr"),
+            evidence("Hourai1234"),
+        ];
+        for case in cases {
+            let assessment = assess(&case);
+            assert_eq!(assessment.reasons.len(), 1, "{:?}", case);
+            assert!(!assessment.is_likely(), "{:?}", case);
+        }
+    }
+
+    #[test]
+    fn test_burst_joins_are_flagged() {
+        let assessment = assess(&UserBotEvidence {
+            similar_recent_joins: BURST_SIZE,
+            ..evidence("Hourai")
+        });
+        assert!(assessment.is_likely());
+    }
+
+    #[test]
+    fn test_generated_names() {
+        assert_eq!(longest_digit_run("a12b3456c"), 4);
+        assert_eq!(longest_digit_run("Hourai"), 0);
+        assert!(char_entropy("xkqzjwvbtr") > char_entropy("aaaaaaaaaa"));
+        assert!(has_word_shape("Touhou Project"));
+        assert!(has_word_shape("ReimuHakurei"));
+        assert!(!has_word_shape("xkqzjwvbtr"));
+    }
+
+    #[test]
+    fn test_account_age_is_measured_at_join() {
+        let created_at = "2021-05-01T00:00:00+00:00"
+            .parse::<DateTime<Utc>>()
+            .unwrap();
+        let now = created_at + Duration::days(30);
+        let joined_at = "2021-05-01T00:05:00.000000+00:00";
+        assert_eq!(
+            account_age(created_at, Some(joined_at), now),
+            Duration::minutes(5)
+        );
+        assert_eq!(account_age(created_at, None, now), Duration::days(30));
+        assert_eq!(
+            account_age(created_at, Some("not a timestamp"), now),
+            Duration::days(30)
+        );
+    }
+
+    #[test]
+    fn test_join_tracker() {
+        let tracker = JoinTracker::default();
+        let guild = GuildId(1);
+        let start = Instant::now();
+        assert_eq!(tracker.record(guild, "CoolUser1234", start), 0);
+        assert_eq!(tracker.record(guild, "cooluser_99", start), 1);
+        assert_eq!(tracker.record(guild, "Cool User", start), 2);
+        assert_eq!(tracker.record(guild, "Hourai", start), 0);
+        // Other guilds are tracked separately.
+        assert_eq!(tracker.record(GuildId(2), "CoolUser", start), 0);
+        // Names without letters are never similar.
+        assert_eq!(tracker.record(guild, "1234", start), 0);
+        assert_eq!(tracker.record(guild, "5678", start), 0);
+
+        // Joins outside of the window are forgotten.
+        let later = start + BURST_WINDOW + std::time::Duration::from_secs(1);
+        assert_eq!(tracker.similar(guild, "CoolUser", later), 0);
+        assert_eq!(tracker.record(guild, "CoolUser", later), 0);
+    }
+
+    #[test]
+    fn test_join_tracker_similar_does_not_record() {
+        let tracker = JoinTracker::default();
+        let guild = GuildId(1);
+        let start = Instant::now();
+        assert_eq!(tracker.similar(guild, "CoolUser1234", start), 0);
+        tracker.record(guild, "CoolUser1234", start);
+        assert_eq!(tracker.similar(guild, "cooluser_99", start), 1);
+        assert_eq!(tracker.similar(guild, "cooluser_99", start), 1);
+        assert_eq!(tracker.similar(GuildId(2), "cooluser_99", start), 0);
+    }
+}
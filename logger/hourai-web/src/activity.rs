@@ -0,0 +1,87 @@
+use crate::{auth::require_moderator, prelude::*, AppState};
+use actix_web::{get, http::StatusCode, web, HttpRequest};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hourai::models::id::GuildId;
+use hourai_sql::activity::{ActivityResolution, GuildActivity};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+struct ActivityQuery {
+    /// The start of the range, in seconds since the Unix epoch. Defaults to a week before `end`.
+    start: Option<i64>,
+    /// The end of the range, in seconds since the Unix epoch. Defaults to now.
+    end: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct GuildActivityResponse {
+    /// The width of each point's bucket, in seconds.
+    resolution: i64,
+    points: Vec<ActivityPoint>,
+}
+
+#[derive(Serialize)]
+struct ActivityPoint {
+    /// The start of the bucket, in seconds since the Unix epoch.
+    timestamp: i64,
+    online: i32,
+    members: i32,
+    messages: i32,
+}
+
+impl From<GuildActivity> for ActivityPoint {
+    fn from(activity: GuildActivity) -> Self {
+        Self {
+            timestamp: activity.bucket.timestamp(),
+            online: activity.online_count,
+            members: activity.member_count,
+            messages: activity.message_count,
+        }
+    }
+}
+
+#[get("/{guild_id}/activity")]
+async fn guild_activity(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+    path: web::Path<u64>,
+    query: web::Query<ActivityQuery>,
+) -> JsonResult<GuildActivityResponse> {
+    let guild_id = GuildId(path.into_inner());
+    require_moderator(&data, &request, guild_id).await?;
+    let end = match query.end {
+        Some(ts) => parse_timestamp(ts)?,
+        None => Utc::now(),
+    };
+    let start = match query.start {
+        Some(ts) => parse_timestamp(ts)?,
+        None => end
+            .checked_sub_signed(Duration::weeks(1))
+            .ok_or(WebError::GenericHTTPError(StatusCode::BAD_REQUEST))?,
+    };
+    if start >= end {
+        return Err(WebError::GenericHTTPError(StatusCode::BAD_REQUEST));
+    }
+
+    let resolution = ActivityResolution::for_span(end - start);
+    let points = GuildActivity::fetch(guild_id, resolution, start, end)
+        .fetch_all(data.sql.replica())
+        .await?
+        .into_iter()
+        .map(ActivityPoint::from)
+        .collect();
+    Ok(web::Json(GuildActivityResponse {
+        resolution: resolution.seconds(),
+        points,
+    }))
+}
+
+fn parse_timestamp(ts: i64) -> WebResult<DateTime<Utc>> {
+    Utc.timestamp_opt(ts, 0)
+        .single()
+        .ok_or(WebError::GenericHTTPError(StatusCode::BAD_REQUEST))
+}
+
+pub fn scoped_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(guild_activity);
+}
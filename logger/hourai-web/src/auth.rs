@@ -0,0 +1,54 @@
+use crate::{prelude::*, AppState};
+use actix_web::HttpRequest;
+use hourai::models::id::{GuildId, UserId};
+use hourai_redis::CachedGuild;
+use serde::Deserialize;
+
+const CURRENT_USER_URL: &str = "https://discord.com/api/users/@me";
+
+#[derive(Deserialize)]
+struct DiscordUser {
+    id: String,
+}
+
+/// Identifies the user making a request from the Discord OAuth2 access token in its
+/// Authorization header. The token must have been granted the "identify" scope.
+pub async fn authenticate(state: &AppState, request: &HttpRequest) -> WebResult<UserId> {
+    let authorization = require_header(request, "Authorization")?;
+    let mut response = state
+        .http
+        .get(CURRENT_USER_URL)
+        .insert_header(("Authorization", authorization))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(WebError::UNAUTHORIZED);
+    }
+
+    let user: DiscordUser = response.json().await?;
+    user.id
+        .parse()
+        .map(UserId)
+        .map_err(|_| WebError::UNAUTHORIZED)
+}
+
+/// Authenticates the user making a request, and checks that they are a moderator of the given
+/// guild.
+pub async fn require_moderator(
+    state: &AppState,
+    request: &HttpRequest,
+    guild_id: GuildId,
+) -> WebResult<UserId> {
+    let user_id = authenticate(state, request).await?;
+    let member = hourai_sql::Member::fetch(guild_id, user_id)
+        .fetch_optional(&state.sql)
+        .await?
+        .ok_or(WebError::FORBIDDEN)?;
+    let roles: Vec<_> = member.role_ids().collect();
+    let mut redis = state.redis.clone();
+    if CachedGuild::is_moderator(guild_id, user_id, &roles, &mut redis).await? {
+        Ok(user_id)
+    } else {
+        Err(WebError::FORBIDDEN)
+    }
+}
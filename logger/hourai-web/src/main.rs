@@ -1,24 +1,40 @@
+mod activity;
+mod auth;
 mod guild_config;
 mod logger;
 mod oauth;
 mod prelude;
+mod stats;
 mod status;
+mod user_data;
+mod verification;
 
 use actix_web::{web, App, HttpServer};
 use hourai::{config, init};
+use hourai_validation::builder::VerifierResources;
+use std::sync::Arc;
 
 pub(crate) struct AppState {
     config: hourai::config::HouraiConfig,
     http: awc::Client,
+    discord: hourai::http::Client,
     sql: hourai_sql::SqlPool,
     redis: hourai_redis::RedisPool,
+    verifier_resources: Arc<VerifierResources>,
 }
 
 pub fn api(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/v1")
             .service(web::scope("/bot").configure(status::scoped_config))
-            .service(web::scope("/guilds").configure(guild_config::scoped_config)),
+            .service(
+                web::scope("/guilds")
+                    .configure(guild_config::scoped_config)
+                    .configure(activity::scoped_config)
+                    .configure(stats::scoped_config)
+                    .configure(verification::scoped_config),
+            )
+            .service(web::scope("/users").configure(user_data::scoped_config)),
     );
     // OAuth is not versioned
     cfg.service(web::scope("/oauth").configure(oauth::scoped_config));
@@ -29,8 +45,20 @@ async fn main() -> std::io::Result<()> {
     let config = config::load_config(config::get_config_path().as_ref());
     init::init(&config);
 
-    let sql = hourai_sql::init(&config).await;
+    let sql = hourai_sql::init(&config, "web").await;
     let redis = hourai_redis::init(&config).await;
+    let discord = init::http_client(&config);
+    let verifier_resources = Arc::new(
+        VerifierResources::load(&config, &discord, sql.clone(), redis.clone())
+            .await
+            .expect("Failed to load verification resources"),
+    );
+    actix_web::rt::spawn(
+        verifier_resources
+            .lists
+            .clone()
+            .watch(std::time::Duration::from_secs(60)),
+    );
     let port = config.web.port;
 
     HttpServer::new(move || {
@@ -39,8 +67,10 @@ async fn main() -> std::io::Result<()> {
             .data(AppState {
                 config: config.clone(),
                 http: awc::Client::new(),
+                discord: discord.clone(),
                 sql: sql.clone(),
                 redis: redis.clone(),
+                verifier_resources: verifier_resources.clone(),
             })
             .service(web::scope("/api").configure(api))
     })
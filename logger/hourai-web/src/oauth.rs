@@ -6,7 +6,7 @@ use time::Duration;
 
 const TOKEN_URL: &str = "https://discord.com/api/oauth2/token";
 const COOKIE_KEY: &str = "discord_refresh_token";
-const SCOPES: &str = "guilds";
+const SCOPES: &str = "identify guilds";
 
 #[derive(Deserialize)]
 struct TokenRequest {
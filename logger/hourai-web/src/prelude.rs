@@ -26,6 +26,8 @@ pub enum WebError {
     RedisError(#[from] redis::RedisError),
     #[error("SQL Error: {}", .0)]
     SqlError(#[from] sqlx::Error),
+    #[error("Internal Error: {}", .0)]
+    InternalError(#[from] anyhow::Error),
     #[error("Missing Header: {}", .0)]
     MissingHeader(String),
     #[error("Invalid request signature.")]
@@ -34,6 +36,7 @@ pub enum WebError {
 
 impl WebError {
     pub const UNAUTHORIZED: WebError = WebError::GenericHTTPError(StatusCode::UNAUTHORIZED);
+    pub const FORBIDDEN: WebError = WebError::GenericHTTPError(StatusCode::FORBIDDEN);
     pub const NOT_FOUND: WebError = WebError::GenericHTTPError(StatusCode::UNAUTHORIZED);
 
     pub fn message(&self) -> String {
@@ -0,0 +1,89 @@
+use crate::{auth::require_moderator, prelude::*, AppState};
+use actix_web::{get, http::StatusCode, web, HttpRequest};
+use chrono::{Duration, NaiveDate, Utc};
+use hourai::models::id::GuildId;
+use hourai_sql::stats::GuildDailyStats;
+use serde::{Deserialize, Serialize};
+
+/// The longest range of days that can be requested at once.
+const MAX_DAYS: i64 = 366;
+
+#[derive(Deserialize)]
+struct StatsQuery {
+    /// The first day of the range, as YYYY-MM-DD. Defaults to 30 days before `end`.
+    start: Option<String>,
+    /// The last day of the range, inclusive, as YYYY-MM-DD. Defaults to today.
+    end: Option<String>,
+}
+
+#[derive(Serialize)]
+struct GuildStatsResponse {
+    days: Vec<DailyStats>,
+}
+
+#[derive(Serialize)]
+struct DailyStats {
+    /// The UTC day, as YYYY-MM-DD.
+    date: String,
+    joins: i32,
+    leaves: i32,
+    bans: i32,
+    messages: i32,
+    verification_passes: i32,
+    verification_failures: i32,
+}
+
+impl From<GuildDailyStats> for DailyStats {
+    fn from(stats: GuildDailyStats) -> Self {
+        Self {
+            date: stats.day.to_string(),
+            joins: stats.joins,
+            leaves: stats.leaves,
+            bans: stats.bans,
+            messages: stats.messages,
+            verification_passes: stats.verification_passes,
+            verification_failures: stats.verification_failures,
+        }
+    }
+}
+
+fn parse_date(date: &str) -> WebResult<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| WebError::GenericHTTPError(StatusCode::BAD_REQUEST))
+}
+
+#[get("/{guild_id}/stats")]
+async fn guild_stats(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+    path: web::Path<u64>,
+    query: web::Query<StatsQuery>,
+) -> JsonResult<GuildStatsResponse> {
+    let guild_id = GuildId(path.into_inner());
+    require_moderator(&data, &request, guild_id).await?;
+    let end = match &query.end {
+        Some(end) => parse_date(end)?,
+        None => Utc::today().naive_utc(),
+    };
+    let start = match &query.start {
+        Some(start) => parse_date(start)?,
+        None => end
+            .checked_sub_signed(Duration::days(29))
+            .ok_or(WebError::GenericHTTPError(StatusCode::BAD_REQUEST))?,
+    };
+    if start > end || end - start >= Duration::days(MAX_DAYS) {
+        return Err(WebError::GenericHTTPError(StatusCode::BAD_REQUEST));
+    }
+
+    let days = GuildDailyStats::fetch(guild_id, start, end)
+        .fetch_all(data.sql.replica())
+        .await?
+        .into_iter()
+        .map(DailyStats::from)
+        .collect();
+    Ok(web::Json(GuildStatsResponse { days }))
+}
+
+pub fn scoped_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(guild_stats);
+}
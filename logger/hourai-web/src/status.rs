@@ -18,11 +18,11 @@ struct ShardStatus {
 #[get("/status")]
 async fn bot_status(data: web::Data<AppState>) -> JsonResult<BotStatus> {
     let guilds = hourai_sql::Member::count_guilds()
-        .fetch_one(&data.sql)
+        .fetch_one(data.sql.replica())
         .await?
         .0;
     let members = hourai_sql::Member::count_members()
-        .fetch_one(&data.sql)
+        .fetch_one(data.sql.replica())
         .await?
         .0;
     Ok(web::Json(BotStatus {
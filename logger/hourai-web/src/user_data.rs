@@ -0,0 +1,166 @@
+use crate::{auth::authenticate, prelude::*, AppState};
+use actix_web::{delete, get, web, HttpRequest};
+use hourai::models::{id::UserId, MessageLike};
+use hourai_redis::{CachedMessage, CachedVoiceState, OnlineStatus};
+use hourai_sql::user_data::{self, USER_DATA_TABLES};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+struct UserDataExport {
+    user_id: String,
+    tables: BTreeMap<&'static str, TableExport>,
+    cache: CacheExport,
+}
+
+#[derive(Serialize)]
+struct TableExport {
+    /// If true, the rows are not removed on erasure, as they are records of moderation actions,
+    /// or are kept while the user has bans.
+    retained: bool,
+    rows: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct CacheExport {
+    messages: Vec<CachedMessageExport>,
+    voice_states: Vec<VoiceStateExport>,
+    /// The guilds the user was last seen online in.
+    online_guilds: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct CachedMessageExport {
+    guild_id: Option<String>,
+    channel_id: String,
+    message_id: String,
+    content: String,
+}
+
+#[derive(Serialize)]
+struct VoiceStateExport {
+    guild_id: String,
+    channel_id: String,
+}
+
+#[derive(Serialize)]
+struct UserDataErasure {
+    user_id: String,
+    /// The number of rows deleted or anonymized in each table.
+    erased: BTreeMap<&'static str, u64>,
+    deleted_messages: usize,
+    /// Tables whose rows about the user were kept as moderation records, or because the user has
+    /// bans.
+    retained: Vec<&'static str>,
+    /// Caches that still hold copies of the user's data, which cannot be erased individually and
+    /// are removed as they age out.
+    expiring: Vec<&'static str>,
+}
+
+/// The Redis event streams hold recent gateway events, including message content and user
+/// profiles, until they are trimmed. See `hourai_redis::EventStream`.
+const EXPIRING_CACHES: &[&str] = &["event_streams"];
+
+#[get("/@me/data")]
+async fn export_data(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+) -> JsonResult<UserDataExport> {
+    let user_id = authenticate(&data, &request).await?;
+
+    let (has_ban_records,) = user_data::has_ban_records(user_id)
+        .fetch_one(&data.sql)
+        .await?;
+    let mut tables = BTreeMap::new();
+    for table in USER_DATA_TABLES {
+        let (rows,) = table.export(user_id).fetch_one(&data.sql).await?;
+        let rows = serde_json::from_str(&rows).map_err(anyhow::Error::from)?;
+        tables.insert(
+            table.name,
+            TableExport {
+                retained: table.is_retained(has_ban_records),
+                rows,
+            },
+        );
+    }
+
+    let mut redis = data.redis.clone();
+    let messages = CachedMessage::fetch_by_author(user_id, &mut redis)
+        .await?
+        .into_iter()
+        .map(|msg| CachedMessageExport {
+            guild_id: msg.guild_id().map(|id| id.0.to_string()),
+            channel_id: msg.channel_id().0.to_string(),
+            message_id: msg.id().0.to_string(),
+            content: msg.content().to_owned(),
+        })
+        .collect();
+    let voice_states = CachedVoiceState::fetch_user(user_id, &mut redis)
+        .await?
+        .into_iter()
+        .map(|(guild_id, channel_id)| VoiceStateExport {
+            guild_id: guild_id.0.to_string(),
+            channel_id: channel_id.0.to_string(),
+        })
+        .collect();
+    let online_guilds = OnlineStatus::fetch_user_guilds(user_id, &mut redis)
+        .await?
+        .into_iter()
+        .map(|guild_id| guild_id.0.to_string())
+        .collect();
+
+    Ok(web::Json(UserDataExport {
+        user_id: user_id.0.to_string(),
+        tables,
+        cache: CacheExport {
+            messages,
+            voice_states,
+            online_guilds,
+        },
+    }))
+}
+
+/// Erases the requesting user's data. Member and presence data is collected again if the user
+/// is still in a guild with the bot. Usernames and avatar hashes are kept while the user has bans
+/// or ban history, so that ban evasion can still be detected.
+#[delete("/@me/data")]
+async fn erase_data(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+) -> JsonResult<UserDataErasure> {
+    let user_id = authenticate(&data, &request).await?;
+
+    let mut erased = BTreeMap::new();
+    let mut txn = data.sql.begin().await?;
+    let (has_ban_records,) = user_data::has_ban_records(user_id)
+        .fetch_one(&mut txn)
+        .await?;
+    for table in USER_DATA_TABLES {
+        if let Some(query) = table.erase(user_id) {
+            let result = query.execute(&mut txn).await?;
+            erased.insert(table.name, result.rows_affected());
+        }
+    }
+    txn.commit().await?;
+
+    let mut redis = data.redis.clone();
+    let deleted_messages = CachedMessage::delete_by_author(user_id, &mut redis).await?;
+    CachedVoiceState::remove_user(user_id, &mut redis).await?;
+    OnlineStatus::remove_user(user_id, &mut redis).await?;
+
+    Ok(web::Json(UserDataErasure {
+        user_id: user_id.0.to_string(),
+        erased,
+        deleted_messages,
+        retained: USER_DATA_TABLES
+            .iter()
+            .filter(|table| table.is_retained(has_ban_records))
+            .map(|table| table.name)
+            .collect(),
+        expiring: EXPIRING_CACHES.to_vec(),
+    }))
+}
+
+pub fn scoped_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(export_data).service(erase_data);
+}
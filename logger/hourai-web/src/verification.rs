@@ -0,0 +1,101 @@
+use crate::{auth::require_moderator, prelude::*, AppState};
+use actix_web::{get, http::StatusCode, web, HttpRequest};
+use hourai::models::id::{GuildId, UserId};
+use hourai::proto::guild_configs::VerificationConfig;
+use hourai_redis::GuildConfig;
+use hourai_validation::context::{VerificationDecision, WeightedSignal};
+use hourai_validation::dry_run;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct VerificationCheckResponse {
+    /// False if verification is disabled, in which case the rest of the fields are empty.
+    enabled: bool,
+    approved: bool,
+    score: i64,
+    threshold: i64,
+    approvals: Vec<Signal>,
+    rejections: Vec<Signal>,
+}
+
+#[derive(Serialize)]
+struct Signal {
+    category: &'static str,
+    /// The weight after being scaled by the guild's scoring config. Negative for rejections.
+    weight: i64,
+    reason: String,
+}
+
+impl From<&WeightedSignal> for Signal {
+    fn from(signal: &WeightedSignal) -> Self {
+        Self {
+            category: signal.signal.category.name(),
+            weight: signal.weight,
+            reason: signal.signal.reason.clone(),
+        }
+    }
+}
+
+impl From<Option<VerificationDecision>> for VerificationCheckResponse {
+    fn from(decision: Option<VerificationDecision>) -> Self {
+        let decision = match decision {
+            Some(decision) => decision,
+            None => {
+                return Self {
+                    enabled: false,
+                    approved: false,
+                    score: 0,
+                    threshold: 0,
+                    approvals: Vec::new(),
+                    rejections: Vec::new(),
+                }
+            }
+        };
+        let signals = |pred: fn(&WeightedSignal) -> bool| -> Vec<Signal> {
+            decision
+                .signals
+                .iter()
+                .filter(|signal| pred(signal))
+                .map(Signal::from)
+                .collect()
+        };
+        Self {
+            enabled: true,
+            approved: decision.approved,
+            score: decision.score,
+            threshold: decision.threshold,
+            approvals: signals(|s| s.signal.is_approval()),
+            rejections: signals(|s| s.signal.is_rejection()),
+        }
+    }
+}
+
+/// Checks how a member, or a user that has not joined yet, would be verified by the guild's
+/// current configuration. This is a dry run: the member is not verified, and nothing about them
+/// is recorded. Only the guild's moderators may run it.
+#[get("/{guild_id}/verification/{user_id}")]
+async fn check_verification(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+    path: web::Path<(u64, u64)>,
+) -> JsonResult<VerificationCheckResponse> {
+    let (guild_id, user_id) = path.into_inner();
+    let (guild_id, user_id) = (GuildId(guild_id), UserId(user_id));
+    require_moderator(&data, &request, guild_id).await?;
+    let config =
+        GuildConfig::fetch_or_default::<VerificationConfig>(guild_id, &mut data.redis.clone())
+            .await?;
+    if !config.get_enabled() {
+        return Ok(web::Json(None.into()));
+    }
+
+    let member = dry_run::fetch_member(&data.discord, guild_id, user_id)
+        .await?
+        .ok_or(WebError::GenericHTTPError(StatusCode::NOT_FOUND))?;
+    let decision = dry_run::run(&config, &data.verifier_resources, member).await?;
+    Ok(web::Json(decision.into()))
+}
+
+pub fn scoped_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(check_verification);
+}
@@ -9,13 +9,66 @@ pub use self::{
 };
 
 use dashmap::{DashMap, DashSet};
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 use twilight_model::{
     gateway::presence::{Presence, Status, UserOrId},
     guild::{Guild, Member},
     id::{GuildId, UserId},
 };
 
+/// Changes to the members online in a guild since they were last taken with
+/// `InMemoryCache::take_presence_changes`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PresenceChanges {
+    /// If true, the guild's online members were replaced wholesale, and the full set should be
+    /// written out instead of the changes. Also set when the guild is removed from the cache.
+    pub resync: bool,
+    pub online: HashSet<UserId>,
+    pub offline: HashSet<UserId>,
+}
+
+impl PresenceChanges {
+    fn set_online(&mut self, user_id: UserId, online: bool) {
+        if online {
+            self.offline.remove(&user_id);
+            self.online.insert(user_id);
+        } else {
+            self.online.remove(&user_id);
+            self.offline.insert(user_id);
+        }
+    }
+
+    fn set_resync(&mut self) {
+        self.resync = true;
+        self.online.clear();
+        self.offline.clear();
+    }
+
+    /// Merges in changes that were made before these ones. Newer changes take precedence.
+    fn merge_older(&mut self, older: PresenceChanges) {
+        if self.resync {
+            return;
+        }
+        if older.resync {
+            self.set_resync();
+            return;
+        }
+        for user_id in older.online {
+            if !self.offline.contains(&user_id) {
+                self.online.insert(user_id);
+            }
+        }
+        for user_id in older.offline {
+            if !self.online.contains(&user_id) {
+                self.offline.insert(user_id);
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 struct GuildItem<T> {
     data: Arc<T>,
@@ -29,6 +82,7 @@ struct InMemoryCacheRef {
     config: Arc<Config>,
     guilds: DashSet<GuildId>,
     guild_presences: DashMap<GuildId, HashSet<UserId>>,
+    presence_changes: DashMap<GuildId, PresenceChanges>,
     unavailable_guilds: DashSet<GuildId>,
     pending_members: DashSet<(GuildId, UserId)>,
 }
@@ -145,12 +199,39 @@ impl InMemoryCache {
             .unwrap_or(false)
     }
 
+    /// Takes the changes to every guild's online members made since the last call.
+    ///
+    /// This is an O(n) operation, where n is the number of guilds with changes. This requires
+    /// the [`GUILD_PRESENCES`] intent.
+    ///
+    /// [`GUILD_PRESENCES`]: ::twilight_model::gateway::Intents::GUILD_PRESENCES
+    pub fn take_presence_changes(&self) -> HashMap<GuildId, PresenceChanges> {
+        let guild_ids: Vec<GuildId> = self.0.presence_changes.iter().map(|r| *r.key()).collect();
+        guild_ids
+            .into_iter()
+            .filter_map(|guild_id| self.0.presence_changes.remove(&guild_id))
+            .collect()
+    }
+
+    /// Puts back changes taken with `take_presence_changes` that could not be written out, so
+    /// that they are taken again with any made since.
+    pub fn restore_presence_changes(&self, changes: HashMap<GuildId, PresenceChanges>) {
+        for (guild_id, older) in changes {
+            self.0
+                .presence_changes
+                .entry(guild_id)
+                .or_default()
+                .merge_older(older);
+        }
+    }
+
     /// Clear the state of the Cache.
     ///
     /// This is equal to creating a new empty cache.
     pub fn clear(&self) {
         self.0.guilds.clear();
         self.0.guild_presences.clear();
+        self.0.presence_changes.clear();
         self.0.unavailable_guilds.clear();
         self.0.pending_members.clear();
     }
@@ -165,6 +246,7 @@ impl InMemoryCache {
         if self.wants(ResourceType::PRESENCE) {
             self.0.guild_presences.insert(guild.id, HashSet::new());
             self.cache_presences(guild.id, guild.presences);
+            self.resync_presences(guild.id);
         }
 
         self.0.guilds.insert(guild.id);
@@ -202,15 +284,31 @@ impl InMemoryCache {
     fn cache_presence(&self, guild_id: GuildId, user_id: UserId, status: Status) -> bool {
         let online = status == Status::Online;
         if let Some(mut kv) = self.0.guild_presences.get_mut(&guild_id) {
-            if online {
-                kv.value_mut().insert(user_id);
+            let changed = if online {
+                kv.value_mut().insert(user_id)
             } else {
-                kv.value_mut().remove(&user_id);
+                kv.value_mut().remove(&user_id)
+            };
+            if changed {
+                self.0
+                    .presence_changes
+                    .entry(guild_id)
+                    .or_default()
+                    .set_online(user_id, online);
             }
         }
         online
     }
 
+    /// Marks a guild's online members as needing to be written out in full.
+    fn resync_presences(&self, guild_id: GuildId) {
+        self.0
+            .presence_changes
+            .entry(guild_id)
+            .or_default()
+            .set_resync();
+    }
+
     fn unavailable_guild(&self, guild_id: GuildId) {
         self.0.unavailable_guilds.insert(guild_id);
         self.0.guilds.remove(&guild_id);
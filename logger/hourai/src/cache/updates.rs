@@ -55,6 +55,7 @@ impl UpdateCache for GuildDelete {
 
         if cache.wants(ResourceType::PRESENCE) {
             cache.0.guild_presences.remove(&id);
+            cache.resync_presences(id);
         }
     }
 }
@@ -158,12 +159,16 @@ impl UpdateCache for UnavailableGuild {
 mod tests {
     use super::super::config::ResourceType;
     use super::*;
+    use std::collections::HashSet;
     use twilight_model::{
         channel::{
             message::{MessageFlags, MessageType},
             ChannelType, GuildChannel, Message, Reaction, TextChannel,
         },
-        gateway::payload::{reaction_remove_emoji::PartialEmoji, ChannelDelete},
+        gateway::{
+            payload::{reaction_remove_emoji::PartialEmoji, ChannelDelete},
+            presence::Status,
+        },
         guild::{
             DefaultMessageNotificationLevel, ExplicitContentFilter, Guild, Member, MfaLevel,
             PartialGuild, PartialMember, PremiumTier, SystemChannelFlags, VerificationLevel,
@@ -193,4 +198,85 @@ mod tests {
         (guild_id, channel_id, channel)
     }
 
+    fn presence_cache() -> (GuildId, InMemoryCache) {
+        let guild_id = GuildId(1);
+        let cache = InMemoryCache::builder()
+            .resource_types(ResourceType::PRESENCE)
+            .build();
+        cache.0.guild_presences.insert(guild_id, HashSet::new());
+        (guild_id, cache)
+    }
+
+    #[test]
+    fn test_presence_changes() {
+        let (guild_id, cache) = presence_cache();
+        cache.cache_presence(guild_id, UserId(1), Status::Online);
+        cache.cache_presence(guild_id, UserId(2), Status::Online);
+        cache.cache_presence(guild_id, UserId(2), Status::Idle);
+        // Going offline when already offline is not a change.
+        cache.cache_presence(guild_id, UserId(3), Status::Offline);
+
+        let mut changes = cache.take_presence_changes();
+        assert_eq!(changes.len(), 1);
+        let changes = changes.remove(&guild_id).unwrap();
+        assert!(!changes.resync);
+        assert_eq!(changes.online, vec![UserId(1)].into_iter().collect());
+        assert_eq!(changes.offline, vec![UserId(2)].into_iter().collect());
+
+        // Changes are only reported once.
+        assert!(cache.take_presence_changes().is_empty());
+    }
+
+    #[test]
+    fn test_presence_changes_resync() {
+        let (guild_id, cache) = presence_cache();
+        cache.cache_presence(guild_id, UserId(1), Status::Online);
+        cache.update(&GuildDelete {
+            id: guild_id,
+            unavailable: false,
+        });
+
+        let changes = cache.take_presence_changes().remove(&guild_id).unwrap();
+        assert!(changes.resync);
+        assert!(changes.online.is_empty());
+        assert!(cache.guild_online(guild_id).is_none());
+    }
+
+    #[test]
+    fn test_restored_presence_changes_are_merged() {
+        let (guild_id, cache) = presence_cache();
+        cache.cache_presence(guild_id, UserId(1), Status::Online);
+        cache.cache_presence(guild_id, UserId(2), Status::Online);
+        let changes = cache.take_presence_changes();
+
+        // Newer changes take precedence over the restored ones.
+        cache.cache_presence(guild_id, UserId(2), Status::Offline);
+        cache.cache_presence(guild_id, UserId(3), Status::Online);
+        cache.restore_presence_changes(changes);
+
+        let changes = cache.take_presence_changes().remove(&guild_id).unwrap();
+        assert!(!changes.resync);
+        assert_eq!(
+            changes.online,
+            vec![UserId(1), UserId(3)].into_iter().collect()
+        );
+        assert_eq!(changes.offline, vec![UserId(2)].into_iter().collect());
+    }
+
+    #[test]
+    fn test_restored_presence_resync_is_kept() {
+        let (guild_id, cache) = presence_cache();
+        cache.update(&GuildDelete {
+            id: guild_id,
+            unavailable: false,
+        });
+        let changes = cache.take_presence_changes();
+
+        cache.cache_presence(guild_id, UserId(1), Status::Online);
+        cache.restore_presence_changes(changes);
+
+        let changes = cache.take_presence_changes().remove(&guild_id).unwrap();
+        assert!(changes.resync);
+        assert!(changes.online.is_empty());
+    }
 }
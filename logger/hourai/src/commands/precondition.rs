@@ -9,6 +9,15 @@ pub fn require_in_guild(ctx: &Context<'_>) -> Result<GuildId> {
         .ok_or_else(|| CommandError::FailedPrecondition("Command must be run in a server.").into())
 }
 
+pub fn require_direct_message(ctx: &Context<'_>) -> Result<()> {
+    if ctx.message.guild_id.is_some() {
+        return Err(
+            CommandError::FailedPrecondition("Command must be run in a direct message.").into(),
+        );
+    }
+    Ok(())
+}
+
 pub fn no_excess_arguments(args: &mut Arguments) -> Result<()> {
     if args.next().is_some() {
         return Err(CommandError::ExcessArguments.into());
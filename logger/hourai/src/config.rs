@@ -1,5 +1,6 @@
 use serde::Deserialize;
 use std::{
+    collections::HashMap,
     env,
     fs::File,
     io::BufReader,
@@ -11,8 +12,18 @@ const DEFAULT_ENV: &str = "dev";
 #[derive(Debug, Deserialize, Clone)]
 pub struct HouraiConfig {
     pub command_prefix: String,
+    /// The connection URL of the primary database. All writes go here.
     pub database: String,
+    /// Connection URLs of read replicas of the primary database. Read-only queries are spread
+    /// across these. If there are none, all queries go to the primary.
+    #[serde(default)]
+    pub database_replicas: Vec<String>,
+    /// Connection pool settings for each service, keyed by service name (i.e. "logger").
+    #[serde(default)]
+    pub database_pools: HashMap<String, DatabasePoolConfig>,
     pub redis: String,
+    /// The directory the globally managed word lists are loaded from.
+    pub list_directory: PathBuf,
     pub music: MusicConfig,
     pub discord: DiscordConfig,
     pub web: WebConfig,
@@ -21,6 +32,30 @@ pub struct HouraiConfig {
     pub third_party: ThirdPartyConfig,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct DatabasePoolConfig {
+    /// The maximum number of connections to each database.
+    pub max_connections: u32,
+    /// The number of idle connections kept open to each database.
+    pub min_connections: u32,
+    /// How long to wait for a free connection before a query fails.
+    pub acquire_timeout_secs: u64,
+    /// How long a connection may sit idle before it is closed. Never closed if not set.
+    pub idle_timeout_secs: Option<u64>,
+}
+
+impl Default for DatabasePoolConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 3,
+            min_connections: 0,
+            acquire_timeout_secs: 30,
+            idle_timeout_secs: Some(600),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct WebConfig {
     pub port: u16,
@@ -50,3 +50,10 @@ snowflake_id!(id::UserId);
 snowflake_id!(id::MessageId);
 snowflake_id!(id::ChannelId);
 snowflake_id!(id::GuildId);
+snowflake_id!(id::AuditLogEntryId);
+
+impl Snowflake<id::AuditLogEntryId> for guild::audit_log::AuditLogEntry {
+    fn id(&self) -> id::AuditLogEntryId {
+        self.id
+    }
+}
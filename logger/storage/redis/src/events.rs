@@ -0,0 +1,255 @@
+use crate::keys::{CacheKey, CachePrefix};
+use crate::protobuf::Protobuf;
+use anyhow::Result;
+use hourai::proto::event::Event;
+use redis::aio::ConnectionLike;
+use redis::streams::{StreamMaxlen, StreamReadOptions, StreamReadReply};
+use redis::{FromRedisValue, ToRedisArgs};
+use std::time::Duration;
+use tracing::error;
+
+/// The approximate maximum number of events retained in each stream. Older events are trimmed
+/// as new ones are published. Events include message content and user profiles, and are only
+/// removed by trimming: erasing a user's data does not remove them from the streams.
+const STREAM_MAX_LEN: usize = 100_000;
+
+/// The field each event's serialized proto is stored under in a stream entry.
+const EVENT_FIELD: &str = "data";
+
+/// The streams normalized gateway events are published to. Each category of event has its own
+/// stream so that consumers only receive the events they are interested in.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventStream {
+    /// Member join, update and leave events.
+    Members = 1_u8,
+    /// Ban and unban events.
+    Bans = 2_u8,
+    /// Message create, update and delete events.
+    Messages = 3_u8,
+}
+
+impl EventStream {
+    pub const ALL: [EventStream; 3] = [Self::Members, Self::Bans, Self::Messages];
+
+    fn key(self) -> CacheKey<u8> {
+        CachePrefix::EventStream.make_key(self as u8)
+    }
+
+    fn from_key(key: &[u8]) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .cloned()
+            .find(|stream| key == stream.key().to_redis_args()[0].as_slice())
+    }
+
+    /// Publishes an event to the stream.
+    pub fn publish(self, event: Event) -> redis::Cmd {
+        redis::Cmd::xadd_maxlen(
+            self.key(),
+            StreamMaxlen::Approx(STREAM_MAX_LEN),
+            "*",
+            &[(EVENT_FIELD, Protobuf(event))],
+        )
+    }
+
+    /// Publishes a batch of events, possibly to different streams, in a single round trip.
+    pub async fn publish_all<C: ConnectionLike>(
+        events: &[(EventStream, Event)],
+        conn: &mut C,
+    ) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let mut pipe = redis::pipe();
+        for (stream, event) in events {
+            pipe.add_command(stream.publish(event.clone())).ignore();
+        }
+        pipe.query_async::<_, ()>(conn).await?;
+        Ok(())
+    }
+
+    /// Creates a consumer group on the stream if it does not already exist. Newly created groups
+    /// only receive events published after their creation.
+    pub async fn create_group<C: ConnectionLike>(self, group: &str, conn: &mut C) -> Result<()> {
+        let result: redis::RedisResult<()> =
+            redis::Cmd::xgroup_create_mkstream(self.key(), group, "$")
+                .query_async(conn)
+                .await;
+        match result {
+            Err(err) if err.code() == Some("BUSYGROUP") => Ok(()),
+            result => Ok(result?),
+        }
+    }
+}
+
+/// An event read from a stream by an EventConsumer. Must be acknowledged once handled, or it
+/// will be redelivered by EventConsumer::read_pending.
+pub struct StreamEvent {
+    pub stream: EventStream,
+    pub id: String,
+    pub event: Event,
+}
+
+/// A named consumer within a Redis consumer group. Every event published to a stream is delivered
+/// to exactly one consumer in each group subscribed to it.
+pub struct EventConsumer {
+    group: String,
+    consumer: String,
+    streams: Vec<EventStream>,
+}
+
+impl EventConsumer {
+    pub fn new(
+        group: impl Into<String>,
+        consumer: impl Into<String>,
+        streams: &[EventStream],
+    ) -> Self {
+        Self {
+            group: group.into(),
+            consumer: consumer.into(),
+            streams: streams.to_vec(),
+        }
+    }
+
+    /// Creates the consumer group on all of the subscribed streams if it does not already exist.
+    pub async fn init<C: ConnectionLike>(&self, conn: &mut C) -> Result<()> {
+        for stream in self.streams.iter() {
+            stream.create_group(&self.group, conn).await?;
+        }
+        Ok(())
+    }
+
+    /// Reads up to `count` new events from each subscribed stream, waiting up to `block` for one
+    /// to be published if none are available.
+    pub async fn read<C: ConnectionLike>(
+        &self,
+        count: usize,
+        block: Duration,
+        conn: &mut C,
+    ) -> Result<Vec<StreamEvent>> {
+        let options = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .count(count)
+            .block(block.as_millis() as usize);
+        self.read_with(">", options, conn).await
+    }
+
+    /// Reads up to `count` events from each subscribed stream that were previously delivered to
+    /// this consumer but never acknowledged. Should be drained on startup to recover from a crash.
+    pub async fn read_pending<C: ConnectionLike>(
+        &self,
+        count: usize,
+        conn: &mut C,
+    ) -> Result<Vec<StreamEvent>> {
+        let options = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .count(count);
+        self.read_with("0", options, conn).await
+    }
+
+    /// Marks events as handled so they will not be redelivered.
+    pub async fn ack<C: ConnectionLike>(&self, events: &[StreamEvent], conn: &mut C) -> Result<()> {
+        for stream in self.streams.iter() {
+            let ids: Vec<&str> = events
+                .iter()
+                .filter(|evt| evt.stream == *stream)
+                .map(|evt| evt.id.as_str())
+                .collect();
+            self.ack_ids(*stream, &ids, conn).await?;
+        }
+        Ok(())
+    }
+
+    async fn ack_ids<C: ConnectionLike>(
+        &self,
+        stream: EventStream,
+        ids: &[&str],
+        conn: &mut C,
+    ) -> Result<()> {
+        if !ids.is_empty() {
+            redis::Cmd::xack(stream.key(), &self.group, ids)
+                .query_async::<_, ()>(conn)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn read_with<C: ConnectionLike>(
+        &self,
+        start: &str,
+        options: StreamReadOptions,
+        conn: &mut C,
+    ) -> Result<Vec<StreamEvent>> {
+        let keys: Vec<CacheKey<u8>> = self.streams.iter().map(|stream| stream.key()).collect();
+        let ids = vec![start; keys.len()];
+        let reply: Option<StreamReadReply> = redis::Cmd::xread_options(&keys, &ids, &options)
+            .query_async(conn)
+            .await?;
+
+        let mut events = Vec::new();
+        for key in reply.map(|reply| reply.keys).unwrap_or_default() {
+            let stream = match EventStream::from_key(key.key.as_bytes()) {
+                Some(stream) => stream,
+                None => continue,
+            };
+            let mut malformed = Vec::new();
+            for entry in key.ids {
+                let event = entry
+                    .map
+                    .get(EVENT_FIELD)
+                    .map(Protobuf::<Event>::from_redis_value);
+                match event {
+                    Some(Ok(proto)) => events.push(StreamEvent {
+                        stream,
+                        id: entry.id,
+                        event: proto.0,
+                    }),
+                    // Entries that cannot be parsed will never succeed on redelivery, or have
+                    // already been trimmed from the stream. Acknowledge them immediately.
+                    _ => {
+                        error!("Malformed event {} in stream {:?}", entry.id, stream);
+                        malformed.push(entry.id);
+                    }
+                }
+            }
+            let malformed: Vec<&str> = malformed.iter().map(|id| id.as_str()).collect();
+            self.ack_ids(stream, &malformed, conn).await?;
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    #[test]
+    fn test_stream_keys_round_trip() {
+        for stream in EventStream::ALL.iter() {
+            let key = stream.key().to_redis_args().remove(0);
+            assert_eq!(EventStream::from_key(&key), Some(*stream));
+        }
+        assert_eq!(EventStream::from_key(b"not a stream"), None);
+    }
+
+    #[test]
+    fn test_publish_encodes_event() {
+        let mut event = Event::new();
+        event.mut_source().set_guild_id(1234);
+        let packed = EventStream::Bans
+            .publish(event.clone())
+            .get_packed_command();
+        let key = EventStream::Bans.key().to_redis_args().remove(0);
+        let data = Protobuf(event).to_redis_args().remove(0);
+        assert!(contains(&packed, &key));
+        assert!(contains(&packed, EVENT_FIELD.as_bytes()));
+        assert!(contains(&packed, &data));
+    }
+}
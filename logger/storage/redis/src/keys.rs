@@ -17,6 +17,15 @@ pub enum CachePrefix {
     Guild = 4_u8,
     /// Cached voice state data.
     VoiceState = 5_u8,
+    /// Redis streams of normalized gateway events. Requires the EventStream as a secondary key.
+    EventStream = 6_u8,
+    /// Redis hashes indexing the guilds owned by each user, keyed by owner ID. Hash fields are
+    /// guild IDs, and values are the guild's comma-separated features.
+    GuildOwners = 7_u8,
+    /// Redis sets indexing the cached messages written by each user, bucketed by when they were
+    /// written. Requires the user ID and bucket as secondary keys. Members are the messages'
+    /// full keys.
+    MessageAuthors = 8_u8,
 }
 
 impl CachePrefix {
@@ -90,6 +99,16 @@ impl<P: Into<u8> + Clone> ToRedisArgs for PrefixedKey<P, ()> {
     }
 }
 
+impl<P: Into<u8> + Clone> ToRedisArgs for PrefixedKey<P, u8> {
+    fn write_redis_args<W: ?Sized>(&self, out: &mut W)
+    where
+        W: RedisWrite,
+    {
+        let key_enc = [self.0.clone().into(), self.1];
+        out.write_arg(&key_enc[..]);
+    }
+}
+
 impl<P: Into<u8> + Clone> ToRedisArgs for PrefixedKey<P, u64> {
     fn write_redis_args<W: ?Sized>(&self, out: &mut W)
     where
@@ -1,22 +1,31 @@
 mod compression;
+mod events;
 mod guild_config;
 mod keys;
+mod permissions;
 mod protobuf;
+mod storage;
 
 use self::compression::Compressed;
+pub use self::events::{EventConsumer, EventStream, StreamEvent};
 pub use self::guild_config::CachedGuildConfig;
-use self::keys::{CacheKey, CachePrefix, GuildKey, Id};
+use self::keys::{CachePrefix, GuildKey, GuildPrefix, Id};
 use self::protobuf::Protobuf;
+pub use self::storage::{InMemoryStorage, Storage};
 use anyhow::Result;
+use byteorder::{BigEndian, ByteOrder};
 use hourai::models::{
-    channel::GuildChannel,
+    channel::{permission_overwrite::*, GuildChannel},
     guild::{Guild, PartialGuild, Permissions, Role},
-    id::*, voice::VoiceState,
-    MessageLike, Snowflake, UserLike,
+    id::*,
+    user::User,
+    voice::VoiceState,
+    MessageLike, RoleFlags, Snowflake, UserLike,
 };
-use hourai::proto::cache::*;
-use redis::aio::ConnectionLike;
+use hourai::proto::{cache::*, guild_configs::RoleConfig};
 use redis::ToRedisArgs;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::debug;
 
 pub type RedisPool = redis::aio::ConnectionManager;
@@ -46,6 +55,10 @@ impl OnlineStatus {
         Self::default()
     }
 
+    /// How long a guild's online set is kept if it is not updated.
+    const TTL_SECS: usize = 3600;
+
+    /// Replaces the full set of members online in a guild.
     pub fn set_online(
         &mut self,
         guild_id: GuildId,
@@ -53,18 +66,94 @@ impl OnlineStatus {
     ) -> &mut Self {
         let key = CachePrefix::OnlineStatus.make_key(guild_id.0);
         let ids: Vec<Id<u64>> = online.into_iter().map(|id| Id(id.0)).collect();
-        self.pipeline
-            .del(key)
-            .ignore()
-            .sadd(key, ids)
-            .ignore()
-            .expire(key, 3600);
+        self.pipeline.del(key).ignore();
+        if !ids.is_empty() {
+            self.pipeline
+                .sadd(key, ids)
+                .ignore()
+                .expire(key, Self::TTL_SECS)
+                .ignore();
+        }
+        self
+    }
+
+    /// Marks members as having come online or gone offline in a guild, leaving the rest of the
+    /// guild's online set untouched.
+    pub fn update_online(
+        &mut self,
+        guild_id: GuildId,
+        online: impl IntoIterator<Item = UserId>,
+        offline: impl IntoIterator<Item = UserId>,
+    ) -> &mut Self {
+        let key = CachePrefix::OnlineStatus.make_key(guild_id.0);
+        let online: Vec<Id<u64>> = online.into_iter().map(|id| Id(id.0)).collect();
+        let offline: Vec<Id<u64>> = offline.into_iter().map(|id| Id(id.0)).collect();
+        if !offline.is_empty() {
+            self.pipeline.srem(key, offline).ignore();
+        }
+        if !online.is_empty() {
+            self.pipeline
+                .sadd(key, online)
+                .ignore()
+                .expire(key, Self::TTL_SECS)
+                .ignore();
+        }
         self
     }
 
     pub fn build(self) -> redis::Pipeline {
         self.pipeline
     }
+
+    /// Lists the guilds a user is currently marked as online in. This scans every guild's online
+    /// set, so it should be reserved for rare operations like data exports.
+    pub async fn fetch_user_guilds(
+        user_id: UserId,
+        conn: &mut dyn Storage,
+    ) -> Result<Vec<GuildId>> {
+        let prefix = storage::encode(CachePrefix::OnlineStatus.make_key(()));
+        let member = storage::encode(Id(user_id.0));
+        let mut guilds = Vec::new();
+        for key in conn.scan_prefix(prefix).await? {
+            if let Some(guild_id) = decode_id_key(&key) {
+                if conn.sismember(key, member.clone()).await? {
+                    guilds.push(GuildId(guild_id));
+                }
+            }
+        }
+        Ok(guilds)
+    }
+
+    /// Removes a user from every guild's online set.
+    pub async fn remove_user(user_id: UserId, conn: &mut dyn Storage) -> Result<()> {
+        let prefix = storage::encode(CachePrefix::OnlineStatus.make_key(()));
+        let member = storage::encode(Id(user_id.0));
+        for key in conn.scan_prefix(prefix).await? {
+            conn.srem(key, vec![member.clone()]).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the ID from a key made from a prefix and a single 64-bit ID.
+fn decode_id_key(key: &[u8]) -> Option<u64> {
+    if key.len() == 9 {
+        Some(BigEndian::read_u64(&key[1..9]))
+    } else {
+        None
+    }
+}
+
+/// Decodes the IDs from a key made from a prefix and a pair of 64-bit IDs.
+fn decode_id_pair_key(key: &[u8]) -> Option<(u64, u64)> {
+    if key.len() == 17 {
+        Some((
+            BigEndian::read_u64(&key[1..9]),
+            BigEndian::read_u64(&key[9..17]),
+        ))
+    } else {
+        None
+    }
 }
 
 pub struct GuildConfig;
@@ -72,25 +161,31 @@ pub struct GuildConfig;
 impl GuildConfig {
     pub async fn fetch<T: ::protobuf::Message + CachedGuildConfig>(
         id: GuildId,
-        conn: &mut RedisPool,
+        conn: &mut dyn Storage,
     ) -> std::result::Result<Option<T>, redis::RedisError> {
-        let key = CachePrefix::GuildConfigs.make_key(id.0);
-        let response: Option<Compressed<Protobuf<T>>> = redis::Cmd::hget(key, vec![T::SUBKEY])
-            .query_async(conn)
-            .await?;
-        Ok(response.map(|c| c.0 .0))
+        let key = storage::encode(CachePrefix::GuildConfigs.make_key(id.0));
+        let response = conn.hget(key, vec![vec![T::SUBKEY]]).await?;
+        match response.into_iter().next().flatten() {
+            Some(data) => Ok(Some(storage::decode::<Compressed<Protobuf<T>>>(data)?.0 .0)),
+            None => Ok(None),
+        }
     }
 
     pub async fn fetch_or_default<T: ::protobuf::Message + CachedGuildConfig>(
         id: GuildId,
-        conn: &mut RedisPool,
+        conn: &mut dyn Storage,
     ) -> std::result::Result<T, redis::RedisError> {
         Ok(Self::fetch::<T>(id, conn).await?.unwrap_or_else(T::new))
     }
 
-    pub fn set<T: ::protobuf::Message + CachedGuildConfig>(id: GuildId, value: T) -> redis::Cmd {
-        let key = CachePrefix::GuildConfigs.make_key(id.0);
-        redis::Cmd::hset(key, vec![T::SUBKEY], Compressed(Protobuf(value)))
+    pub async fn set<T: ::protobuf::Message + CachedGuildConfig>(
+        id: GuildId,
+        value: T,
+        conn: &mut dyn Storage,
+    ) -> std::result::Result<(), redis::RedisError> {
+        let key = storage::encode(CachePrefix::GuildConfigs.make_key(id.0));
+        let value = storage::encode(Compressed(Protobuf(value)));
+        conn.hset(key, vec![(vec![T::SUBKEY], value)]).await
     }
 }
 
@@ -99,6 +194,13 @@ pub struct CachedMessage {
 }
 
 impl CachedMessage {
+    /// How long messages are kept in the cache.
+    pub const TTL: Duration = Duration::from_secs(86400);
+
+    /// The length of each bucket of the message author index. Bounds the size of each bucket,
+    /// even for users that always have a message in the cache.
+    const AUTHOR_BUCKET_SECS: u64 = 3600;
+
     pub fn new(message: impl MessageLike) -> Self {
         let mut msg = CachedMessageProto::new();
         msg.set_id(message.id().0);
@@ -108,29 +210,27 @@ impl CachedMessage {
             msg.set_guild_id(guild_id.0)
         }
 
-        let user = msg.mut_author();
-        let author = message.author();
-        user.set_id(author.id().0);
-        user.set_username(author.name().to_owned());
-        user.set_discriminator(author.discriminator() as u32);
-        user.set_bot(author.bot());
-        if let Some(avatar) = author.avatar_hash() {
-            user.set_avatar(avatar.to_owned());
-        }
+        msg.set_author(user_to_proto(message.author()));
 
         Self {
             proto: Protobuf(msg),
         }
     }
 
-    pub async fn fetch<C: ConnectionLike>(
+    pub fn into_proto(self) -> CachedMessageProto {
+        self.proto.0
+    }
+
+    pub async fn fetch(
         channel_id: ChannelId,
         message_id: MessageId,
-        conn: &mut C,
+        conn: &mut dyn Storage,
     ) -> Result<Option<CachedMessageProto>> {
-        let key = CachePrefix::Messages.make_key((channel_id.0, message_id.0));
-        let proto: Option<Protobuf<CachedMessageProto>> =
-            redis::Cmd::get(key).query_async(conn).await?;
+        let key = storage::encode(CachePrefix::Messages.make_key((channel_id.0, message_id.0)));
+        let proto: Option<Protobuf<CachedMessageProto>> = match conn.get(key).await? {
+            Some(data) => Some(storage::decode(data)?),
+            None => None,
+        };
         Ok(proto.map(|msg| {
             let mut cached_message = msg.0;
             cached_message.set_id(message_id.0);
@@ -139,156 +239,399 @@ impl CachedMessage {
         }))
     }
 
-    pub fn flush(mut self) -> redis::Cmd {
+    pub async fn flush(mut self, conn: &mut dyn Storage) -> Result<()> {
         let channel_id = self.proto.0.get_channel_id();
         let id = self.proto.0.get_id();
-        let key = CachePrefix::Messages.make_key((channel_id, id));
+        let author_id = self.proto.0.get_author().get_id();
+        let key = storage::encode(CachePrefix::Messages.make_key((channel_id, id)));
         // Remove IDs to save space, as it's in the key.
         self.proto.0.clear_id();
         self.proto.0.clear_channel_id();
-        // Keep 1 day's worth of messages cached.
-        redis::Cmd::set_ex(key, self.proto, 86400)
+        conn.set(key.clone(), storage::encode(self.proto), Some(Self::TTL))
+            .await?;
+        // Each bucket expires along with the last message added to it.
+        let bucket = Self::author_bucket(SystemTime::now());
+        let index = storage::encode(CachePrefix::MessageAuthors.make_key((author_id, bucket)));
+        conn.sadd_expire(index, vec![key], Self::TTL).await?;
+        Ok(())
+    }
+
+    fn author_bucket(time: SystemTime) -> u64 {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        secs / Self::AUTHOR_BUCKET_SECS
+    }
+
+    /// Gets the keys of the author index buckets that may hold messages that are still cached.
+    fn author_index_keys(user_id: UserId) -> Vec<Vec<u8>> {
+        let current = Self::author_bucket(SystemTime::now());
+        let count = Self::TTL.as_secs() / Self::AUTHOR_BUCKET_SECS + 1;
+        (current.saturating_sub(count)..=current)
+            .map(|bucket| {
+                storage::encode(CachePrefix::MessageAuthors.make_key((user_id.0, bucket)))
+            })
+            .collect()
+    }
+
+    /// Gets the keys of the cached messages written by a user, from the message author index.
+    /// Some may already have expired or been deleted.
+    async fn fetch_author_keys(user_id: UserId, conn: &mut dyn Storage) -> Result<Vec<Vec<u8>>> {
+        let mut keys = Vec::new();
+        for index in Self::author_index_keys(user_id) {
+            keys.extend(conn.smembers(index).await?);
+        }
+        // Edited messages are indexed again when they are updated.
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    /// Fetches every cached message written by a user.
+    pub async fn fetch_by_author(
+        user_id: UserId,
+        conn: &mut dyn Storage,
+    ) -> Result<Vec<CachedMessageProto>> {
+        let mut messages = Vec::new();
+        for key in Self::fetch_author_keys(user_id, conn).await? {
+            let (channel_id, message_id) = match decode_id_pair_key(&key) {
+                Some(ids) => ids,
+                None => continue,
+            };
+            let message = Self::fetch(ChannelId(channel_id), MessageId(message_id), conn).await?;
+            // The message may have been edited by someone else since, or replaced with a
+            // different message.
+            if let Some(message) = message {
+                if message.get_author().get_id() == user_id.0 {
+                    messages.push(message);
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Deletes every cached message written by a user, returning how many were deleted.
+    pub async fn delete_by_author(user_id: UserId, conn: &mut dyn Storage) -> Result<usize> {
+        let keys: Vec<Vec<u8>> = Self::fetch_by_author(user_id, conn)
+            .await?
+            .iter()
+            .map(|msg| {
+                storage::encode(
+                    CachePrefix::Messages.make_key((msg.get_channel_id(), msg.get_id())),
+                )
+            })
+            .collect();
+        let count = keys.len();
+        conn.del(keys).await?;
+        conn.del(Self::author_index_keys(user_id)).await?;
+        Ok(count)
     }
 
-    pub fn delete(channel_id: ChannelId, id: MessageId) -> redis::Cmd {
-        Self::bulk_delete(channel_id, vec![id])
+    pub async fn delete(
+        channel_id: ChannelId,
+        id: MessageId,
+        conn: &mut dyn Storage,
+    ) -> Result<()> {
+        Self::bulk_delete(channel_id, vec![id], conn).await
     }
 
-    pub fn bulk_delete(
+    pub async fn bulk_delete(
         channel_id: ChannelId,
-        ids: impl IntoIterator<Item = MessageId>,
-    ) -> redis::Cmd {
-        let keys: Vec<CacheKey<(u64, u64)>> = ids
+        ids: Vec<MessageId>,
+        conn: &mut dyn Storage,
+    ) -> Result<()> {
+        let keys = ids
             .into_iter()
-            .map(|id| CachePrefix::Messages.make_key((channel_id.0, id.0)))
+            .map(|id| storage::encode(CachePrefix::Messages.make_key((channel_id.0, id.0))))
             .collect();
-        redis::Cmd::del(keys)
+        conn.del(keys).await?;
+        Ok(())
     }
 }
 
 pub struct CachedVoiceState;
 
 impl CachedVoiceState {
-
-    pub fn update_guild(guild: &Guild) -> redis::Pipeline {
-        let key = CachePrefix::VoiceState.make_key(guild.id.0);
-        let mut pipe = redis::pipe();
-        pipe.atomic().del(key).ignore();
-        for state in guild.voice_states.iter() {
-            pipe.add_command(Self::save(state)).ignore();
-        }
-        pipe
+    /// Replaces all of the cached voice states for a guild.
+    pub async fn update_guild(guild: &Guild, conn: &mut dyn Storage) -> Result<()> {
+        let key = storage::encode(CachePrefix::VoiceState.make_key(guild.id.0));
+        let entries = guild
+            .voice_states
+            .iter()
+            .filter_map(|state| {
+                state.channel_id.map(|channel_id| {
+                    (
+                        storage::encode(state.user_id.0),
+                        storage::encode(channel_id.0),
+                    )
+                })
+            })
+            .collect();
+        conn.hreplace(key, entries).await?;
+        Ok(())
     }
 
-    pub fn get_channel(guild_id: GuildId, user_id: UserId) -> redis::Cmd {
-        let key = CachePrefix::VoiceState.make_key(guild_id.0);
-        redis::Cmd::hget(key, user_id.0)
+    pub async fn get_channel(
+        guild_id: GuildId,
+        user_id: UserId,
+        conn: &mut dyn Storage,
+    ) -> Result<Option<ChannelId>> {
+        let key = storage::encode(CachePrefix::VoiceState.make_key(guild_id.0));
+        let response = conn.hget(key, vec![storage::encode(user_id.0)]).await?;
+        match response.into_iter().next().flatten() {
+            Some(data) => Ok(Some(ChannelId(storage::decode(data)?))),
+            None => Ok(None),
+        }
     }
 
-    pub fn get_channels(guild_id: GuildId) -> redis::Cmd {
-        redis::Cmd::hgetall(CachePrefix::VoiceState.make_key(guild_id.0))
+    pub async fn get_channels(
+        guild_id: GuildId,
+        conn: &mut dyn Storage,
+    ) -> Result<HashMap<UserId, ChannelId>> {
+        let key = storage::encode(CachePrefix::VoiceState.make_key(guild_id.0));
+        let mut channels = HashMap::new();
+        for (user_id, channel_id) in conn.hgetall(key).await? {
+            channels.insert(
+                UserId(storage::decode(user_id)?),
+                ChannelId(storage::decode(channel_id)?),
+            );
+        }
+        Ok(channels)
     }
 
-    pub fn save(state: &VoiceState) -> redis::Cmd {
-        let guild_id = state.guild_id.expect("Only voice states in guilds should be cached");
-        let key = CachePrefix::VoiceState.make_key(guild_id.0);
+    pub async fn save(state: &VoiceState, conn: &mut dyn Storage) -> Result<()> {
+        let guild_id = state
+            .guild_id
+            .expect("Only voice states in guilds should be cached");
         if let Some(channel_id) = state.channel_id {
-            redis::Cmd::hset(key, state.user_id.0, channel_id.0)
+            let key = storage::encode(CachePrefix::VoiceState.make_key(guild_id.0));
+            let entry = (
+                storage::encode(state.user_id.0),
+                storage::encode(channel_id.0),
+            );
+            conn.hset(key, vec![entry]).await?;
         } else {
-            redis::Cmd::hdel(key, state.user_id.0)
+            Self::remove(guild_id, state.user_id, conn).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn remove(guild_id: GuildId, user_id: UserId, conn: &mut dyn Storage) -> Result<()> {
+        let key = storage::encode(CachePrefix::VoiceState.make_key(guild_id.0));
+        conn.hdel(key, vec![storage::encode(user_id.0)]).await?;
+        Ok(())
+    }
+
+    /// Lists the voice channels a user is connected to across all guilds. This scans every
+    /// guild's voice states, so it should be reserved for rare operations like data exports.
+    pub async fn fetch_user(
+        user_id: UserId,
+        conn: &mut dyn Storage,
+    ) -> Result<Vec<(GuildId, ChannelId)>> {
+        let prefix = storage::encode(CachePrefix::VoiceState.make_key(()));
+        let mut channels = Vec::new();
+        for key in conn.scan_prefix(prefix).await? {
+            let guild_id = match decode_id_key(&key) {
+                Some(guild_id) => GuildId(guild_id),
+                None => continue,
+            };
+            if let Some(channel_id) = Self::get_channel(guild_id, user_id, conn).await? {
+                channels.push((guild_id, channel_id));
+            }
         }
+        Ok(channels)
     }
 
-    pub fn clear_guild(guild_id: GuildId) -> redis::Cmd {
-        redis::Cmd::del(CachePrefix::VoiceState.make_key(guild_id.0))
+    /// Removes a user's voice state from every guild.
+    pub async fn remove_user(user_id: UserId, conn: &mut dyn Storage) -> Result<()> {
+        for (guild_id, _) in Self::fetch_user(user_id, conn).await? {
+            Self::remove(guild_id, user_id, conn).await?;
+        }
+        Ok(())
     }
 
+    pub async fn clear_guild(guild_id: GuildId, conn: &mut dyn Storage) -> Result<()> {
+        let key = storage::encode(CachePrefix::VoiceState.make_key(guild_id.0));
+        conn.del(vec![key]).await?;
+        Ok(())
+    }
 }
 
 pub struct CachedGuild;
 
 impl CachedGuild {
-    pub fn save(guild: &hourai::models::guild::Guild) -> redis::Pipeline {
-        let key = CachePrefix::Guild.make_key(guild.id.0);
-        let mut pipe = redis::pipe();
-        pipe.atomic().del(key).ignore();
-        pipe.add_command(Self::save_resource(guild.id, guild.id, guild))
-            .ignore();
+    /// Replaces all of the cached information about a guild.
+    pub async fn save(guild: &Guild, conn: &mut dyn Storage) -> Result<()> {
+        let previous = Self::fetch_resource::<Guild>(guild.id, guild.id, conn).await?;
+        let key = storage::encode(CachePrefix::Guild.make_key(guild.id.0));
+        let mut entries = vec![Self::resource_entry(guild.id, guild)];
         for channel in guild.channels.iter() {
-            pipe.add_command(Self::save_resource(guild.id, channel.id(), channel))
-                .ignore();
+            entries.push(Self::resource_entry(channel.id(), channel));
         }
         for role in guild.roles.iter() {
-            pipe.add_command(Self::save_resource(guild.id, role.id, role))
-                .ignore();
+            entries.push(Self::resource_entry(role.id, role));
         }
-        pipe
+        conn.hreplace(key, entries).await?;
+        Self::index_owner(previous, &guild.to_proto(), conn).await
+    }
+
+    /// Updates the guild level information about a guild, leaving its roles and channels as is.
+    pub async fn update(guild: &PartialGuild, conn: &mut dyn Storage) -> Result<()> {
+        let previous = Self::fetch_resource::<PartialGuild>(guild.id, guild.id, conn).await?;
+        Self::save_resource(guild.id, guild.id, guild, conn).await?;
+        Self::index_owner(previous, &guild.to_proto(), conn).await
     }
 
     /// Deletes all of the cached information about a guild from the cache.
-    pub fn delete(guild_id: GuildId) -> redis::Cmd {
-        redis::Cmd::del(CachePrefix::Guild.make_key(guild_id.0))
+    pub async fn delete(guild_id: GuildId, conn: &mut dyn Storage) -> Result<()> {
+        if let Some(previous) = Self::fetch_resource::<Guild>(guild_id, guild_id, conn).await? {
+            let owner_key =
+                storage::encode(CachePrefix::GuildOwners.make_key(previous.get_owner_id()));
+            conn.hdel(owner_key, vec![storage::encode(Id(guild_id.0))])
+                .await?;
+        }
+        let key = storage::encode(CachePrefix::Guild.make_key(guild_id.0));
+        conn.del(vec![key]).await?;
+        Ok(())
+    }
+
+    /// Fetches the features of every cached guild a user owns, regardless of which process
+    /// cached the guild.
+    pub async fn fetch_owned_guilds(
+        user_id: UserId,
+        conn: &mut dyn Storage,
+    ) -> Result<HashMap<GuildId, Vec<String>>> {
+        let key = storage::encode(CachePrefix::GuildOwners.make_key(user_id.0));
+        let mut guilds = HashMap::new();
+        for (guild_id, features) in conn.hgetall(key).await? {
+            if guild_id.len() != 8 {
+                continue;
+            }
+            let features = String::from_utf8(features)?;
+            guilds.insert(
+                GuildId(BigEndian::read_u64(&guild_id)),
+                features
+                    .split(',')
+                    .filter(|feature| !feature.is_empty())
+                    .map(|feature| feature.to_owned())
+                    .collect(),
+            );
+        }
+        Ok(guilds)
+    }
+
+    /// Moves a guild to its current owner in the owner index, and updates its features.
+    async fn index_owner(
+        previous: Option<CachedGuildProto>,
+        guild: &CachedGuildProto,
+        conn: &mut dyn Storage,
+    ) -> Result<()> {
+        let field = storage::encode(Id(guild.get_id()));
+        if let Some(previous) = previous {
+            if previous.get_owner_id() != guild.get_owner_id() {
+                let key =
+                    storage::encode(CachePrefix::GuildOwners.make_key(previous.get_owner_id()));
+                conn.hdel(key, vec![field.clone()]).await?;
+            }
+        }
+        let key = storage::encode(CachePrefix::GuildOwners.make_key(guild.get_owner_id()));
+        let features = guild.get_features().join(",");
+        conn.hset(key, vec![(field, features.into_bytes())]).await?;
+        Ok(())
     }
 
     /// Gets a cached resource from the cache.
     pub async fn fetch_resource<T: GuildResource>(
         guild_id: GuildId,
         resource_id: T::Id,
-        conn: &mut RedisPool,
+        conn: &mut dyn Storage,
     ) -> Result<Option<T::Proto>>
     where
         GuildKey<T::Subkey>: ToRedisArgs,
     {
-        let guild_key = CachePrefix::Guild.make_key(guild_id.0);
-        let resource_key: GuildKey<T::Subkey> = resource_id.into();
-        let proto: Option<Protobuf<T::Proto>> = redis::Cmd::hget(guild_key, resource_key)
-            .query_async(conn)
-            .await?;
-        Ok(proto.map(|proto| proto.0))
+        Ok(Self::fetch_resources::<T>(guild_id, &[resource_id], conn)
+            .await?
+            .pop())
     }
 
     /// Fetches multiple resources from the cache.
     pub async fn fetch_resources<T: GuildResource>(
         guild_id: GuildId,
         resource_ids: &[T::Id],
-        conn: &mut RedisPool,
+        conn: &mut dyn Storage,
     ) -> Result<Vec<T::Proto>>
     where
         GuildKey<T::Subkey>: ToRedisArgs,
     {
-        let guild_key = CachePrefix::Guild.make_key(guild_id.0);
-        let resource_keys: Vec<GuildKey<T::Subkey>> =
-            resource_ids.iter().map(|id| id.clone().into()).collect();
-        let protos: Vec<Option<Protobuf<T::Proto>>> = redis::Cmd::hget(guild_key, resource_keys)
-            .query_async(conn)
-            .await?;
-        Ok(protos
-            .into_iter()
-            .filter_map(|p| p.map(|proto| proto.0))
-            .collect())
+        let guild_key = storage::encode(CachePrefix::Guild.make_key(guild_id.0));
+        let resource_keys = resource_ids
+            .iter()
+            .map(|id| storage::encode::<GuildKey<T::Subkey>>((*id).into()))
+            .collect();
+        let values = conn.hget(guild_key, resource_keys).await?;
+        let mut protos = Vec::new();
+        for data in values.into_iter().flatten() {
+            protos.push(storage::decode::<Protobuf<T::Proto>>(data)?.0);
+        }
+        Ok(protos)
+    }
+
+    /// Fetches all of the cached resources of a given type for a guild.
+    pub async fn fetch_all_resources<T: GuildResource>(
+        guild_id: GuildId,
+        conn: &mut dyn Storage,
+    ) -> Result<Vec<T::Proto>> {
+        let guild_key = storage::encode(CachePrefix::Guild.make_key(guild_id.0));
+        let prefix: u8 = T::PREFIX.into();
+        let mut protos = Vec::new();
+        for (key, value) in conn.hgetall(guild_key).await? {
+            if key.first() == Some(&prefix) {
+                protos.push(T::Proto::parse_from_bytes(&value)?);
+            }
+        }
+        Ok(protos)
     }
 
     /// Saves a resoruce into the cache.
-    pub fn save_resource<T: GuildResource>(
+    pub async fn save_resource<T: GuildResource>(
         guild_id: GuildId,
         resource_id: T::Id,
         data: &T,
-    ) -> redis::Cmd
+        conn: &mut dyn Storage,
+    ) -> Result<()>
     where
         GuildKey<T::Subkey>: ToRedisArgs,
     {
-        let guild_key = CachePrefix::Guild.make_key(guild_id.0);
-        let proto = Protobuf(data.to_proto());
-        redis::Cmd::hset(guild_key, resource_id.into(), proto)
+        let guild_key = storage::encode(CachePrefix::Guild.make_key(guild_id.0));
+        conn.hset(guild_key, vec![Self::resource_entry(resource_id, data)])
+            .await?;
+        Ok(())
     }
 
     /// Deletes a resource from the cache.
-    pub fn delete_resource<T: GuildResource>(guild_id: GuildId, resource_id: T::Id) -> redis::Cmd
+    pub async fn delete_resource<T: GuildResource>(
+        guild_id: GuildId,
+        resource_id: T::Id,
+        conn: &mut dyn Storage,
+    ) -> Result<()>
     where
         GuildKey<T::Subkey>: ToRedisArgs,
     {
-        let guild_key = CachePrefix::Guild.make_key(guild_id.0);
-        redis::Cmd::hdel(guild_key, resource_id.into())
+        let guild_key = storage::encode(CachePrefix::Guild.make_key(guild_id.0));
+        let resource_key = storage::encode::<GuildKey<T::Subkey>>(resource_id.into());
+        conn.hdel(guild_key, vec![resource_key]).await?;
+        Ok(())
+    }
+
+    fn resource_entry<T: GuildResource>(resource_id: T::Id, data: &T) -> (Vec<u8>, Vec<u8>)
+    where
+        GuildKey<T::Subkey>: ToRedisArgs,
+    {
+        (
+            storage::encode::<GuildKey<T::Subkey>>(resource_id.into()),
+            storage::encode(Protobuf(data.to_proto())),
+        )
     }
 
     /// Given a list of role IDs, finds the highest among them.
@@ -296,7 +639,7 @@ impl CachedGuild {
     pub async fn highest_role(
         guild_id: GuildId,
         role_ids: &[RoleId],
-        conn: &mut RedisPool,
+        conn: &mut dyn Storage,
     ) -> Result<i64> {
         Ok(Self::fetch_resources::<Role>(guild_id, role_ids, conn)
             .await?
@@ -313,32 +656,77 @@ impl CachedGuild {
         guild_id: GuildId,
         user_id: UserId,
         role_ids: impl Iterator<Item = RoleId>,
-        conn: &mut RedisPool,
+        conn: &mut dyn Storage,
     ) -> Result<Permissions> {
-        // The owner has all permissions.
-        if let Some(guild) = Self::fetch_resource::<Guild>(guild_id, guild_id, conn).await? {
-            if guild.get_owner_id() == user_id.0 {
-                return Ok(Permissions::all());
-            }
-        } else {
-            return Ok(Permissions::empty());
+        let guild = match Self::fetch_resource::<Guild>(guild_id, guild_id, conn).await? {
+            Some(guild) => guild,
+            None => return Ok(Permissions::empty()),
+        };
+        let roles = Self::fetch_member_roles(guild_id, role_ids, conn).await?;
+        Ok(permissions::guild_permissions(&guild, user_id, &roles))
+    }
+
+    /// Checks if a member is a moderator: either an administrator, or holding a role flagged as a
+    /// moderator role in the guild's role config.
+    pub async fn is_moderator(
+        guild_id: GuildId,
+        user_id: UserId,
+        role_ids: &[RoleId],
+        conn: &mut dyn Storage,
+    ) -> Result<bool> {
+        let perms =
+            Self::guild_permissions(guild_id, user_id, role_ids.iter().cloned(), conn).await?;
+        if perms.contains(Permissions::ADMINISTRATOR) {
+            return Ok(true);
         }
 
+        let config = GuildConfig::fetch_or_default::<RoleConfig>(guild_id, conn).await?;
+        let settings = config.get_settings();
+        Ok(role_ids.iter().any(|role| {
+            settings
+                .get(&role.0)
+                .map(|role| RoleFlags::from_bits_truncate(role.get_flags()))
+                .map(|flags| flags.contains(RoleFlags::MODERATOR))
+                .unwrap_or(false)
+        }))
+    }
+
+    /// Gets the channel-level permissions for a given member, applying the channel's
+    /// permission overwrites.
+    /// If the guild, the channel or any of the roles are not present, this will return
+    /// Permissions::empty.
+    pub async fn channel_permissions(
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        user_id: UserId,
+        role_ids: impl Iterator<Item = RoleId>,
+        conn: &mut dyn Storage,
+    ) -> Result<Permissions> {
+        let guild = match Self::fetch_resource::<Guild>(guild_id, guild_id, conn).await? {
+            Some(guild) => guild,
+            None => return Ok(Permissions::empty()),
+        };
+        let channel = match Self::fetch_resource::<GuildChannel>(guild_id, channel_id, conn).await?
+        {
+            Some(channel) => channel,
+            None => return Ok(Permissions::empty()),
+        };
+        let roles = Self::fetch_member_roles(guild_id, role_ids, conn).await?;
+        Ok(permissions::channel_permissions(
+            &guild, &channel, user_id, &roles,
+        ))
+    }
+
+    /// Fetches the roles of a member, including the @everyone role.
+    async fn fetch_member_roles(
+        guild_id: GuildId,
+        role_ids: impl Iterator<Item = RoleId>,
+        conn: &mut dyn Storage,
+    ) -> Result<Vec<CachedRoleProto>> {
         // The everyone role ID is the same as the guild ID.
         let mut role_ids: Vec<RoleId> = role_ids.collect();
         role_ids.push(RoleId(guild_id.0));
-        let perms = Self::fetch_resources::<Role>(guild_id, &role_ids, conn)
-            .await?
-            .into_iter()
-            .map(|role| Permissions::from_bits_truncate(role.get_permissions()))
-            .fold(Permissions::empty(), |acc, perm| acc | perm);
-
-        // Administrators by default have every permission enabled.
-        Ok(if perms.contains(Permissions::ADMINISTRATOR) {
-            Permissions::all()
-        } else {
-            perms
-        })
+        Self::fetch_resources::<Role>(guild_id, &role_ids, conn).await
     }
 }
 
@@ -347,12 +735,33 @@ pub trait ToProto {
     fn to_proto(&self) -> Self::Proto;
 }
 
+fn user_to_proto(user: &impl UserLike) -> CachedUserProto {
+    let mut proto = CachedUserProto::new();
+    proto.set_id(user.id().0);
+    proto.set_username(user.name().to_owned());
+    proto.set_discriminator(user.discriminator() as u32);
+    proto.set_bot(user.bot());
+    if let Some(avatar) = user.avatar_hash() {
+        proto.set_avatar(avatar.to_owned());
+    }
+    proto
+}
+
+impl ToProto for User {
+    type Proto = CachedUserProto;
+    fn to_proto(&self) -> Self::Proto {
+        user_to_proto(self)
+    }
+}
+
 pub trait GuildResource: ToProto {
+    const PREFIX: GuildPrefix;
     type Id: Into<GuildKey<Self::Subkey>> + Copy;
     type Subkey;
 }
 
 impl GuildResource for Guild {
+    const PREFIX: GuildPrefix = GuildPrefix::Guild;
     type Id = GuildId;
     type Subkey = ();
 }
@@ -373,6 +782,7 @@ impl ToProto for Guild {
 }
 
 impl GuildResource for PartialGuild {
+    const PREFIX: GuildPrefix = GuildPrefix::Guild;
     type Id = GuildId;
     type Subkey = ();
 }
@@ -393,6 +803,7 @@ impl ToProto for PartialGuild {
 }
 
 impl GuildResource for GuildChannel {
+    const PREFIX: GuildPrefix = GuildPrefix::Channel;
     type Id = ChannelId;
     type Subkey = u64;
 }
@@ -400,14 +811,56 @@ impl GuildResource for GuildChannel {
 impl ToProto for GuildChannel {
     type Proto = CachedGuildChannelProto;
     fn to_proto(&self) -> Self::Proto {
+        let (kind, parent_id, position, overwrites) = match self {
+            GuildChannel::Category(ch) => (ch.kind, None, ch.position, &ch.permission_overwrites),
+            GuildChannel::Text(ch) => (
+                ch.kind,
+                ch.parent_id,
+                ch.position,
+                &ch.permission_overwrites,
+            ),
+            GuildChannel::Voice(ch) => (
+                ch.kind,
+                ch.parent_id,
+                ch.position,
+                &ch.permission_overwrites,
+            ),
+        };
         let mut proto = Self::Proto::new();
         proto.set_channel_id(self.id().0);
         proto.set_name(self.name().to_owned());
+        proto.set_channel_type(kind as u32);
+        proto.set_position(position);
+        if let Some(parent_id) = parent_id {
+            proto.set_parent_id(parent_id.0);
+        }
+        proto.permission_overwrites = overwrites.iter().map(|o| o.to_proto()).collect();
+        proto
+    }
+}
+
+impl ToProto for PermissionOverwrite {
+    type Proto = CachedPermissionOverwriteProto;
+    fn to_proto(&self) -> Self::Proto {
+        let mut proto = Self::Proto::new();
+        match self.kind {
+            PermissionOverwriteType::Role(id) => {
+                proto.set_field_type(CachedPermissionOverwriteProto_Type::ROLE);
+                proto.set_id(id.0);
+            }
+            PermissionOverwriteType::Member(id) => {
+                proto.set_field_type(CachedPermissionOverwriteProto_Type::MEMBER);
+                proto.set_id(id.0);
+            }
+        }
+        proto.set_allow(self.allow.bits());
+        proto.set_deny(self.deny.bits());
         proto
     }
 }
 
 impl GuildResource for Role {
+    const PREFIX: GuildPrefix = GuildPrefix::Role;
     type Id = RoleId;
     type Subkey = u64;
 }
@@ -423,3 +876,251 @@ impl ToProto for Role {
         proto
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hourai::proto::guild_configs::LoggingConfig;
+
+    fn message(channel_id: u64, id: u64) -> CachedMessageProto {
+        let mut msg = CachedMessageProto::new();
+        msg.set_id(id);
+        msg.set_channel_id(channel_id);
+        msg.set_content("Hello world!".to_owned());
+        let author = msg.mut_author();
+        author.set_id(1234);
+        author.set_username("User".to_owned());
+        author.set_discriminator(1);
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_message_round_trip() {
+        let mut storage = InMemoryStorage::new();
+        CachedMessage::new(message(1, 2))
+            .flush(&mut storage)
+            .await
+            .unwrap();
+        let cached = CachedMessage::fetch(ChannelId(1), MessageId(2), &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(cached, Some(message(1, 2)));
+        let missing = CachedMessage::fetch(ChannelId(1), MessageId(3), &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(missing, None);
+    }
+
+    #[tokio::test]
+    async fn test_message_expires() {
+        let mut storage = InMemoryStorage::new();
+        CachedMessage::new(message(1, 2))
+            .flush(&mut storage)
+            .await
+            .unwrap();
+        storage.advance_time(CachedMessage::TTL);
+        let cached = CachedMessage::fetch(ChannelId(1), MessageId(2), &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(cached, None);
+    }
+
+    #[tokio::test]
+    async fn test_message_bulk_delete() {
+        let mut storage = InMemoryStorage::new();
+        for id in 1..=3 {
+            CachedMessage::new(message(1, id))
+                .flush(&mut storage)
+                .await
+                .unwrap();
+        }
+        CachedMessage::bulk_delete(ChannelId(1), vec![MessageId(1), MessageId(2)], &mut storage)
+            .await
+            .unwrap();
+        for id in 1..=2 {
+            let cached = CachedMessage::fetch(ChannelId(1), MessageId(id), &mut storage)
+                .await
+                .unwrap();
+            assert_eq!(cached, None);
+        }
+        let cached = CachedMessage::fetch(ChannelId(1), MessageId(3), &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(cached, Some(message(1, 3)));
+    }
+
+    #[tokio::test]
+    async fn test_guild_config_round_trip() {
+        let mut storage = InMemoryStorage::new();
+        let guild_id = GuildId(1);
+        let config = GuildConfig::fetch::<LoggingConfig>(guild_id, &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(config, None);
+
+        let mut config = LoggingConfig::new();
+        config.set_modlog_channel_id(1234);
+        GuildConfig::set(guild_id, config.clone(), &mut storage)
+            .await
+            .unwrap();
+        let fetched = GuildConfig::fetch_or_default::<LoggingConfig>(guild_id, &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(fetched, config);
+    }
+
+    #[tokio::test]
+    async fn test_messages_by_author() {
+        let mut storage = InMemoryStorage::new();
+        for id in 1..=3 {
+            CachedMessage::new(message(id, id))
+                .flush(&mut storage)
+                .await
+                .unwrap();
+        }
+        let mut other = message(1, 4);
+        other.mut_author().set_id(5678);
+        CachedMessage::new(other.clone())
+            .flush(&mut storage)
+            .await
+            .unwrap();
+
+        let mut messages = CachedMessage::fetch_by_author(UserId(1234), &mut storage)
+            .await
+            .unwrap();
+        messages.sort_by_key(|msg| msg.get_id());
+        assert_eq!(messages, vec![message(1, 1), message(2, 2), message(3, 3)]);
+
+        let deleted = CachedMessage::delete_by_author(UserId(1234), &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(deleted, 3);
+        let messages = CachedMessage::fetch_by_author(UserId(1234), &mut storage)
+            .await
+            .unwrap();
+        assert!(messages.is_empty());
+        let cached = CachedMessage::fetch(ChannelId(1), MessageId(4), &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(cached, Some(other));
+    }
+
+    #[tokio::test]
+    async fn test_online_status_by_user() {
+        let mut storage = InMemoryStorage::new();
+        for guild_id in 1..=2 {
+            let key = storage::encode(CachePrefix::OnlineStatus.make_key(guild_id));
+            let members = vec![storage::encode(Id(1234)), storage::encode(Id(guild_id))];
+            storage.sadd(key, members).await.unwrap();
+        }
+
+        let mut guilds = OnlineStatus::fetch_user_guilds(UserId(1234), &mut storage)
+            .await
+            .unwrap();
+        guilds.sort();
+        assert_eq!(guilds, vec![GuildId(1), GuildId(2)]);
+        let guilds = OnlineStatus::fetch_user_guilds(UserId(2), &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(guilds, vec![GuildId(2)]);
+
+        OnlineStatus::remove_user(UserId(1234), &mut storage)
+            .await
+            .unwrap();
+        let guilds = OnlineStatus::fetch_user_guilds(UserId(1234), &mut storage)
+            .await
+            .unwrap();
+        assert!(guilds.is_empty());
+        let guilds = OnlineStatus::fetch_user_guilds(UserId(2), &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(guilds, vec![GuildId(2)]);
+    }
+
+    #[tokio::test]
+    async fn test_voice_states_by_user() {
+        let mut storage = InMemoryStorage::new();
+        for guild_id in 1..=2_u64 {
+            let key = storage::encode(CachePrefix::VoiceState.make_key(guild_id));
+            let entries = vec![
+                (storage::encode(1234_u64), storage::encode(guild_id * 10)),
+                (storage::encode(5678_u64), storage::encode(guild_id * 10)),
+            ];
+            storage.hset(key, entries).await.unwrap();
+        }
+
+        let mut channels = CachedVoiceState::fetch_user(UserId(1234), &mut storage)
+            .await
+            .unwrap();
+        channels.sort();
+        assert_eq!(
+            channels,
+            vec![(GuildId(1), ChannelId(10)), (GuildId(2), ChannelId(20))]
+        );
+
+        CachedVoiceState::remove_user(UserId(1234), &mut storage)
+            .await
+            .unwrap();
+        let channels = CachedVoiceState::fetch_user(UserId(1234), &mut storage)
+            .await
+            .unwrap();
+        assert!(channels.is_empty());
+        let channel = CachedVoiceState::get_channel(GuildId(1), UserId(5678), &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(channel, Some(ChannelId(10)));
+    }
+
+    fn guild(id: u64, owner_id: u64, features: &[&str]) -> CachedGuildProto {
+        let mut guild = CachedGuildProto::new();
+        guild.set_id(id);
+        guild.set_owner_id(owner_id);
+        guild.features = features.iter().map(|f| f.to_string()).collect();
+        guild
+    }
+
+    #[tokio::test]
+    async fn test_guild_owner_index() {
+        let mut storage = InMemoryStorage::new();
+        let verified = guild(1, 10, &["VERIFIED", "COMMUNITY"]);
+        CachedGuild::index_owner(None, &verified, &mut storage)
+            .await
+            .unwrap();
+        CachedGuild::index_owner(None, &guild(2, 10, &[]), &mut storage)
+            .await
+            .unwrap();
+        let owned = CachedGuild::fetch_owned_guilds(UserId(10), &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(owned.len(), 2);
+        assert_eq!(owned[&GuildId(1)], vec!["VERIFIED", "COMMUNITY"]);
+        assert!(owned[&GuildId(2)].is_empty());
+
+        // Transferring ownership moves the guild to the new owner.
+        let transferred = guild(1, 20, &["VERIFIED"]);
+        CachedGuild::index_owner(Some(verified), &transferred, &mut storage)
+            .await
+            .unwrap();
+        let owned = CachedGuild::fetch_owned_guilds(UserId(10), &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(owned.keys().collect::<Vec<_>>(), vec![&GuildId(2)]);
+        let owned = CachedGuild::fetch_owned_guilds(UserId(20), &mut storage)
+            .await
+            .unwrap();
+        assert_eq!(owned[&GuildId(1)], vec!["VERIFIED"]);
+
+        // Deleting the guild removes it from the index.
+        let key = storage::encode(CachePrefix::Guild.make_key(1_u64));
+        let entries = vec![(
+            storage::encode::<GuildKey<()>>(GuildId(1).into()),
+            storage::encode(Protobuf(transferred)),
+        )];
+        storage.hset(key, entries).await.unwrap();
+        CachedGuild::delete(GuildId(1), &mut storage).await.unwrap();
+        let owned = CachedGuild::fetch_owned_guilds(UserId(20), &mut storage)
+            .await
+            .unwrap();
+        assert!(owned.is_empty());
+    }
+}
@@ -0,0 +1,409 @@
+use hourai::models::{guild::Permissions, id::*};
+use hourai::proto::cache::*;
+
+/// Computes the guild-level permissions for a member given the cached guild and the cached
+/// protos of the member's roles, including the @everyone role.
+pub fn guild_permissions(
+    guild: &CachedGuildProto,
+    user_id: UserId,
+    roles: &[CachedRoleProto],
+) -> Permissions {
+    // The owner has all permissions.
+    if guild.get_owner_id() == user_id.0 {
+        return Permissions::all();
+    }
+
+    let perms = roles
+        .iter()
+        .map(|role| Permissions::from_bits_truncate(role.get_permissions()))
+        .fold(Permissions::empty(), |acc, perm| acc | perm);
+
+    // Administrators by default have every permission enabled.
+    if perms.contains(Permissions::ADMINISTRATOR) {
+        Permissions::all()
+    } else {
+        perms
+    }
+}
+
+/// Computes the permissions a member has in a specific channel given the cached guild, the
+/// cached channel, and the cached protos of the member's roles, including the @everyone role.
+///
+/// Overwrites are applied in the order Discord applies them: the @everyone overwrite, then the
+/// union of all role overwrites, then the member specific overwrite. Within each step, denies
+/// are applied before allows. Owners and administrators bypass overwrites entirely.
+///
+/// Category permissions are not consulted directly. Channels synced with their category carry
+/// a copy of the category's overwrites, and unsynced channels do not inherit from it at all.
+pub fn channel_permissions(
+    guild: &CachedGuildProto,
+    channel: &CachedGuildChannelProto,
+    user_id: UserId,
+    roles: &[CachedRoleProto],
+) -> Permissions {
+    let mut perms = guild_permissions(guild, user_id, roles);
+    if perms.contains(Permissions::ADMINISTRATOR) {
+        return Permissions::all();
+    }
+
+    // The everyone role ID is the same as the guild ID.
+    let everyone_id = guild.get_id();
+    let overwrites = channel.get_permission_overwrites();
+
+    let everyone = overwrites.iter().find(|overwrite| {
+        overwrite.get_field_type() == CachedPermissionOverwriteProto_Type::ROLE
+            && overwrite.get_id() == everyone_id
+    });
+    if let Some(overwrite) = everyone {
+        perms = apply_overwrite(perms, overwrite.get_allow(), overwrite.get_deny());
+    }
+
+    let (allow, deny) = overwrites
+        .iter()
+        .filter(|overwrite| {
+            overwrite.get_field_type() == CachedPermissionOverwriteProto_Type::ROLE
+                && overwrite.get_id() != everyone_id
+                && roles
+                    .iter()
+                    .any(|role| role.get_role_id() == overwrite.get_id())
+        })
+        .fold((0, 0), |(allow, deny), overwrite| {
+            (allow | overwrite.get_allow(), deny | overwrite.get_deny())
+        });
+    perms = apply_overwrite(perms, allow, deny);
+
+    let member = overwrites.iter().find(|overwrite| {
+        overwrite.get_field_type() == CachedPermissionOverwriteProto_Type::MEMBER
+            && overwrite.get_id() == user_id.0
+    });
+    if let Some(overwrite) = member {
+        perms = apply_overwrite(perms, overwrite.get_allow(), overwrite.get_deny());
+    }
+
+    apply_implicit_permissions(perms)
+}
+
+fn apply_overwrite(perms: Permissions, allow: u64, deny: u64) -> Permissions {
+    (perms & !Permissions::from_bits_truncate(deny)) | Permissions::from_bits_truncate(allow)
+}
+
+/// Removes permissions that Discord implicitly denies when their prerequisites are missing.
+fn apply_implicit_permissions(perms: Permissions) -> Permissions {
+    // Members who cannot see a channel cannot do anything in it.
+    if !perms.contains(Permissions::VIEW_CHANNEL) {
+        return Permissions::empty();
+    }
+
+    // Members who cannot send messages cannot use any of the message sub-permissions.
+    if !perms.contains(Permissions::SEND_MESSAGES) {
+        return perms
+            - (Permissions::SEND_TTS_MESSAGES
+                | Permissions::MENTION_EVERYONE
+                | Permissions::EMBED_LINKS
+                | Permissions::ATTACH_FILES);
+    }
+
+    perms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD_ID: u64 = 1;
+    const OWNER_ID: u64 = 2;
+    const USER_ID: u64 = 3;
+    const ROLE_A: u64 = 10;
+    const ROLE_B: u64 = 11;
+    const CATEGORY_ID: u64 = 20;
+    const CHANNEL_ID: u64 = 21;
+
+    fn guild() -> CachedGuildProto {
+        let mut guild = CachedGuildProto::new();
+        guild.set_id(GUILD_ID);
+        guild.set_owner_id(OWNER_ID);
+        guild
+    }
+
+    fn role(id: u64, perms: Permissions) -> CachedRoleProto {
+        let mut role = CachedRoleProto::new();
+        role.set_role_id(id);
+        role.set_permissions(perms.bits());
+        role
+    }
+
+    fn everyone(perms: Permissions) -> CachedRoleProto {
+        role(GUILD_ID, perms)
+    }
+
+    fn overwrite(
+        kind: CachedPermissionOverwriteProto_Type,
+        id: u64,
+        allow: Permissions,
+        deny: Permissions,
+    ) -> CachedPermissionOverwriteProto {
+        let mut overwrite = CachedPermissionOverwriteProto::new();
+        overwrite.set_field_type(kind);
+        overwrite.set_id(id);
+        overwrite.set_allow(allow.bits());
+        overwrite.set_deny(deny.bits());
+        overwrite
+    }
+
+    fn role_overwrite(
+        id: u64,
+        allow: Permissions,
+        deny: Permissions,
+    ) -> CachedPermissionOverwriteProto {
+        overwrite(CachedPermissionOverwriteProto_Type::ROLE, id, allow, deny)
+    }
+
+    fn member_overwrite(
+        id: u64,
+        allow: Permissions,
+        deny: Permissions,
+    ) -> CachedPermissionOverwriteProto {
+        overwrite(CachedPermissionOverwriteProto_Type::MEMBER, id, allow, deny)
+    }
+
+    fn channel(
+        id: u64,
+        parent_id: Option<u64>,
+        overwrites: Vec<CachedPermissionOverwriteProto>,
+    ) -> CachedGuildChannelProto {
+        let mut channel = CachedGuildChannelProto::new();
+        channel.set_channel_id(id);
+        if let Some(parent_id) = parent_id {
+            channel.set_parent_id(parent_id);
+        }
+        channel.set_permission_overwrites(::protobuf::RepeatedField::from_vec(overwrites));
+        channel
+    }
+
+    fn base() -> Permissions {
+        Permissions::VIEW_CHANNEL
+            | Permissions::SEND_MESSAGES
+            | Permissions::EMBED_LINKS
+            | Permissions::ATTACH_FILES
+    }
+
+    #[test]
+    fn test_guild_permissions_union_of_roles() {
+        let roles = vec![
+            everyone(Permissions::VIEW_CHANNEL),
+            role(ROLE_A, Permissions::KICK_MEMBERS),
+        ];
+        let perms = guild_permissions(&guild(), UserId(USER_ID), &roles);
+        assert_eq!(perms, Permissions::VIEW_CHANNEL | Permissions::KICK_MEMBERS);
+    }
+
+    #[test]
+    fn test_guild_permissions_owner_has_all() {
+        let roles = vec![everyone(Permissions::empty())];
+        let perms = guild_permissions(&guild(), UserId(OWNER_ID), &roles);
+        assert_eq!(perms, Permissions::all());
+    }
+
+    #[test]
+    fn test_channel_permissions_no_overwrites() {
+        let roles = vec![everyone(base())];
+        let ch = channel(CHANNEL_ID, None, vec![]);
+        let perms = channel_permissions(&guild(), &ch, UserId(USER_ID), &roles);
+        assert_eq!(perms, base());
+    }
+
+    #[test]
+    fn test_channel_permissions_administrator_bypasses_overwrites() {
+        let roles = vec![everyone(base()), role(ROLE_A, Permissions::ADMINISTRATOR)];
+        let ch = channel(
+            CHANNEL_ID,
+            None,
+            vec![
+                role_overwrite(GUILD_ID, Permissions::empty(), Permissions::all()),
+                member_overwrite(USER_ID, Permissions::empty(), Permissions::all()),
+            ],
+        );
+        let perms = channel_permissions(&guild(), &ch, UserId(USER_ID), &roles);
+        assert_eq!(perms, Permissions::all());
+    }
+
+    #[test]
+    fn test_channel_permissions_owner_bypasses_overwrites() {
+        let roles = vec![everyone(base())];
+        let ch = channel(
+            CHANNEL_ID,
+            None,
+            vec![member_overwrite(
+                OWNER_ID,
+                Permissions::empty(),
+                Permissions::all(),
+            )],
+        );
+        let perms = channel_permissions(&guild(), &ch, UserId(OWNER_ID), &roles);
+        assert_eq!(perms, Permissions::all());
+    }
+
+    #[test]
+    fn test_channel_permissions_everyone_overwrite() {
+        let roles = vec![everyone(base())];
+        let ch = channel(
+            CHANNEL_ID,
+            None,
+            vec![role_overwrite(
+                GUILD_ID,
+                Permissions::ADD_REACTIONS,
+                Permissions::ATTACH_FILES,
+            )],
+        );
+        let perms = channel_permissions(&guild(), &ch, UserId(USER_ID), &roles);
+        assert_eq!(
+            perms,
+            (base() - Permissions::ATTACH_FILES) | Permissions::ADD_REACTIONS
+        );
+    }
+
+    #[test]
+    fn test_channel_permissions_role_allow_beats_role_deny() {
+        // Role overwrites are combined before being applied, so an allow from any role wins
+        // over a deny from another role.
+        let roles = vec![
+            everyone(base()),
+            role(ROLE_A, Permissions::empty()),
+            role(ROLE_B, Permissions::empty()),
+        ];
+        let ch = channel(
+            CHANNEL_ID,
+            None,
+            vec![
+                role_overwrite(ROLE_A, Permissions::empty(), Permissions::EMBED_LINKS),
+                role_overwrite(ROLE_B, Permissions::EMBED_LINKS, Permissions::empty()),
+            ],
+        );
+        let perms = channel_permissions(&guild(), &ch, UserId(USER_ID), &roles);
+        assert_eq!(perms, base());
+    }
+
+    #[test]
+    fn test_channel_permissions_role_overwrite_beats_everyone() {
+        let roles = vec![everyone(base()), role(ROLE_A, Permissions::empty())];
+        let ch = channel(
+            CHANNEL_ID,
+            None,
+            vec![
+                role_overwrite(GUILD_ID, Permissions::empty(), Permissions::SEND_MESSAGES),
+                role_overwrite(ROLE_A, Permissions::SEND_MESSAGES, Permissions::empty()),
+            ],
+        );
+        let perms = channel_permissions(&guild(), &ch, UserId(USER_ID), &roles);
+        assert_eq!(perms, base());
+    }
+
+    #[test]
+    fn test_channel_permissions_ignores_roles_not_held() {
+        let roles = vec![everyone(base())];
+        let ch = channel(
+            CHANNEL_ID,
+            None,
+            vec![role_overwrite(
+                ROLE_A,
+                Permissions::MANAGE_MESSAGES,
+                Permissions::empty(),
+            )],
+        );
+        let perms = channel_permissions(&guild(), &ch, UserId(USER_ID), &roles);
+        assert_eq!(perms, base());
+    }
+
+    #[test]
+    fn test_channel_permissions_member_overwrite_beats_roles() {
+        let roles = vec![everyone(base()), role(ROLE_A, Permissions::empty())];
+        let ch = channel(
+            CHANNEL_ID,
+            None,
+            vec![
+                role_overwrite(ROLE_A, Permissions::MANAGE_MESSAGES, Permissions::empty()),
+                member_overwrite(USER_ID, Permissions::empty(), Permissions::MANAGE_MESSAGES),
+            ],
+        );
+        let perms = channel_permissions(&guild(), &ch, UserId(USER_ID), &roles);
+        assert_eq!(perms, base());
+    }
+
+    #[test]
+    fn test_channel_permissions_member_overwrite_for_other_user() {
+        let roles = vec![everyone(base())];
+        let ch = channel(
+            CHANNEL_ID,
+            None,
+            vec![member_overwrite(
+                OWNER_ID + 100,
+                Permissions::empty(),
+                Permissions::all(),
+            )],
+        );
+        let perms = channel_permissions(&guild(), &ch, UserId(USER_ID), &roles);
+        assert_eq!(perms, base());
+    }
+
+    #[test]
+    fn test_channel_permissions_no_view_channel_denies_all() {
+        let roles = vec![everyone(base() | Permissions::MANAGE_MESSAGES)];
+        let ch = channel(
+            CHANNEL_ID,
+            None,
+            vec![role_overwrite(
+                GUILD_ID,
+                Permissions::empty(),
+                Permissions::VIEW_CHANNEL,
+            )],
+        );
+        let perms = channel_permissions(&guild(), &ch, UserId(USER_ID), &roles);
+        assert_eq!(perms, Permissions::empty());
+    }
+
+    #[test]
+    fn test_channel_permissions_no_send_messages_denies_message_permissions() {
+        let roles = vec![everyone(base() | Permissions::MENTION_EVERYONE)];
+        let ch = channel(
+            CHANNEL_ID,
+            None,
+            vec![role_overwrite(
+                GUILD_ID,
+                Permissions::empty(),
+                Permissions::SEND_MESSAGES,
+            )],
+        );
+        let perms = channel_permissions(&guild(), &ch, UserId(USER_ID), &roles);
+        assert_eq!(perms, Permissions::VIEW_CHANNEL);
+    }
+
+    #[test]
+    fn test_channel_permissions_synced_channel_matches_category() {
+        // A channel synced with its category has a copy of the category's overwrites.
+        let roles = vec![everyone(base()), role(ROLE_A, Permissions::empty())];
+        let overwrites = vec![
+            role_overwrite(GUILD_ID, Permissions::empty(), Permissions::ATTACH_FILES),
+            role_overwrite(ROLE_A, Permissions::MANAGE_MESSAGES, Permissions::empty()),
+        ];
+        let category = channel(CATEGORY_ID, None, overwrites.clone());
+        let child = channel(CHANNEL_ID, Some(CATEGORY_ID), overwrites);
+        let user = UserId(USER_ID);
+        let expected = (base() - Permissions::ATTACH_FILES) | Permissions::MANAGE_MESSAGES;
+        assert_eq!(
+            channel_permissions(&guild(), &category, user, &roles),
+            expected
+        );
+        assert_eq!(
+            channel_permissions(&guild(), &child, user, &roles),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_channel_permissions_unsynced_channel_ignores_category() {
+        let roles = vec![everyone(base())];
+        let child = channel(CHANNEL_ID, Some(CATEGORY_ID), vec![]);
+        let perms = channel_permissions(&guild(), &child, UserId(USER_ID), &roles);
+        assert_eq!(perms, base());
+    }
+}
@@ -0,0 +1,722 @@
+use async_trait::async_trait;
+use redis::{ErrorKind, FromRedisValue, RedisError, RedisResult, ToRedisArgs};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The subset of Redis operations used by the typed cache APIs. Keys, hash fields and values are
+/// all raw bytes, encoded the same way regardless of the backend.
+///
+/// Implementations must match Redis's semantics exactly, including expiry: a SET without a TTL
+/// clears any existing one, hash writes leave it untouched, and hashes left empty are removed.
+#[async_trait]
+pub trait Storage: Send {
+    /// GET: Fetches a string value.
+    async fn get(&mut self, key: Vec<u8>) -> RedisResult<Option<Vec<u8>>>;
+
+    /// SET/SETEX: Sets a string value, expiring after `ttl` if provided.
+    async fn set(&mut self, key: Vec<u8>, value: Vec<u8>, ttl: Option<Duration>)
+        -> RedisResult<()>;
+
+    /// DEL: Deletes keys of any type.
+    async fn del(&mut self, keys: Vec<Vec<u8>>) -> RedisResult<()>;
+
+    /// HMGET: Fetches multiple fields from a hash, in the same order as provided.
+    async fn hget(
+        &mut self,
+        key: Vec<u8>,
+        fields: Vec<Vec<u8>>,
+    ) -> RedisResult<Vec<Option<Vec<u8>>>>;
+
+    /// HGETALL: Fetches all fields of a hash.
+    async fn hgetall(&mut self, key: Vec<u8>) -> RedisResult<HashMap<Vec<u8>, Vec<u8>>>;
+
+    /// HSET: Sets multiple fields in a hash.
+    async fn hset(&mut self, key: Vec<u8>, entries: Vec<(Vec<u8>, Vec<u8>)>) -> RedisResult<()>;
+
+    /// HDEL: Deletes multiple fields from a hash.
+    async fn hdel(&mut self, key: Vec<u8>, fields: Vec<Vec<u8>>) -> RedisResult<()>;
+
+    /// Atomically replaces the entire contents of a hash. Equivalent to a DEL followed by a HSET
+    /// in a MULTI transaction.
+    async fn hreplace(&mut self, key: Vec<u8>, entries: Vec<(Vec<u8>, Vec<u8>)>)
+        -> RedisResult<()>;
+
+    /// SADD: Adds multiple members to a set.
+    async fn sadd(&mut self, key: Vec<u8>, members: Vec<Vec<u8>>) -> RedisResult<()>;
+
+    /// SISMEMBER: Checks if a value is a member of a set.
+    async fn sismember(&mut self, key: Vec<u8>, member: Vec<u8>) -> RedisResult<bool>;
+
+    /// SREM: Removes multiple members from a set.
+    async fn srem(&mut self, key: Vec<u8>, members: Vec<Vec<u8>>) -> RedisResult<()>;
+
+    /// SMEMBERS: Fetches every member of a set.
+    async fn smembers(&mut self, key: Vec<u8>) -> RedisResult<Vec<Vec<u8>>>;
+
+    /// Adds multiple members to a set and sets the whole set to expire after `ttl`. Equivalent to
+    /// a SADD followed by an EXPIRE in a MULTI transaction.
+    async fn sadd_expire(
+        &mut self,
+        key: Vec<u8>,
+        members: Vec<Vec<u8>>,
+        ttl: Duration,
+    ) -> RedisResult<()>;
+
+    /// SCAN: Lists every key that starts with a given prefix. This walks the entire keyspace, so
+    /// it should be reserved for rare maintenance operations.
+    async fn scan_prefix(&mut self, prefix: Vec<u8>) -> RedisResult<Vec<Vec<u8>>>;
+}
+
+/// The number of keys Redis is asked to inspect per SCAN call.
+const SCAN_BATCH_SIZE: usize = 1000;
+
+/// Encodes a single Redis argument into its raw byte form.
+pub(crate) fn encode<T: ToRedisArgs>(arg: T) -> Vec<u8> {
+    let mut args = arg.to_redis_args();
+    debug_assert_eq!(args.len(), 1, "Expected a single Redis argument");
+    args.remove(0)
+}
+
+/// Decodes a raw byte value into a given type.
+pub(crate) fn decode<T: FromRedisValue>(data: Vec<u8>) -> RedisResult<T> {
+    T::from_redis_value(&redis::Value::Data(data))
+}
+
+/// Rounds a TTL up to whole seconds, the resolution of SETEX and EXPIRE. Sub-second TTLs would
+/// otherwise round down to 0, which Redis either rejects or treats as expiring immediately.
+fn round_ttl(ttl: Duration) -> Duration {
+    let secs = ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0);
+    Duration::from_secs(secs.max(1))
+}
+
+#[async_trait]
+impl Storage for crate::RedisPool {
+    async fn get(&mut self, key: Vec<u8>) -> RedisResult<Option<Vec<u8>>> {
+        redis::Cmd::get(key).query_async(self).await
+    }
+
+    async fn set(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> RedisResult<()> {
+        let cmd = match ttl {
+            Some(ttl) => redis::Cmd::set_ex(key, value, round_ttl(ttl).as_secs() as usize),
+            None => redis::Cmd::set(key, value),
+        };
+        cmd.query_async(self).await
+    }
+
+    async fn del(&mut self, keys: Vec<Vec<u8>>) -> RedisResult<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        redis::Cmd::del(keys).query_async(self).await
+    }
+
+    async fn hget(
+        &mut self,
+        key: Vec<u8>,
+        fields: Vec<Vec<u8>>,
+    ) -> RedisResult<Vec<Option<Vec<u8>>>> {
+        if fields.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Always use HMGET, HGET returns a single value instead of an array for one field.
+        redis::cmd("HMGET")
+            .arg(key)
+            .arg(fields)
+            .query_async(self)
+            .await
+    }
+
+    async fn hgetall(&mut self, key: Vec<u8>) -> RedisResult<HashMap<Vec<u8>, Vec<u8>>> {
+        redis::Cmd::hgetall(key).query_async(self).await
+    }
+
+    async fn hset(&mut self, key: Vec<u8>, entries: Vec<(Vec<u8>, Vec<u8>)>) -> RedisResult<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        redis::Cmd::hset_multiple(key, &entries)
+            .query_async(self)
+            .await
+    }
+
+    async fn hdel(&mut self, key: Vec<u8>, fields: Vec<Vec<u8>>) -> RedisResult<()> {
+        if fields.is_empty() {
+            return Ok(());
+        }
+        redis::Cmd::hdel(key, fields).query_async(self).await
+    }
+
+    async fn hreplace(
+        &mut self,
+        key: Vec<u8>,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> RedisResult<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(&key).ignore();
+        if !entries.is_empty() {
+            pipe.hset_multiple(&key, &entries).ignore();
+        }
+        pipe.query_async(self).await
+    }
+
+    async fn sadd(&mut self, key: Vec<u8>, members: Vec<Vec<u8>>) -> RedisResult<()> {
+        if members.is_empty() {
+            return Ok(());
+        }
+        redis::Cmd::sadd(key, members).query_async(self).await
+    }
+
+    async fn sismember(&mut self, key: Vec<u8>, member: Vec<u8>) -> RedisResult<bool> {
+        redis::Cmd::sismember(key, member).query_async(self).await
+    }
+
+    async fn srem(&mut self, key: Vec<u8>, members: Vec<Vec<u8>>) -> RedisResult<()> {
+        if members.is_empty() {
+            return Ok(());
+        }
+        redis::Cmd::srem(key, members).query_async(self).await
+    }
+
+    async fn smembers(&mut self, key: Vec<u8>) -> RedisResult<Vec<Vec<u8>>> {
+        redis::Cmd::smembers(key).query_async(self).await
+    }
+
+    async fn sadd_expire(
+        &mut self,
+        key: Vec<u8>,
+        members: Vec<Vec<u8>>,
+        ttl: Duration,
+    ) -> RedisResult<()> {
+        if members.is_empty() {
+            return Ok(());
+        }
+        redis::pipe()
+            .atomic()
+            .sadd(&key, members)
+            .ignore()
+            .expire(&key, round_ttl(ttl).as_secs() as usize)
+            .ignore()
+            .query_async(self)
+            .await
+    }
+
+    async fn scan_prefix(&mut self, prefix: Vec<u8>) -> RedisResult<Vec<Vec<u8>>> {
+        // Escape any glob characters in the prefix so it is matched literally.
+        let mut pattern = Vec::with_capacity(prefix.len() * 2 + 1);
+        for byte in prefix {
+            if b"*?[]\\".contains(&byte) {
+                pattern.push(b'\\');
+            }
+            pattern.push(byte);
+        }
+        pattern.push(b'*');
+
+        let mut keys = Vec::new();
+        let mut cursor = 0_u64;
+        loop {
+            let (next, batch): (u64, Vec<Vec<u8>>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(SCAN_BATCH_SIZE)
+                .query_async(self)
+                .await?;
+            keys.extend(batch);
+            if next == 0 {
+                // SCAN may return the same key more than once.
+                keys.sort();
+                keys.dedup();
+                return Ok(keys);
+            }
+            cursor = next;
+        }
+    }
+}
+
+enum Value {
+    String(Vec<u8>),
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+    Set(HashSet<Vec<u8>>),
+}
+
+struct Entry {
+    value: Value,
+    expires_at: Option<Instant>,
+}
+
+#[derive(Default)]
+struct InMemoryState {
+    entries: HashMap<Vec<u8>, Entry>,
+    time_offset: Duration,
+}
+
+impl InMemoryState {
+    fn now(&self) -> Instant {
+        Instant::now() + self.time_offset
+    }
+
+    /// Gets a live entry, lazily evicting it if it has expired.
+    fn entry(&mut self, key: &[u8]) -> Option<&mut Entry> {
+        let now = self.now();
+        let expired = match self.entries.get(key) {
+            Some(entry) => entry.expires_at.map(|t| t <= now).unwrap_or(false),
+            None => return None,
+        };
+        if expired {
+            self.entries.remove(key);
+            None
+        } else {
+            self.entries.get_mut(key)
+        }
+    }
+
+    fn hash(&mut self, key: &[u8]) -> RedisResult<Option<&mut HashMap<Vec<u8>, Vec<u8>>>> {
+        match self.entry(key) {
+            Some(Entry {
+                value: Value::Hash(hash),
+                ..
+            }) => Ok(Some(hash)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+
+    fn set(&mut self, key: &[u8]) -> RedisResult<Option<&mut HashSet<Vec<u8>>>> {
+        match self.entry(key) {
+            Some(Entry {
+                value: Value::Set(set),
+                ..
+            }) => Ok(Some(set)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+}
+
+fn wrong_type() -> RedisError {
+    RedisError::from((
+        ErrorKind::ExtensionError,
+        "WRONGTYPE",
+        "Operation against a key holding the wrong kind of value".to_owned(),
+    ))
+}
+
+/// An in-process implementation of Storage. Cheap to clone, all clones share the same data.
+///
+/// Intended for tests: nothing is persisted or shared across processes.
+#[derive(Clone, Default)]
+pub struct InMemoryStorage(Arc<Mutex<InMemoryState>>);
+
+impl InMemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the storage's clock forward, expiring any keys whose TTL has elapsed.
+    pub fn advance_time(&self, duration: Duration) {
+        self.0.lock().unwrap().time_offset += duration;
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut InMemoryState) -> R) -> R {
+        f(&mut self.0.lock().unwrap())
+    }
+}
+
+#[async_trait]
+impl Storage for InMemoryStorage {
+    async fn get(&mut self, key: Vec<u8>) -> RedisResult<Option<Vec<u8>>> {
+        self.with_state(|state| match state.entry(&key) {
+            Some(Entry {
+                value: Value::String(value),
+                ..
+            }) => Ok(Some(value.clone())),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        })
+    }
+
+    async fn set(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> RedisResult<()> {
+        self.with_state(|state| {
+            let expires_at = ttl.map(|ttl| state.now() + round_ttl(ttl));
+            state.entries.insert(
+                key,
+                Entry {
+                    value: Value::String(value),
+                    expires_at,
+                },
+            );
+            Ok(())
+        })
+    }
+
+    async fn del(&mut self, keys: Vec<Vec<u8>>) -> RedisResult<()> {
+        self.with_state(|state| {
+            for key in keys {
+                state.entries.remove(&key);
+            }
+            Ok(())
+        })
+    }
+
+    async fn hget(
+        &mut self,
+        key: Vec<u8>,
+        fields: Vec<Vec<u8>>,
+    ) -> RedisResult<Vec<Option<Vec<u8>>>> {
+        self.with_state(|state| {
+            let hash = state.hash(&key)?;
+            Ok(fields
+                .iter()
+                .map(|field| hash.as_ref().and_then(|h| h.get(field).cloned()))
+                .collect())
+        })
+    }
+
+    async fn hgetall(&mut self, key: Vec<u8>) -> RedisResult<HashMap<Vec<u8>, Vec<u8>>> {
+        self.with_state(|state| Ok(state.hash(&key)?.cloned().unwrap_or_default()))
+    }
+
+    async fn hset(&mut self, key: Vec<u8>, entries: Vec<(Vec<u8>, Vec<u8>)>) -> RedisResult<()> {
+        self.with_state(|state| {
+            if entries.is_empty() {
+                return Ok(());
+            }
+            if let Some(hash) = state.hash(&key)? {
+                hash.extend(entries);
+            } else {
+                let entry = Entry {
+                    value: Value::Hash(entries.into_iter().collect()),
+                    expires_at: None,
+                };
+                state.entries.insert(key, entry);
+            }
+            Ok(())
+        })
+    }
+
+    async fn hdel(&mut self, key: Vec<u8>, fields: Vec<Vec<u8>>) -> RedisResult<()> {
+        self.with_state(|state| {
+            let empty = match state.hash(&key)? {
+                Some(hash) => {
+                    for field in fields.iter() {
+                        hash.remove(field);
+                    }
+                    hash.is_empty()
+                }
+                None => false,
+            };
+            if empty {
+                state.entries.remove(&key);
+            }
+            Ok(())
+        })
+    }
+
+    async fn hreplace(
+        &mut self,
+        key: Vec<u8>,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> RedisResult<()> {
+        self.with_state(|state| {
+            state.entries.remove(&key);
+            if !entries.is_empty() {
+                let entry = Entry {
+                    value: Value::Hash(entries.into_iter().collect()),
+                    expires_at: None,
+                };
+                state.entries.insert(key, entry);
+            }
+            Ok(())
+        })
+    }
+
+    async fn sadd(&mut self, key: Vec<u8>, members: Vec<Vec<u8>>) -> RedisResult<()> {
+        self.with_state(|state| {
+            if members.is_empty() {
+                return Ok(());
+            }
+            if let Some(set) = state.set(&key)? {
+                set.extend(members);
+            } else {
+                let entry = Entry {
+                    value: Value::Set(members.into_iter().collect()),
+                    expires_at: None,
+                };
+                state.entries.insert(key, entry);
+            }
+            Ok(())
+        })
+    }
+
+    async fn sismember(&mut self, key: Vec<u8>, member: Vec<u8>) -> RedisResult<bool> {
+        self.with_state(|state| {
+            Ok(state
+                .set(&key)?
+                .map(|set| set.contains(&member))
+                .unwrap_or(false))
+        })
+    }
+
+    async fn srem(&mut self, key: Vec<u8>, members: Vec<Vec<u8>>) -> RedisResult<()> {
+        self.with_state(|state| {
+            let empty = match state.set(&key)? {
+                Some(set) => {
+                    for member in members.iter() {
+                        set.remove(member);
+                    }
+                    set.is_empty()
+                }
+                None => false,
+            };
+            if empty {
+                state.entries.remove(&key);
+            }
+            Ok(())
+        })
+    }
+
+    async fn smembers(&mut self, key: Vec<u8>) -> RedisResult<Vec<Vec<u8>>> {
+        self.with_state(|state| {
+            Ok(state
+                .set(&key)?
+                .map(|set| set.iter().cloned().collect())
+                .unwrap_or_default())
+        })
+    }
+
+    async fn sadd_expire(
+        &mut self,
+        key: Vec<u8>,
+        members: Vec<Vec<u8>>,
+        ttl: Duration,
+    ) -> RedisResult<()> {
+        if members.is_empty() {
+            return Ok(());
+        }
+        self.sadd(key.clone(), members).await?;
+        self.with_state(|state| {
+            let expires_at = state.now() + round_ttl(ttl);
+            if let Some(entry) = state.entry(&key) {
+                entry.expires_at = Some(expires_at);
+            }
+            Ok(())
+        })
+    }
+
+    async fn scan_prefix(&mut self, prefix: Vec<u8>) -> RedisResult<Vec<Vec<u8>>> {
+        self.with_state(|state| {
+            let keys: Vec<Vec<u8>> = state
+                .entries
+                .keys()
+                .filter(|key| key.starts_with(&prefix))
+                .cloned()
+                .collect();
+            // Only return keys that have not expired.
+            Ok(keys
+                .into_iter()
+                .filter(|key| state.entry(key).is_some())
+                .collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(value: &str) -> Vec<u8> {
+        value.as_bytes().to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_set_and_get() {
+        let mut storage = InMemoryStorage::new();
+        assert_eq!(storage.get(key("a")).await.unwrap(), None);
+        storage.set(key("a"), key("1"), None).await.unwrap();
+        assert_eq!(storage.get(key("a")).await.unwrap(), Some(key("1")));
+        storage.del(vec![key("a")]).await.unwrap();
+        assert_eq!(storage.get(key("a")).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_set_expires_after_ttl() {
+        let mut storage = InMemoryStorage::new();
+        let ttl = Duration::from_secs(60);
+        storage.set(key("a"), key("1"), Some(ttl)).await.unwrap();
+        storage.advance_time(Duration::from_secs(59));
+        assert_eq!(storage.get(key("a")).await.unwrap(), Some(key("1")));
+        storage.advance_time(Duration::from_secs(1));
+        assert_eq!(storage.get(key("a")).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_sub_second_ttls_round_up() {
+        assert_eq!(round_ttl(Duration::from_millis(1)), Duration::from_secs(1));
+        assert_eq!(
+            round_ttl(Duration::from_millis(1500)),
+            Duration::from_secs(2)
+        );
+        assert_eq!(round_ttl(Duration::from_secs(2)), Duration::from_secs(2));
+
+        let mut storage = InMemoryStorage::new();
+        let ttl = Duration::from_millis(500);
+        storage.set(key("a"), key("1"), Some(ttl)).await.unwrap();
+        storage.advance_time(ttl);
+        assert_eq!(storage.get(key("a")).await.unwrap(), Some(key("1")));
+        storage.advance_time(ttl);
+        assert_eq!(storage.get(key("a")).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_set_without_ttl_clears_ttl() {
+        let mut storage = InMemoryStorage::new();
+        let ttl = Duration::from_secs(60);
+        storage.set(key("a"), key("1"), Some(ttl)).await.unwrap();
+        storage.set(key("a"), key("2"), None).await.unwrap();
+        storage.advance_time(Duration::from_secs(120));
+        assert_eq!(storage.get(key("a")).await.unwrap(), Some(key("2")));
+    }
+
+    #[tokio::test]
+    async fn test_hash_operations() {
+        let mut storage = InMemoryStorage::new();
+        storage
+            .hset(key("h"), vec![(key("a"), key("1")), (key("b"), key("2"))])
+            .await
+            .unwrap();
+        let values = storage
+            .hget(key("h"), vec![key("b"), key("c"), key("a")])
+            .await
+            .unwrap();
+        assert_eq!(values, vec![Some(key("2")), None, Some(key("1"))]);
+        storage
+            .hset(key("h"), vec![(key("a"), key("3"))])
+            .await
+            .unwrap();
+        storage.hdel(key("h"), vec![key("b")]).await.unwrap();
+        let all = storage.hgetall(key("h")).await.unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all.get(&key("a")), Some(&key("3")));
+    }
+
+    #[tokio::test]
+    async fn test_hdel_removes_empty_hash() {
+        let mut storage = InMemoryStorage::new();
+        storage
+            .hset(key("h"), vec![(key("a"), key("1"))])
+            .await
+            .unwrap();
+        storage.hdel(key("h"), vec![key("a")]).await.unwrap();
+        // The key no longer exists, so it can be reused as a string.
+        storage.set(key("h"), key("1"), None).await.unwrap();
+        assert_eq!(storage.get(key("h")).await.unwrap(), Some(key("1")));
+    }
+
+    #[tokio::test]
+    async fn test_hreplace_replaces_all_fields() {
+        let mut storage = InMemoryStorage::new();
+        storage
+            .hset(key("h"), vec![(key("a"), key("1")), (key("b"), key("2"))])
+            .await
+            .unwrap();
+        storage
+            .hreplace(key("h"), vec![(key("c"), key("3"))])
+            .await
+            .unwrap();
+        let all = storage.hgetall(key("h")).await.unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all.get(&key("c")), Some(&key("3")));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_type() {
+        let mut storage = InMemoryStorage::new();
+        storage.set(key("a"), key("1"), None).await.unwrap();
+        assert!(storage.hgetall(key("a")).await.is_err());
+        assert!(storage
+            .hset(key("a"), vec![(key("b"), key("2"))])
+            .await
+            .is_err());
+        storage
+            .hset(key("h"), vec![(key("b"), key("2"))])
+            .await
+            .unwrap();
+        assert!(storage.get(key("h")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_operations() {
+        let mut storage = InMemoryStorage::new();
+        storage
+            .sadd(key("s"), vec![key("a"), key("b")])
+            .await
+            .unwrap();
+        assert!(storage.sismember(key("s"), key("a")).await.unwrap());
+        assert!(!storage.sismember(key("s"), key("c")).await.unwrap());
+        assert!(!storage.sismember(key("t"), key("a")).await.unwrap());
+        storage.srem(key("s"), vec![key("a")]).await.unwrap();
+        assert!(!storage.sismember(key("s"), key("a")).await.unwrap());
+        storage.srem(key("s"), vec![key("b")]).await.unwrap();
+        // The key no longer exists, so it can be reused as a string.
+        storage.set(key("s"), key("1"), None).await.unwrap();
+        assert!(storage.sismember(key("s"), key("1")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sadd_expire() {
+        let mut storage = InMemoryStorage::new();
+        let ttl = Duration::from_secs(60);
+        storage
+            .sadd_expire(key("s"), vec![key("a")], ttl)
+            .await
+            .unwrap();
+        storage.advance_time(Duration::from_secs(30));
+        // Adding more members pushes back the expiry of the whole set.
+        storage
+            .sadd_expire(key("s"), vec![key("b")], ttl)
+            .await
+            .unwrap();
+        storage.advance_time(Duration::from_secs(59));
+        let mut members = storage.smembers(key("s")).await.unwrap();
+        members.sort();
+        assert_eq!(members, vec![key("a"), key("b")]);
+        storage.advance_time(Duration::from_secs(1));
+        assert!(storage.smembers(key("s")).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_scan_prefix() {
+        let mut storage = InMemoryStorage::new();
+        storage.set(key("a1"), key("1"), None).await.unwrap();
+        storage
+            .set(key("a2"), key("2"), Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        storage
+            .hset(key("a3"), vec![(key("f"), key("3"))])
+            .await
+            .unwrap();
+        storage.set(key("b1"), key("4"), None).await.unwrap();
+
+        let mut keys = storage.scan_prefix(key("a")).await.unwrap();
+        keys.sort();
+        assert_eq!(keys, vec![key("a1"), key("a2"), key("a3")]);
+
+        storage.advance_time(Duration::from_secs(60));
+        let mut keys = storage.scan_prefix(key("a")).await.unwrap();
+        keys.sort();
+        assert_eq!(keys, vec![key("a1"), key("a3")]);
+    }
+}
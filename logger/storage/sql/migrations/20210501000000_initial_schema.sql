@@ -0,0 +1,123 @@
+-- Baseline schema. Every statement is idempotent so that databases created before migrations
+-- were introduced can adopt them without being rebuilt.
+
+CREATE TABLE IF NOT EXISTS admin_configs (
+    id bigint NOT NULL,
+    source_bans boolean NOT NULL,
+    is_blocked boolean NOT NULL,
+    CONSTRAINT admin_configs_pkey PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS aliases (
+    guild_id bigint NOT NULL,
+    name character varying(2000) NOT NULL,
+    content character varying(2000),
+    CONSTRAINT aliases_pkey PRIMARY KEY (guild_id, name)
+);
+
+CREATE UNLOGGED TABLE IF NOT EXISTS bans (
+    guild_id bigint NOT NULL,
+    user_id bigint NOT NULL,
+    reason text,
+    avatar text,
+    CONSTRAINT bans_pkey PRIMARY KEY (guild_id, user_id)
+);
+CREATE INDEX IF NOT EXISTS bans_guild_id_idx ON bans USING btree (guild_id);
+CREATE INDEX IF NOT EXISTS bans_user_id_idx ON bans USING btree (user_id);
+
+CREATE TABLE IF NOT EXISTS escalation_histories (
+    id serial NOT NULL,
+    guild_id bigint NOT NULL,
+    subject_id bigint NOT NULL,
+    authorizer_id bigint NOT NULL,
+    authorizer_name character varying(255) NOT NULL,
+    display_name character varying(2000) NOT NULL,
+    "timestamp" timestamp with time zone NOT NULL,
+    action bytea NOT NULL,
+    level_delta integer NOT NULL,
+    CONSTRAINT escalation_histories_pkey PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS feeds (
+    id serial NOT NULL,
+    type character varying(255) NOT NULL,
+    source character varying(8192) NOT NULL,
+    last_updated timestamp with time zone NOT NULL,
+    CONSTRAINT feeds_pkey PRIMARY KEY (id),
+    CONSTRAINT feeds_type_source_key UNIQUE (type, source)
+);
+
+CREATE TABLE IF NOT EXISTS feed_channels (
+    feed_id bigint,
+    channel_id bigint,
+    CONSTRAINT feed_channels_feed_id_fkey FOREIGN KEY (feed_id) REFERENCES feeds(id)
+);
+
+CREATE TABLE IF NOT EXISTS members (
+    guild_id bigint NOT NULL,
+    user_id bigint NOT NULL,
+    role_ids bigint[] NOT NULL,
+    nickname character varying(32),
+    present boolean DEFAULT false NOT NULL,
+    last_seen timestamp with time zone DEFAULT now() NOT NULL,
+    bot boolean DEFAULT false NOT NULL,
+    premium_since timestamp with time zone,
+    CONSTRAINT members_pkey PRIMARY KEY (guild_id, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS oauth (
+    refresh_token text NOT NULL,
+    user_id bigint NOT NULL,
+    access_token text NOT NULL,
+    expiration timestamp without time zone NOT NULL,
+    CONSTRAINT oauth_pkey PRIMARY KEY (refresh_token)
+);
+
+CREATE TABLE IF NOT EXISTS pending_actions (
+    id serial NOT NULL,
+    "timestamp" timestamp with time zone NOT NULL,
+    data bytea NOT NULL,
+    ts timestamp without time zone NOT NULL,
+    CONSTRAINT pending_actions_pkey PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS pending_deescalations (
+    user_id bigint NOT NULL,
+    guild_id bigint NOT NULL,
+    expiration timestamp with time zone NOT NULL,
+    amount bigint NOT NULL,
+    entry_id integer NOT NULL,
+    CONSTRAINT pending_deescalations_pkey PRIMARY KEY (user_id, guild_id),
+    CONSTRAINT pending_deescalations_entry_id_fkey
+        FOREIGN KEY (entry_id) REFERENCES escalation_histories(id)
+);
+
+CREATE TABLE IF NOT EXISTS tags (
+    guild_id bigint NOT NULL,
+    tag character varying(2000) NOT NULL,
+    response character varying(2000) NOT NULL,
+    CONSTRAINT tags_pkey PRIMARY KEY (guild_id, tag)
+);
+
+CREATE TABLE IF NOT EXISTS usernames (
+    user_id bigint NOT NULL,
+    "timestamp" timestamp with time zone DEFAULT now() NOT NULL,
+    name character varying(32) NOT NULL,
+    discriminator integer,
+    CONSTRAINT usernames_pkey PRIMARY KEY (user_id, "timestamp"),
+    CONSTRAINT idx_unique_username UNIQUE (user_id, name, discriminator)
+);
+CREATE INDEX IF NOT EXISTS idx_username_user_id ON usernames USING btree (user_id);
+
+-- The Grafana dashboards have read only access to every table. The role only exists in
+-- production deployments.
+DO $$
+BEGIN
+    IF EXISTS (SELECT FROM pg_roles WHERE rolname = 'grafana') THEN
+        GRANT SELECT ON
+            admin_configs, aliases, bans, escalation_histories, feed_channels, feeds,
+            members, pending_actions, pending_deescalations, tags, usernames
+        TO grafana;
+    END IF;
+END
+$$;
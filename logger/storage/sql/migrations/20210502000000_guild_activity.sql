@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS guild_activity (
+    guild_id bigint NOT NULL,
+    resolution integer NOT NULL,
+    bucket timestamp with time zone NOT NULL,
+    online_count integer NOT NULL,
+    member_count integer NOT NULL,
+    message_count integer NOT NULL,
+    CONSTRAINT guild_activity_pkey PRIMARY KEY (guild_id, resolution, bucket)
+);
+CREATE INDEX IF NOT EXISTS guild_activity_resolution_bucket_idx
+    ON guild_activity USING btree (resolution, bucket);
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT FROM pg_roles WHERE rolname = 'grafana') THEN
+        GRANT SELECT ON guild_activity TO grafana;
+    END IF;
+END
+$$;
@@ -0,0 +1,2 @@
+-- Pending actions were scheduled by "timestamp" but polled by "ts", which was never written.
+ALTER TABLE pending_actions DROP COLUMN IF EXISTS ts;
@@ -0,0 +1,25 @@
+-- Unlike bans, which only mirrors the bans currently in place, every ban is kept here
+-- permanently. Each row covers a single ban, from when it was placed until it was lifted.
+CREATE TABLE IF NOT EXISTS ban_history (
+    id bigserial NOT NULL,
+    guild_id bigint NOT NULL,
+    user_id bigint NOT NULL,
+    banned_at timestamp with time zone DEFAULT now() NOT NULL,
+    banned_by bigint,
+    reason text,
+    unbanned_at timestamp with time zone,
+    unbanned_by bigint,
+    CONSTRAINT ban_history_pkey PRIMARY KEY (id)
+);
+CREATE INDEX IF NOT EXISTS ban_history_user_id_idx ON ban_history USING btree (user_id);
+-- A user can only have one active ban in a guild at a time.
+CREATE UNIQUE INDEX IF NOT EXISTS ban_history_active_idx
+    ON ban_history USING btree (guild_id, user_id) WHERE unbanned_at IS NULL;
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT FROM pg_roles WHERE rolname = 'grafana') THEN
+        GRANT SELECT ON ban_history TO grafana;
+    END IF;
+END
+$$;
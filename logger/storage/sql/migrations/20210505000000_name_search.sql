@@ -0,0 +1,19 @@
+-- Fuzzy name matching. Names are compared by the trigram similarity of their forms normalized
+-- by `hourai_validation::normalize::name`, so that visually similar names, e.g. ones using
+-- homoglyphs from other scripts or leetspeak, compare equal. The normalized forms cannot be
+-- computed in Postgres, so they are set when names are logged, and backfilled in batches for
+-- older rows. The columns are nullable without defaults, so adding them does not rewrite the
+-- tables.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+ALTER TABLE usernames ADD COLUMN IF NOT EXISTS normalized_name text;
+CREATE INDEX IF NOT EXISTS usernames_normalized_name_trgm_idx
+    ON usernames USING gin (normalized_name gin_trgm_ops);
+
+ALTER TABLE members ADD COLUMN IF NOT EXISTS normalized_nickname text;
+CREATE INDEX IF NOT EXISTS members_normalized_nickname_trgm_idx
+    ON members USING gin (normalized_nickname gin_trgm_ops);
+-- Only covers the nicknames left to backfill, so it shrinks to nothing once they are.
+CREATE INDEX IF NOT EXISTS members_unnormalized_nickname_idx
+    ON members USING btree (nickname)
+    WHERE nickname IS NOT NULL AND normalized_nickname IS NULL;
@@ -0,0 +1,30 @@
+-- The last case number used in each guild. Case numbers are allocated by upserting into this
+-- table, which serializes concurrent allocations on the guild's row.
+CREATE TABLE IF NOT EXISTS case_counters (
+    guild_id bigint NOT NULL,
+    last_case integer NOT NULL,
+    CONSTRAINT case_counters_pkey PRIMARY KEY (guild_id)
+);
+
+-- A unified record of every moderation action taken against a user.
+CREATE TABLE IF NOT EXISTS cases (
+    guild_id bigint NOT NULL,
+    case_number integer NOT NULL,
+    user_id bigint NOT NULL,
+    moderator_id bigint,
+    action bytea NOT NULL,
+    reason varchar(2000),
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    modlog_channel_id bigint,
+    modlog_message_id bigint,
+    CONSTRAINT cases_pkey PRIMARY KEY (guild_id, case_number)
+);
+CREATE INDEX IF NOT EXISTS cases_user_id_idx ON cases USING btree (user_id);
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT FROM pg_roles WHERE rolname = 'grafana') THEN
+        GRANT SELECT ON case_counters, cases TO grafana;
+    END IF;
+END
+$$;
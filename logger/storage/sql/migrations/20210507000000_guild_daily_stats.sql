@@ -0,0 +1,20 @@
+-- Per-guild event counts, bucketed by UTC day.
+CREATE TABLE IF NOT EXISTS guild_daily_stats (
+    guild_id bigint NOT NULL,
+    day date NOT NULL,
+    joins integer DEFAULT 0 NOT NULL,
+    leaves integer DEFAULT 0 NOT NULL,
+    bans integer DEFAULT 0 NOT NULL,
+    messages integer DEFAULT 0 NOT NULL,
+    verification_passes integer DEFAULT 0 NOT NULL,
+    verification_failures integer DEFAULT 0 NOT NULL,
+    CONSTRAINT guild_daily_stats_pkey PRIMARY KEY (guild_id, day)
+);
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT FROM pg_roles WHERE rolname = 'grafana') THEN
+        GRANT SELECT ON guild_daily_stats TO grafana;
+    END IF;
+END
+$$;
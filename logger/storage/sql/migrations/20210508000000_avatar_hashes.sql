@@ -0,0 +1,25 @@
+-- Perceptual hashes of user avatars, used to find banned users that re-upload the same image
+-- with small changes. Unlike bans, this is not rebuilt on startup, as hashing an avatar requires
+-- downloading it.
+CREATE TABLE IF NOT EXISTS avatar_hashes (
+    user_id bigint NOT NULL,
+    avatar text NOT NULL,
+    hash bigint NOT NULL,
+    CONSTRAINT avatar_hashes_pkey PRIMARY KEY (user_id, avatar)
+);
+CREATE INDEX IF NOT EXISTS avatar_hashes_hash_idx ON avatar_hashes USING btree (hash);
+
+-- The number of bits that differ between two hashes.
+CREATE OR REPLACE FUNCTION hamming_distance(a bigint, b bigint) RETURNS integer
+    LANGUAGE sql IMMUTABLE STRICT PARALLEL SAFE
+    AS $$
+        SELECT length(replace((a # b)::bit(64)::text, '0', ''))
+    $$;
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT FROM pg_roles WHERE rolname = 'grafana') THEN
+        GRANT SELECT ON avatar_hashes TO grafana;
+    END IF;
+END
+$$;
@@ -0,0 +1,4 @@
+-- Exact matching of names that only differ by confusable or invisible characters, against the
+-- normalized names added for fuzzy matching.
+CREATE INDEX IF NOT EXISTS usernames_normalized_name_idx
+    ON usernames USING btree (normalized_name);
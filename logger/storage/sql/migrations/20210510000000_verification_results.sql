@@ -0,0 +1,30 @@
+-- Every verification decision made about a member: automatic runs of the guild's verifiers,
+-- and moderators overriding them. Written by the Python bot, which runs verification.
+CREATE TABLE IF NOT EXISTS verification_results (
+    id bigserial NOT NULL,
+    guild_id bigint NOT NULL,
+    user_id bigint NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    approved boolean NOT NULL,
+    reasons text[] NOT NULL,
+    -- A fingerprint of the guild's verification config at the time of the decision.
+    config_version bigint NOT NULL,
+    -- The moderator that made the decision. NULL for automatic decisions.
+    moderator_id bigint,
+    appealed_at timestamp with time zone,
+    CONSTRAINT verification_results_pkey PRIMARY KEY (id)
+);
+CREATE INDEX IF NOT EXISTS verification_results_guild_user_idx
+    ON verification_results USING btree (guild_id, user_id, created_at);
+CREATE INDEX IF NOT EXISTS verification_results_user_id_idx
+    ON verification_results USING btree (user_id, created_at);
+CREATE INDEX IF NOT EXISTS verification_results_moderator_id_idx
+    ON verification_results USING btree (moderator_id) WHERE moderator_id IS NOT NULL;
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT FROM pg_roles WHERE rolname = 'grafana') THEN
+        GRANT SELECT ON verification_results TO grafana;
+    END IF;
+END
+$$;
@@ -0,0 +1,26 @@
+-- Members that have not completed membership screening yet. Pending members cannot be given
+-- roles.
+ALTER TABLE members ADD COLUMN IF NOT EXISTS pending boolean DEFAULT false NOT NULL;
+
+-- In progress jobs giving a guild's verification role to its existing members. Members are
+-- processed in user ID order, so a job can resume after the last member it processed.
+CREATE TABLE IF NOT EXISTS verification_propagations (
+    guild_id bigint NOT NULL,
+    role_id bigint NOT NULL,
+    -- Where the job reports its progress.
+    channel_id bigint NOT NULL,
+    message_id bigint NOT NULL,
+    last_member_id bigint DEFAULT 0 NOT NULL,
+    total bigint NOT NULL,
+    granted bigint DEFAULT 0 NOT NULL,
+    started_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT verification_propagations_pkey PRIMARY KEY (guild_id)
+);
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT FROM pg_roles WHERE rolname = 'grafana') THEN
+        GRANT SELECT ON verification_propagations TO grafana;
+    END IF;
+END
+$$;
@@ -11,7 +11,7 @@ pub struct PendingAction {
 
 impl PendingAction {
     pub fn fetch_expired<'a>() -> SqlQueryAs<'a, Self> {
-        sqlx::query_as("SELECT id, data FROM pending_actions WHERE ts < now()")
+        sqlx::query_as("SELECT id, data FROM pending_actions WHERE timestamp < now()")
     }
 
     pub fn schedule<'a>(action: Action, timestamp: impl Into<DateTime<Utc>>) -> SqlQuery<'a> {
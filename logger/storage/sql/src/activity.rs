@@ -0,0 +1,191 @@
+use crate::models::{SqlQuery, SqlQueryAs};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hourai::models::id::GuildId;
+
+/// The maximum number of points that should be returned when fetching a range of activity.
+const MAX_POINTS: i64 = 2000;
+
+/// The width of the time buckets activity is recorded in. Recent activity is recorded at a fine
+/// resolution and is periodically downsampled into coarser buckets as it ages.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ActivityResolution {
+    FiveMinutes,
+    Hourly,
+    Daily,
+}
+
+impl ActivityResolution {
+    pub const ALL: [ActivityResolution; 3] = [Self::FiveMinutes, Self::Hourly, Self::Daily];
+
+    /// The width of a bucket, in seconds.
+    pub fn seconds(self) -> i64 {
+        match self {
+            Self::FiveMinutes => 300,
+            Self::Hourly => 3600,
+            Self::Daily => 86400,
+        }
+    }
+
+    /// How long buckets are kept at this resolution before being downsampled into the next
+    /// coarser one. None if the buckets are kept forever.
+    pub fn retention(self) -> Option<Duration> {
+        match self {
+            Self::FiveMinutes => Some(Duration::days(2)),
+            Self::Hourly => Some(Duration::days(60)),
+            Self::Daily => None,
+        }
+    }
+
+    /// The resolution buckets are downsampled into once they exceed their retention.
+    pub fn coarser(self) -> Option<Self> {
+        match self {
+            Self::FiveMinutes => Some(Self::Hourly),
+            Self::Hourly => Some(Self::Daily),
+            Self::Daily => None,
+        }
+    }
+
+    /// Picks the finest resolution that covers a span of time without returning an
+    /// excessive number of points.
+    pub fn for_span(span: Duration) -> Self {
+        Self::ALL
+            .iter()
+            .cloned()
+            .find(|res| span.num_seconds() / res.seconds() <= MAX_POINTS)
+            .unwrap_or(Self::Daily)
+    }
+
+    /// Rounds a timestamp down to the start of the bucket it falls in.
+    pub fn truncate(self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+        let secs = timestamp.timestamp();
+        Utc.timestamp(secs - secs.rem_euclid(self.seconds()), 0)
+    }
+}
+
+/// A single time bucket of a guild's activity.
+#[derive(Debug, sqlx::FromRow)]
+pub struct GuildActivity {
+    pub guild_id: i64,
+    pub resolution: i32,
+    pub bucket: DateTime<Utc>,
+    /// The number of members online at the end of the bucket. Averaged when downsampled.
+    pub online_count: i32,
+    /// The number of members in the guild at the end of the bucket. Averaged when downsampled.
+    pub member_count: i32,
+    /// The number of messages sent during the bucket.
+    pub message_count: i32,
+}
+
+impl GuildActivity {
+    pub fn new(guild_id: GuildId, resolution: ActivityResolution, bucket: DateTime<Utc>) -> Self {
+        Self {
+            guild_id: guild_id.0 as i64,
+            resolution: resolution.seconds() as i32,
+            bucket: resolution.truncate(bucket),
+            online_count: 0,
+            member_count: 0,
+            message_count: 0,
+        }
+    }
+
+    pub fn guild_id(&self) -> GuildId {
+        GuildId(self.guild_id as u64)
+    }
+
+    pub fn bulk_insert<'a>(samples: Vec<Self>) -> SqlQuery<'a> {
+        let guild_ids: Vec<i64> = samples.iter().map(|s| s.guild_id).collect();
+        let resolutions: Vec<i32> = samples.iter().map(|s| s.resolution).collect();
+        let buckets: Vec<DateTime<Utc>> = samples.iter().map(|s| s.bucket).collect();
+        let online: Vec<i32> = samples.iter().map(|s| s.online_count).collect();
+        let members: Vec<i32> = samples.iter().map(|s| s.member_count).collect();
+        let messages: Vec<i32> = samples.iter().map(|s| s.message_count).collect();
+        sqlx::query(
+            "INSERT INTO guild_activity \
+             (guild_id, resolution, bucket, online_count, member_count, message_count) \
+             SELECT * FROM UNNEST ($1, $2, $3, $4, $5, $6) \
+             AS t(guild_id, resolution, bucket, online_count, member_count, message_count) \
+             ON CONFLICT ON CONSTRAINT guild_activity_pkey \
+             DO UPDATE SET \
+                online_count = excluded.online_count, \
+                member_count = excluded.member_count, \
+                message_count = guild_activity.message_count + excluded.message_count",
+        )
+        .bind(guild_ids)
+        .bind(resolutions)
+        .bind(buckets)
+        .bind(online)
+        .bind(members)
+        .bind(messages)
+    }
+
+    /// Fetches a guild's activity between two timestamps, aggregated into buckets of the given
+    /// resolution. Buckets that have already been downsampled past the requested resolution are
+    /// returned as is.
+    pub fn fetch<'a>(
+        guild_id: GuildId,
+        resolution: ActivityResolution,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "SELECT \
+                guild_id, \
+                $2::integer AS resolution, \
+                to_timestamp(floor(extract(epoch FROM bucket) / $2::integer) * $2::integer) AS bucket, \
+                round(avg(online_count))::integer AS online_count, \
+                round(avg(member_count))::integer AS member_count, \
+                sum(message_count)::integer AS message_count \
+             FROM guild_activity \
+             WHERE guild_id = $1 AND bucket >= $3 AND bucket < $4 \
+             GROUP BY 1, 3 \
+             ORDER BY 3",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(resolution.seconds() as i32)
+        .bind(start)
+        .bind(end)
+    }
+
+    /// Aggregates all buckets of one resolution from before a timestamp into buckets of a
+    /// coarser resolution. `before` should be aligned to the coarser resolution so that no
+    /// bucket is only partially aggregated. The source buckets should be removed with
+    /// `delete_before` in the same transaction.
+    pub fn downsample<'a>(
+        from: ActivityResolution,
+        to: ActivityResolution,
+        before: DateTime<Utc>,
+    ) -> SqlQuery<'a> {
+        sqlx::query(
+            "INSERT INTO guild_activity \
+             (guild_id, resolution, bucket, online_count, member_count, message_count) \
+             SELECT \
+                guild_id, \
+                $2::integer, \
+                to_timestamp(floor(extract(epoch FROM bucket) / $2::integer) * $2::integer), \
+                round(avg(online_count))::integer, \
+                round(avg(member_count))::integer, \
+                sum(message_count)::integer \
+             FROM guild_activity \
+             WHERE resolution = $1 AND bucket < $3 \
+             GROUP BY 1, 3 \
+             ON CONFLICT ON CONSTRAINT guild_activity_pkey \
+             DO UPDATE SET \
+                online_count = excluded.online_count, \
+                member_count = excluded.member_count, \
+                message_count = excluded.message_count",
+        )
+        .bind(from.seconds() as i32)
+        .bind(to.seconds() as i32)
+        .bind(before)
+    }
+
+    /// Deletes all buckets of a resolution from before a timestamp.
+    pub fn delete_before<'a>(
+        resolution: ActivityResolution,
+        before: DateTime<Utc>,
+    ) -> SqlQuery<'a> {
+        sqlx::query("DELETE FROM guild_activity WHERE resolution = $1 AND bucket < $2")
+            .bind(resolution.seconds() as i32)
+            .bind(before)
+    }
+}
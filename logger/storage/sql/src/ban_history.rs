@@ -0,0 +1,123 @@
+use crate::models::{Ban, SqlQuery, SqlQueryAs};
+use chrono::{DateTime, Utc};
+use hourai::models::id::*;
+
+/// A single ban of a user from a guild, from when it was placed until it was lifted.
+///
+/// Unlike `Ban`, which only mirrors the bans currently in place, these are never deleted.
+#[derive(Debug, sqlx::FromRow)]
+pub struct BanHistory {
+    pub id: i64,
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub banned_at: DateTime<Utc>,
+    pub banned_by: Option<i64>,
+    pub reason: Option<String>,
+    /// None if the user is still banned.
+    pub unbanned_at: Option<DateTime<Utc>>,
+    pub unbanned_by: Option<i64>,
+}
+
+impl BanHistory {
+    pub fn guild_id(&self) -> GuildId {
+        GuildId(self.guild_id as u64)
+    }
+
+    pub fn user_id(&self) -> UserId {
+        UserId(self.user_id as u64)
+    }
+
+    pub fn banned_by(&self) -> Option<UserId> {
+        self.banned_by.map(|id| UserId(id as u64))
+    }
+
+    pub fn unbanned_by(&self) -> Option<UserId> {
+        self.unbanned_by.map(|id| UserId(id as u64))
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.unbanned_at.is_none()
+    }
+
+    /// Constructs a query to record a new ban. If the user is already banned, any missing
+    /// details of the active ban are filled in instead.
+    pub fn record_ban<'a>(
+        guild_id: GuildId,
+        user_id: UserId,
+        banned_by: Option<UserId>,
+        reason: Option<String>,
+    ) -> SqlQuery<'a> {
+        sqlx::query(
+            "INSERT INTO ban_history (guild_id, user_id, banned_by, reason) \
+             VALUES ($1, $2, $3, $4) \
+             ON CONFLICT (guild_id, user_id) WHERE unbanned_at IS NULL \
+             DO UPDATE SET \
+                banned_by = coalesce(ban_history.banned_by, excluded.banned_by), \
+                reason = coalesce(ban_history.reason, excluded.reason)",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(user_id.0 as i64)
+        .bind(banned_by.map(|id| id.0 as i64))
+        .bind(reason)
+    }
+
+    /// Constructs a query to mark a user's active ban in a guild as lifted.
+    pub fn record_unban<'a>(
+        guild_id: GuildId,
+        user_id: UserId,
+        unbanned_by: Option<UserId>,
+    ) -> SqlQuery<'a> {
+        sqlx::query(
+            "UPDATE ban_history SET unbanned_at = now(), unbanned_by = $3 \
+             WHERE guild_id = $1 AND user_id = $2 AND unbanned_at IS NULL",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(user_id.0 as i64)
+        .bind(unbanned_by.map(|id| id.0 as i64))
+    }
+
+    /// Constructs a query to record any of a guild's current bans that are missing from the
+    /// history, i.e. those placed while the bot was offline. The time of these bans is only
+    /// known to be no later than now, and who placed them is unknown.
+    pub fn reconcile_bans<'a>(guild_id: GuildId, bans: &[Ban]) -> SqlQuery<'a> {
+        let user_ids: Vec<i64> = bans.iter().map(|b| b.user_id).collect();
+        let reasons: Vec<Option<String>> = bans.iter().map(|b| b.reason.clone()).collect();
+        sqlx::query(
+            "INSERT INTO ban_history (guild_id, user_id, reason) \
+             SELECT $1, * FROM UNNEST ($2, $3) \
+             ON CONFLICT (guild_id, user_id) WHERE unbanned_at IS NULL \
+             DO NOTHING",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(user_ids)
+        .bind(reasons)
+    }
+
+    /// Constructs a query to mark all of a guild's active bans that are no longer in place as
+    /// lifted, i.e. those lifted while the bot was offline.
+    pub fn reconcile_unbans<'a>(guild_id: GuildId, bans: &[Ban]) -> SqlQuery<'a> {
+        let user_ids: Vec<i64> = bans.iter().map(|b| b.user_id).collect();
+        sqlx::query(
+            "UPDATE ban_history SET unbanned_at = now() \
+             WHERE guild_id = $1 AND unbanned_at IS NULL AND NOT (user_id = ANY($2))",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(user_ids)
+    }
+
+    /// Constructs a query to fetch every time a user has been banned across all guilds, oldest
+    /// first.
+    pub fn fetch_user<'a>(user_id: UserId) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as("SELECT * FROM ban_history WHERE user_id = $1 ORDER BY banned_at, id")
+            .bind(user_id.0 as i64)
+    }
+
+    /// Constructs a query to fetch every time a user has been banned from a guild, oldest first.
+    pub fn fetch_guild_user<'a>(guild_id: GuildId, user_id: UserId) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "SELECT * FROM ban_history WHERE guild_id = $1 AND user_id = $2 ORDER BY banned_at, id",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(user_id.0 as i64)
+    }
+}
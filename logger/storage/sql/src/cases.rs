@@ -0,0 +1,134 @@
+use crate::models::{SqlQuery, SqlQueryAs};
+use crate::types;
+use chrono::{DateTime, Utc};
+use hourai::models::id::*;
+use hourai::proto::action::Action;
+
+/// The maximum length of a case's reason, in characters. Matches Discord's limit for embed
+/// fields, so the reason always fits in the case's modlog message.
+pub const MAX_REASON_LENGTH: usize = 1024;
+
+/// A numbered record of a moderation action taken against a user in a guild.
+///
+/// Case numbers are sequential per guild, starting at 1.
+#[derive(Debug, sqlx::FromRow)]
+pub struct Case {
+    pub guild_id: i64,
+    pub case_number: i32,
+    pub user_id: i64,
+    /// None if the moderator is unknown, or the action was taken automatically.
+    pub moderator_id: Option<i64>,
+    action: types::Protobuf<Action>,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub modlog_channel_id: Option<i64>,
+    pub modlog_message_id: Option<i64>,
+}
+
+impl Case {
+    pub fn guild_id(&self) -> GuildId {
+        GuildId(self.guild_id as u64)
+    }
+
+    pub fn user_id(&self) -> UserId {
+        UserId(self.user_id as u64)
+    }
+
+    pub fn moderator_id(&self) -> Option<UserId> {
+        self.moderator_id.map(|id| UserId(id as u64))
+    }
+
+    pub fn action(&self) -> &Action {
+        &self.action.0
+    }
+
+    /// The channel and message the case was posted to in the guild's modlog, if any.
+    pub fn modlog_message(&self) -> Option<(ChannelId, MessageId)> {
+        match (self.modlog_channel_id, self.modlog_message_id) {
+            (Some(channel_id), Some(message_id)) => {
+                Some((ChannelId(channel_id as u64), MessageId(message_id as u64)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Constructs a query to open a new case under the guild's next case number, returning the
+    /// created case. The guild and user are taken from the action.
+    pub fn create<'a>(
+        action: Action,
+        moderator_id: Option<UserId>,
+        reason: Option<String>,
+    ) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "WITH counter AS ( \
+                INSERT INTO case_counters (guild_id, last_case) VALUES ($1, 1) \
+                ON CONFLICT ON CONSTRAINT case_counters_pkey \
+                DO UPDATE SET last_case = case_counters.last_case + 1 \
+                RETURNING last_case \
+             ) \
+             INSERT INTO cases (guild_id, case_number, user_id, moderator_id, action, reason) \
+             SELECT $1, last_case, $2, $3, $4, $5 FROM counter \
+             RETURNING *",
+        )
+        .bind(action.get_guild_id() as i64)
+        .bind(action.get_user_id() as i64)
+        .bind(moderator_id.map(|id| id.0 as i64))
+        .bind(types::Protobuf(action))
+        .bind(reason)
+    }
+
+    /// Constructs a query to record where the case was posted in the guild's modlog.
+    pub fn set_modlog_message<'a>(
+        guild_id: GuildId,
+        case_number: i32,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> SqlQuery<'a> {
+        sqlx::query(
+            "UPDATE cases SET modlog_channel_id = $3, modlog_message_id = $4 \
+             WHERE guild_id = $1 AND case_number = $2",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(case_number)
+        .bind(channel_id.0 as i64)
+        .bind(message_id.0 as i64)
+    }
+
+    /// Constructs a query to change the reason given for a case, returning the updated case.
+    /// Returns nothing if the case does not exist.
+    pub fn update_reason<'a>(
+        guild_id: GuildId,
+        case_number: i32,
+        reason: impl Into<String>,
+    ) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "UPDATE cases SET reason = $3 WHERE guild_id = $1 AND case_number = $2 RETURNING *",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(case_number)
+        .bind(reason.into())
+    }
+
+    /// Constructs a query to fetch a single case.
+    pub fn fetch<'a>(guild_id: GuildId, case_number: i32) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as("SELECT * FROM cases WHERE guild_id = $1 AND case_number = $2")
+            .bind(guild_id.0 as i64)
+            .bind(case_number)
+    }
+
+    /// Constructs a query to fetch all of the cases against a user in a guild, oldest first.
+    pub fn fetch_guild_user<'a>(guild_id: GuildId, user_id: UserId) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "SELECT * FROM cases WHERE guild_id = $1 AND user_id = $2 ORDER BY case_number",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(user_id.0 as i64)
+    }
+
+    /// Constructs a query to fetch all of the cases against a user across all guilds, oldest
+    /// first.
+    pub fn fetch_user<'a>(user_id: UserId) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as("SELECT * FROM cases WHERE user_id = $1 ORDER BY created_at, guild_id")
+            .bind(user_id.0 as i64)
+    }
+}
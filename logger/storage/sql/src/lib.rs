@@ -1,19 +1,38 @@
 pub mod actions;
+pub mod activity;
+pub mod ban_history;
+pub mod cases;
 mod models;
+mod pool;
+pub mod search;
+pub mod stats;
+pub mod tags;
 mod types;
+pub mod user_data;
+pub mod verification;
 
 pub use self::models::*;
+pub use self::pool::SqlPool;
 pub use sqlx::types as sql_types;
 pub use sqlx::*;
 use tracing::debug;
 
-pub type SqlPool = sqlx::Pool<SqlDatabase>;
+/// The versioned schema migrations, embedded from the `migrations` directory.
+pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
 
-pub async fn init(config: &hourai::config::HouraiConfig) -> sqlx::PgPool {
-    debug!("Creating Postgres client");
-    sqlx::postgres::PgPoolOptions::new()
-        .max_connections(3)
-        .connect(&config.database)
+/// Connects to the database with the pool settings configured for the given service, and
+/// applies any pending migrations.
+pub async fn init(config: &hourai::config::HouraiConfig, service: &'static str) -> SqlPool {
+    let pool = pool::connect_all(config, service).await;
+    migrate(pool.primary())
         .await
-        .expect("Failed to initialize SQL connection pool")
+        .expect("Failed to apply SQL migrations");
+    pool
+}
+
+/// Applies any pending migrations to the database. Concurrent callers are serialized with an
+/// advisory lock, so it is safe for every service to run this on startup.
+pub async fn migrate(pool: &PgPool) -> std::result::Result<(), sqlx::migrate::MigrateError> {
+    debug!("Applying SQL migrations");
+    MIGRATOR.run(pool).await
 }
@@ -23,7 +23,10 @@ pub struct Username {
     pub user_id: i64,
     pub timestamp: DateTime<Utc>,
     pub name: String,
-    pub discriminator: Option<u32>,
+    pub discriminator: Option<i32>,
+    /// The name as normalized by `hourai_validation::normalize::name`. None if the name has not
+    /// been normalized yet.
+    pub normalized_name: Option<String>,
 }
 
 impl Username {
@@ -32,21 +35,22 @@ impl Username {
             user_id: user.id().0 as i64,
             timestamp: Utc::now(),
             name: user.name().to_owned(),
-            discriminator: Some(user.discriminator() as u32),
+            discriminator: Some(user.discriminator() as i32),
+            normalized_name: None,
         }
     }
 
     pub fn fetch<'a>(user_id: UserId, limit: Option<u64>) -> SqlQueryAs<'a, Self> {
         if let Some(max) = limit {
             sqlx::query_as(
-                "SELECT user_id, timestamp, name, discriminator \
+                "SELECT user_id, timestamp, name, discriminator, normalized_name \
                  FROM usernames WHERE user_id = $1 LIMIT $2",
             )
             .bind(user_id.0 as i64)
             .bind(max as i64)
         } else {
             sqlx::query_as(
-                "SELECT user_id, timestamp, name, discriminator \
+                "SELECT user_id, timestamp, name, discriminator, normalized_name \
                  FROM usernames WHERE user_id = $1",
             )
             .bind(user_id.0 as i64)
@@ -55,30 +59,59 @@ impl Username {
 
     pub fn insert(&self) -> SqlQuery {
         sqlx::query(
-            "INSERT INTO usernames (user_id, name, discriminator) \
-                     VALUES ($1, $2, $3) \
+            "INSERT INTO usernames (user_id, name, discriminator, normalized_name) \
+                     VALUES ($1, $2, $3, $4) \
                      ON CONFLICT ON CONSTRAINT idx_unique_username \
                      DO NOTHING",
         )
         .bind(self.user_id)
         .bind(self.name.clone())
         .bind(self.discriminator)
+        .bind(self.normalized_name.clone())
     }
 
+    /// Constructs a query to bulk add multiple usernames. Each username is recorded with its own
+    /// timestamp, so a batch may contain multiple names for the same user.
     pub fn bulk_insert<'a>(usernames: Vec<Self>) -> SqlQuery<'a> {
         let user_ids: Vec<i64> = usernames.iter().map(|u| u.user_id).collect();
+        let timestamps: Vec<DateTime<Utc>> = usernames.iter().map(|u| u.timestamp).collect();
         let names: Vec<String> = usernames.iter().map(|u| u.name.clone()).collect();
-        let discriminator: Vec<Option<u32>> = usernames.iter().map(|u| u.discriminator).collect();
+        let discriminator: Vec<Option<i32>> = usernames.iter().map(|u| u.discriminator).collect();
+        let normalized: Vec<Option<String>> = usernames
+            .iter()
+            .map(|u| u.normalized_name.clone())
+            .collect();
         sqlx::query(
-            "INSERT INTO usernames (user_id, name, discriminator) \
-             SELECT * FROM UNNEST ($1, $2, $3) \
-             AS t(user_id, name, discriminator) \
+            "INSERT INTO usernames (user_id, timestamp, name, discriminator, normalized_name) \
+             SELECT * FROM UNNEST ($1, $2, $3, $4, $5) \
+             AS t(user_id, timestamp, name, discriminator, normalized_name) \
              ON CONFLICT ON CONSTRAINT idx_unique_username \
              DO NOTHING",
         )
         .bind(user_ids)
+        .bind(timestamps)
         .bind(names)
         .bind(discriminator)
+        .bind(normalized)
+    }
+
+    /// Fetches up to `limit` distinct names that have not been normalized yet.
+    pub fn fetch_unnormalized<'a>(limit: i64) -> SqlQueryAs<'a, (String,)> {
+        sqlx::query_as("SELECT DISTINCT name FROM usernames WHERE normalized_name IS NULL LIMIT $1")
+            .bind(limit)
+    }
+
+    /// Constructs a query to set the normalized form of every row with each of the given names.
+    /// Takes pairs of names and their normalized forms.
+    pub fn bulk_set_normalized<'a>(names: Vec<(String, String)>) -> SqlQuery<'a> {
+        let (names, normalized): (Vec<String>, Vec<String>) = names.into_iter().unzip();
+        sqlx::query(
+            "UPDATE usernames SET normalized_name = t.normalized_name \
+             FROM UNNEST ($1, $2) AS t(name, normalized_name) \
+             WHERE usernames.name = t.name AND usernames.normalized_name IS NULL",
+        )
+        .bind(names)
+        .bind(normalized)
     }
 }
 
@@ -95,19 +128,72 @@ impl VerificationBan {
         let mut name = name.into();
         name.make_ascii_lowercase();
         sqlx::query_as(
-            "SELECT \
-                ban.user_id, ban.reason, username.name, username.discriminator \
+            "SELECT DISTINCT \
+                bans.user_id, bans.reason, usernames.name, \
+                to_char(coalesce(usernames.discriminator, 0), 'FM0000') AS discriminator \
             FROM bans \
-            LEFT JOIN usernames \
+            INNER JOIN usernames \
                 ON bans.user_id = usernames.user_id \
             WHERE \
-                ban.guild_id = $1 AND \
-                LOWER(username.name) = $2",
+                bans.guild_id = $1 AND \
+                LOWER(usernames.name) = $2",
         )
         .bind(guild_id.0 as i64)
         .bind(name)
     }
 
+    /// Like `fetch_by_name`, but matches names by their normalized form instead, i.e. names that
+    /// only differ by confusable or invisible characters. The given name must already be
+    /// normalized, see `Username::normalized_name`.
+    pub fn fetch_by_normalized_name<'a>(
+        guild_id: GuildId,
+        normalized: impl Into<String>,
+    ) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "SELECT DISTINCT \
+                bans.user_id, bans.reason, usernames.name, \
+                to_char(coalesce(usernames.discriminator, 0), 'FM0000') AS discriminator \
+            FROM bans \
+            INNER JOIN usernames \
+                ON bans.user_id = usernames.user_id \
+            WHERE \
+                bans.guild_id = $1 AND \
+                usernames.normalized_name = $2",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(normalized.into())
+    }
+
+    /// Like `fetch_by_normalized_name`, but also matches names that are similar to the given
+    /// normalized name rather than only exact matches. See `search::NameMatch` for how names
+    /// are compared. Results are ordered by similarity, best match first.
+    pub fn fetch_by_similar_name<'a>(
+        guild_id: GuildId,
+        normalized: impl Into<String>,
+        min_similarity: f32,
+    ) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "SELECT user_id, reason, name, discriminator FROM ( \
+                SELECT DISTINCT ON (bans.user_id) \
+                    bans.user_id, bans.reason, usernames.name, \
+                    to_char(coalesce(usernames.discriminator, 0), 'FM0000') AS discriminator, \
+                    similarity(usernames.normalized_name, $2) AS similarity \
+                FROM bans \
+                INNER JOIN usernames \
+                    ON bans.user_id = usernames.user_id \
+                WHERE \
+                    bans.guild_id = $1 AND \
+                    usernames.normalized_name % $2 \
+                ORDER BY bans.user_id, similarity DESC \
+            ) AS matches \
+            WHERE similarity >= $3 \
+            ORDER BY similarity DESC",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(normalized.into())
+        .bind(min_similarity)
+    }
+
     pub fn fetch_by_avatar<'a>(
         guild_id: GuildId,
         avatar: impl Into<String>,
@@ -115,18 +201,112 @@ impl VerificationBan {
         let mut avatar = avatar.into();
         avatar.make_ascii_lowercase();
         sqlx::query_as(
-            "SELECT \
-                ban.user_id, ban.reason, username.name, username.discriminator \
+            "SELECT DISTINCT \
+                bans.user_id, bans.reason, coalesce(usernames.name, '') AS name, \
+                to_char(coalesce(usernames.discriminator, 0), 'FM0000') AS discriminator \
             FROM bans \
             LEFT JOIN usernames \
                 ON bans.user_id = usernames.user_id \
             WHERE \
-                ban.guild_id = $1 AND \
-                LOWER(ban.avatar) = $2",
+                bans.guild_id = $1 AND \
+                LOWER(bans.avatar) = $2",
         )
         .bind(guild_id.0 as i64)
         .bind(avatar)
     }
+
+    /// Like `fetch_by_avatar`, but matches banned users whose avatar's perceptual hash differs
+    /// from the given hash by at most `max_distance` bits. Results are ordered by distance,
+    /// closest match first.
+    pub fn fetch_by_avatar_hash<'a>(
+        guild_id: GuildId,
+        hash: u64,
+        max_distance: u32,
+    ) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "SELECT user_id, reason, name, discriminator FROM ( \
+                SELECT DISTINCT ON (bans.user_id) \
+                    bans.user_id, bans.reason, coalesce(usernames.name, '') AS name, \
+                    to_char(coalesce(usernames.discriminator, 0), 'FM0000') AS discriminator, \
+                    hamming_distance(avatar_hashes.hash, $2) AS distance \
+                FROM bans \
+                INNER JOIN avatar_hashes \
+                    ON bans.user_id = avatar_hashes.user_id AND \
+                       bans.avatar = avatar_hashes.avatar \
+                LEFT JOIN usernames \
+                    ON bans.user_id = usernames.user_id \
+                WHERE \
+                    bans.guild_id = $1 AND \
+                    hamming_distance(avatar_hashes.hash, $2) <= $3 \
+                ORDER BY bans.user_id, usernames.timestamp DESC \
+            ) AS matches \
+            ORDER BY distance",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(hash as i64)
+        .bind(max_distance as i32)
+    }
+}
+
+/// A perceptual hash of a user's avatar. See `hourai_validation::avatar` for how these are
+/// computed.
+#[derive(Debug, sqlx::FromRow)]
+pub struct AvatarHash {
+    pub user_id: i64,
+    pub avatar: String,
+    pub hash: i64,
+}
+
+impl AvatarHash {
+    pub fn new(user_id: UserId, avatar: impl Into<String>, hash: u64) -> Self {
+        Self {
+            user_id: user_id.0 as i64,
+            avatar: avatar.into(),
+            hash: hash as i64,
+        }
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash as u64
+    }
+
+    pub fn fetch<'a>(user_id: UserId, avatar: impl Into<String>) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "SELECT user_id, avatar, hash FROM avatar_hashes WHERE user_id = $1 AND avatar = $2",
+        )
+        .bind(user_id.0 as i64)
+        .bind(avatar.into())
+    }
+
+    /// Constructs a query to fetch the avatars of banned users that have not been hashed yet.
+    /// Avatars are picked at random, so that ones that cannot be hashed do not hold up the rest.
+    pub fn fetch_unhashed_bans<'a>(limit: i64) -> SqlQueryAs<'a, (i64, String)> {
+        sqlx::query_as(
+            "SELECT user_id, avatar FROM ( \
+                SELECT DISTINCT bans.user_id, bans.avatar \
+                FROM bans \
+                LEFT JOIN avatar_hashes \
+                    ON bans.user_id = avatar_hashes.user_id AND \
+                       bans.avatar = avatar_hashes.avatar \
+                WHERE \
+                    bans.avatar IS NOT NULL AND \
+                    avatar_hashes.user_id IS NULL \
+            ) AS unhashed \
+            ORDER BY random() \
+            LIMIT $1",
+        )
+        .bind(limit)
+    }
+
+    pub fn insert<'a>(self) -> SqlQuery<'a> {
+        sqlx::query(
+            "INSERT INTO avatar_hashes (user_id, avatar, hash) VALUES ($1, $2, $3) \
+             ON CONFLICT ON CONSTRAINT avatar_hashes_pkey DO UPDATE SET hash = excluded.hash",
+        )
+        .bind(self.user_id)
+        .bind(self.avatar)
+        .bind(self.hash)
+    }
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -216,8 +396,8 @@ impl Ban {
                 bans.guild_id, bans.user_id, bans.reason, bans.avatar \
             FROM \
                 bans \
-            FULL OUTER JOIN \
-                admin_configs \
+            LEFT JOIN \
+                admin_configs ON bans.guild_id = admin_configs.id \
             WHERE \
                 bans.user_id = $1 AND \
                 (admin_configs.id IS NULL OR admin_configs.source_bans = true)",
@@ -226,7 +406,7 @@ impl Ban {
     }
 }
 
-#[derive(Debug, sqlx::FromRow)]
+#[derive(Debug, Clone, sqlx::FromRow)]
 pub struct Member {
     pub guild_id: i64,
     pub user_id: i64,
@@ -234,6 +414,11 @@ pub struct Member {
     pub nickname: Option<String>,
     pub bot: bool,
     pub premium_since: Option<DateTime<Utc>>,
+    /// Whether the member has yet to complete membership screening.
+    pub pending: bool,
+    /// The nickname as normalized by `hourai_validation::normalize::name`. None if the member has
+    /// no nickname, or it has not been normalized yet.
+    pub normalized_nickname: Option<String>,
 }
 
 impl From<&TwilightMember> for Member {
@@ -249,6 +434,8 @@ impl From<&TwilightMember> for Member {
             nickname: member.nick.clone(),
             bot: member.user.bot,
             premium_since: premium,
+            pending: member.pending,
+            normalized_nickname: None,
         }
     }
 }
@@ -266,6 +453,8 @@ impl From<&MemberUpdate> for Member {
             nickname: member.nick.clone(),
             bot: member.user.bot,
             premium_since: premium,
+            pending: member.pending,
+            normalized_nickname: None,
         }
     }
 }
@@ -297,14 +486,16 @@ impl Member {
 
     pub fn insert<'a>(self) -> SqlQuery<'a> {
         sqlx::query(
-            "INSERT INTO members (guild_id, user_id, role_ids, nickname, present, bot, premium_since) \
-                     VALUES ($1, $2, $3, $4, true, $5, $6) \
+            "INSERT INTO members (guild_id, user_id, role_ids, nickname, present, bot, premium_since, pending, normalized_nickname) \
+                     VALUES ($1, $2, $3, $4, true, $5, $6, $7, $8) \
                      ON CONFLICT ON CONSTRAINT members_pkey \
                      DO UPDATE SET \
                         role_ids = excluded.role_ids, \
                         nickname = excluded.nickname, \
+                        normalized_nickname = excluded.normalized_nickname, \
                         premium_since = excluded.premium_since, \
                         bot = excluded.bot, \
+                        pending = excluded.pending, \
                         last_seen = now(), \
                         present = true",
         )
@@ -314,6 +505,83 @@ impl Member {
         .bind(self.nickname)
         .bind(self.bot)
         .bind(self.premium_since)
+        .bind(self.pending)
+        .bind(self.normalized_nickname)
+    }
+
+    /// Constructs a query to bulk add or update multiple members. Each member may only appear
+    /// once in a batch.
+    pub fn bulk_insert<'a>(members: Vec<Self>) -> SqlQuery<'a> {
+        let guild_ids: Vec<i64> = members.iter().map(|m| m.guild_id).collect();
+        let user_ids: Vec<i64> = members.iter().map(|m| m.user_id).collect();
+        // Postgres arrays must be rectangular, so each member's roles are passed as an array
+        // literal and parsed back out by the query.
+        let role_ids: Vec<String> = members
+            .iter()
+            .map(|m| {
+                let ids: Vec<String> = m.role_ids.iter().map(|id| id.to_string()).collect();
+                format!("{{{}}}", ids.join(","))
+            })
+            .collect();
+        let nicknames: Vec<Option<String>> = members.iter().map(|m| m.nickname.clone()).collect();
+        let bots: Vec<bool> = members.iter().map(|m| m.bot).collect();
+        let premium_since: Vec<Option<DateTime<Utc>>> =
+            members.iter().map(|m| m.premium_since).collect();
+        let pending: Vec<bool> = members.iter().map(|m| m.pending).collect();
+        let normalized: Vec<Option<String>> = members
+            .iter()
+            .map(|m| m.normalized_nickname.clone())
+            .collect();
+        sqlx::query(
+            "INSERT INTO members \
+                (guild_id, user_id, role_ids, nickname, present, bot, premium_since, pending, \
+                 normalized_nickname) \
+             SELECT guild_id, user_id, role_ids::bigint[], nickname, true, bot, premium_since, \
+                pending, normalized_nickname \
+             FROM UNNEST ($1, $2, $3, $4, $5, $6, $7, $8) \
+             AS t(guild_id, user_id, role_ids, nickname, bot, premium_since, pending, \
+                normalized_nickname) \
+             ON CONFLICT ON CONSTRAINT members_pkey \
+             DO UPDATE SET \
+                role_ids = excluded.role_ids, \
+                nickname = excluded.nickname, \
+                normalized_nickname = excluded.normalized_nickname, \
+                premium_since = excluded.premium_since, \
+                bot = excluded.bot, \
+                pending = excluded.pending, \
+                last_seen = now(), \
+                present = true",
+        )
+        .bind(guild_ids)
+        .bind(user_ids)
+        .bind(role_ids)
+        .bind(nicknames)
+        .bind(bots)
+        .bind(premium_since)
+        .bind(pending)
+        .bind(normalized)
+    }
+
+    /// Fetches up to `limit` distinct nicknames that have not been normalized yet.
+    pub fn fetch_unnormalized_nicknames<'a>(limit: i64) -> SqlQueryAs<'a, (String,)> {
+        sqlx::query_as(
+            "SELECT DISTINCT nickname FROM members \
+             WHERE nickname IS NOT NULL AND normalized_nickname IS NULL LIMIT $1",
+        )
+        .bind(limit)
+    }
+
+    /// Constructs a query to set the normalized form of every member with each of the given
+    /// nicknames. Takes pairs of nicknames and their normalized forms.
+    pub fn bulk_set_normalized_nicknames<'a>(nicknames: Vec<(String, String)>) -> SqlQuery<'a> {
+        let (nicknames, normalized): (Vec<String>, Vec<String>) = nicknames.into_iter().unzip();
+        sqlx::query(
+            "UPDATE members SET normalized_nickname = t.normalized_nickname \
+             FROM UNNEST ($1, $2) AS t(nickname, normalized_nickname) \
+             WHERE members.nickname = t.nickname AND members.normalized_nickname IS NULL",
+        )
+        .bind(nicknames)
+        .bind(normalized)
     }
 
     pub fn count_guilds<'a>() -> SqlQueryAs<'a, (i64,)> {
@@ -324,6 +592,17 @@ impl Member {
         sqlx::query_as("SELECT count(*) FROM members")
     }
 
+    /// Counts the members present in each of the given guilds.
+    pub fn count_present_by_guild<'a>(guild_ids: &[GuildId]) -> SqlQueryAs<'a, (i64, i64)> {
+        let guild_ids: Vec<i64> = guild_ids.iter().map(|id| id.0 as i64).collect();
+        sqlx::query_as(
+            "SELECT guild_id, count(*) FROM members \
+             WHERE guild_id = ANY($1) AND present \
+             GROUP BY guild_id",
+        )
+        .bind(guild_ids)
+    }
+
     pub fn count_guild_members<'a>(
         guild_id: GuildId,
         include_bots: bool,
@@ -345,6 +624,38 @@ impl Member {
             .bind(user_id.0 as i64)
     }
 
+    /// Constructs a query to count the present members that have completed membership
+    /// screening, but do not have a role.
+    pub fn count_missing_role<'a>(guild_id: GuildId, role_id: RoleId) -> SqlQueryAs<'a, (i64,)> {
+        sqlx::query_as(
+            "SELECT count(*) FROM members \
+             WHERE guild_id = $1 AND present AND NOT pending AND NOT ($2 = ANY(role_ids))",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(role_id.0 as i64)
+    }
+
+    /// Constructs a query to fetch the IDs of the present members that have completed membership
+    /// screening, but do not have a role. Members are listed in user ID order, starting after
+    /// `after`.
+    pub fn fetch_missing_role<'a>(
+        guild_id: GuildId,
+        role_id: RoleId,
+        after: UserId,
+        limit: i64,
+    ) -> SqlQueryAs<'a, (i64,)> {
+        sqlx::query_as(
+            "SELECT user_id FROM members \
+             WHERE guild_id = $1 AND present AND NOT pending AND NOT ($2 = ANY(role_ids)) \
+                AND user_id > $3 \
+             ORDER BY user_id LIMIT $4",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(role_id.0 as i64)
+        .bind(after.0 as i64)
+        .bind(limit)
+    }
+
     /// Marks all members as not present in preparation for repopulating the column.
     pub fn clear_present_shard<'a>(shard_id: u64, shard_total: u64) -> SqlQuery<'a> {
         sqlx::query("UPDATE members SET present = false WHERE (guild_id >> 22) % $2 = $1")
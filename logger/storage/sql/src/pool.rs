@@ -0,0 +1,165 @@
+use crate::models::SqlDatabase;
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use hourai::config::{DatabasePoolConfig, HouraiConfig};
+use metrics::gauge;
+use sqlx::{
+    database::{Database, HasStatement},
+    postgres::{PgPool, PgPoolOptions},
+    Describe, Either, Error, Execute, Executor, Transaction,
+};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tracing::debug;
+
+/// A connection pool to the primary database, and to any read replicas of it.
+///
+/// When used as an executor, every statement is sent to the primary. Reads that can tolerate
+/// replication lag, i.e. stats and counts shown on dashboards, opt into the replicas with
+/// `replica()`. Transactions always run against the primary.
+#[derive(Clone, Debug)]
+pub struct SqlPool {
+    service: &'static str,
+    primary: PgPool,
+    replicas: Arc<[PgPool]>,
+    next_replica: Arc<AtomicUsize>,
+    max_connections: u32,
+}
+
+impl SqlPool {
+    /// Creates a pool from already connected pools to the primary and its replicas. See `init`
+    /// to connect with the configured settings.
+    pub fn new(
+        service: &'static str,
+        primary: PgPool,
+        replicas: Vec<PgPool>,
+        max_connections: u32,
+    ) -> Self {
+        Self {
+            service,
+            primary,
+            replicas: replicas.into(),
+            next_replica: Arc::new(AtomicUsize::new(0)),
+            max_connections,
+        }
+    }
+
+    pub fn primary(&self) -> &PgPool {
+        &self.primary
+    }
+
+    /// Gets the next read replica, or the primary if there are none. Replicas may lag behind the
+    /// primary, so this must not be used for reads that need to see recent writes.
+    pub fn replica(&self) -> &PgPool {
+        if self.replicas.is_empty() {
+            return self.primary_with_metrics();
+        }
+        let idx = self.next_replica.fetch_add(1, Ordering::Relaxed);
+        let pool = &self.replicas[idx % self.replicas.len()];
+        self.record_saturation("replica", pool);
+        pool
+    }
+
+    pub async fn begin(&self) -> sqlx::Result<Transaction<'static, SqlDatabase>> {
+        self.primary.begin().await
+    }
+
+    fn primary_with_metrics(&self) -> &PgPool {
+        self.record_saturation("primary", &self.primary);
+        &self.primary
+    }
+
+    fn record_saturation(&self, role: &'static str, pool: &PgPool) {
+        let size = pool.size();
+        let busy = size as usize - pool.num_idle().min(size as usize);
+        gauge!("hourai_sql_pool_connections", size as f64, "service" => self.service, "role" => role);
+        gauge!("hourai_sql_pool_busy_connections", busy as f64, "service" => self.service, "role" => role);
+        gauge!(
+            "hourai_sql_pool_saturation",
+            busy as f64 / self.max_connections.max(1) as f64,
+            "service" => self.service,
+            "role" => role
+        );
+    }
+}
+
+impl<'p> Executor<'p> for &'_ SqlPool {
+    type Database = SqlDatabase;
+
+    fn fetch_many<'e, 'q: 'e, E: 'q>(
+        self,
+        query: E,
+    ) -> BoxStream<
+        'e,
+        Result<
+            Either<<SqlDatabase as Database>::QueryResult, <SqlDatabase as Database>::Row>,
+            Error,
+        >,
+    >
+    where
+        E: Execute<'q, Self::Database>,
+    {
+        self.primary_with_metrics().fetch_many(query)
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E: 'q>(
+        self,
+        query: E,
+    ) -> BoxFuture<'e, Result<Option<<SqlDatabase as Database>::Row>, Error>>
+    where
+        E: Execute<'q, Self::Database>,
+    {
+        self.primary_with_metrics().fetch_optional(query)
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [<Self::Database as Database>::TypeInfo],
+    ) -> BoxFuture<'e, Result<<Self::Database as HasStatement<'q>>::Statement, Error>> {
+        self.primary.prepare_with(sql, parameters)
+    }
+
+    #[doc(hidden)]
+    fn describe<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+    ) -> BoxFuture<'e, Result<Describe<Self::Database>, Error>> {
+        self.primary.describe(sql)
+    }
+}
+
+async fn connect(url: &str, config: &DatabasePoolConfig) -> PgPool {
+    PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .connect_timeout(Duration::from_secs(config.acquire_timeout_secs))
+        .idle_timeout(config.idle_timeout_secs.map(Duration::from_secs))
+        .connect(url)
+        .await
+        .expect("Failed to initialize SQL connection pool")
+}
+
+/// Connects to the primary database and all of its read replicas, using the pool settings
+/// configured for the given service.
+pub(crate) async fn connect_all(config: &HouraiConfig, service: &'static str) -> SqlPool {
+    let pool_config = config
+        .database_pools
+        .get(service)
+        .cloned()
+        .unwrap_or_default();
+    debug!(
+        "Creating Postgres client for {} with {} replica(s): {:?}",
+        service,
+        config.database_replicas.len(),
+        pool_config
+    );
+    let primary = connect(&config.database, &pool_config).await;
+    let mut replicas = Vec::with_capacity(config.database_replicas.len());
+    for url in config.database_replicas.iter() {
+        replicas.push(connect(url, &pool_config).await);
+    }
+    SqlPool::new(service, primary, replicas, pool_config.max_connections)
+}
@@ -0,0 +1,91 @@
+use crate::models::SqlQueryAs;
+use hourai::models::id::*;
+
+/// A user whose username or nickname is similar to a searched name.
+///
+/// Names are compared by the trigram similarity of their normalized forms, see
+/// `hourai_validation::normalize::name`, which fold confusables, case and leetspeak, so "Ηоսrаі"
+/// and "h0urai" both match "hourai". Searched names must be normalized the same way. Names
+/// that have not been normalized yet are not matched. Similarity thresholds below pg_trgm's
+/// `similarity_threshold` (0.3 by default) have no effect, as the trigram index will not return
+/// weaker matches.
+#[derive(Debug, sqlx::FromRow)]
+pub struct NameMatch {
+    pub user_id: i64,
+    /// The name that matched, either a past or present username or a nickname.
+    pub name: String,
+    /// How similar the name is to the query, from 0 (nothing in common) to 1 (identical
+    /// normalized forms).
+    pub similarity: f32,
+}
+
+impl NameMatch {
+    pub fn user_id(&self) -> UserId {
+        UserId(self.user_id as u64)
+    }
+
+    /// Constructs a query to find the users that have ever had a username similar to the given
+    /// normalized name, best match first. Each user is returned at most once.
+    pub fn search_usernames<'a>(
+        normalized: impl Into<String>,
+        min_similarity: f32,
+        limit: i64,
+    ) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "SELECT * FROM ( \
+                SELECT DISTINCT ON (user_id) \
+                    user_id, name, similarity(normalized_name, $1) AS similarity \
+                FROM usernames \
+                WHERE normalized_name % $1 \
+                ORDER BY user_id, similarity DESC \
+             ) AS matches \
+             WHERE similarity >= $2 \
+             ORDER BY similarity DESC \
+             LIMIT $3",
+        )
+        .bind(normalized.into())
+        .bind(min_similarity)
+        .bind(limit)
+    }
+
+    /// Constructs a query to find the present members of a guild that have ever had a username,
+    /// or currently have a nickname, similar to the given normalized name, best match first.
+    /// Each user is returned at most once.
+    pub fn search_guild_members<'a>(
+        guild_id: GuildId,
+        normalized: impl Into<String>,
+        min_similarity: f32,
+        limit: i64,
+    ) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "SELECT * FROM ( \
+                SELECT DISTINCT ON (user_id) * FROM ( \
+                    SELECT \
+                        members.user_id, usernames.name, \
+                        similarity(usernames.normalized_name, $2) AS similarity \
+                    FROM members \
+                    INNER JOIN usernames ON members.user_id = usernames.user_id \
+                    WHERE \
+                        members.guild_id = $1 AND members.present AND \
+                        usernames.normalized_name % $2 \
+                    UNION ALL \
+                    SELECT \
+                        user_id, nickname AS name, \
+                        similarity(normalized_nickname, $2) AS similarity \
+                    FROM members \
+                    WHERE \
+                        guild_id = $1 AND present AND \
+                        normalized_nickname % $2 \
+                ) AS candidates \
+                ORDER BY user_id, similarity DESC \
+             ) AS matches \
+             WHERE similarity >= $3 \
+             ORDER BY similarity DESC \
+             LIMIT $4",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(normalized.into())
+        .bind(min_similarity)
+        .bind(limit)
+    }
+}
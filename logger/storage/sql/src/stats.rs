@@ -0,0 +1,96 @@
+use crate::models::{SqlQuery, SqlQueryAs};
+use chrono::{NaiveDate, Utc};
+use hourai::models::id::*;
+
+/// A counter tracked per guild per day.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DailyStat {
+    Joins,
+    Leaves,
+    Bans,
+    Messages,
+    VerificationPasses,
+    VerificationFailures,
+}
+
+/// The number of times various events occurred in a guild over a single UTC day.
+#[derive(Debug, sqlx::FromRow)]
+pub struct GuildDailyStats {
+    pub guild_id: i64,
+    pub day: NaiveDate,
+    pub joins: i32,
+    pub leaves: i32,
+    pub bans: i32,
+    pub messages: i32,
+    pub verification_passes: i32,
+    pub verification_failures: i32,
+}
+
+impl GuildDailyStats {
+    pub fn guild_id(&self) -> GuildId {
+        GuildId(self.guild_id as u64)
+    }
+
+    /// Constructs a query to add to one of a guild's counters for the current day.
+    pub fn increment<'a>(guild_id: GuildId, stat: DailyStat, amount: i32) -> SqlQuery<'a> {
+        Self::bulk_increment(stat, Utc::today().naive_utc(), vec![(guild_id, amount)])
+    }
+
+    /// Constructs a query to add to the same counter of many guilds for a given day.
+    pub fn bulk_increment<'a>(
+        stat: DailyStat,
+        day: NaiveDate,
+        amounts: impl IntoIterator<Item = (GuildId, i32)>,
+    ) -> SqlQuery<'a> {
+        let (guild_ids, amounts): (Vec<i64>, Vec<i32>) = amounts
+            .into_iter()
+            .map(|(guild_id, amount)| (guild_id.0 as i64, amount))
+            .unzip();
+        let zeros = vec![0; amounts.len()];
+        let column = |column: DailyStat| {
+            if column == stat {
+                amounts.clone()
+            } else {
+                zeros.clone()
+            }
+        };
+        sqlx::query(
+            "INSERT INTO guild_daily_stats (guild_id, day, joins, leaves, bans, messages, \
+                verification_passes, verification_failures) \
+             SELECT guild_id, $1, joins, leaves, bans, messages, \
+                verification_passes, verification_failures \
+             FROM UNNEST ($2, $3, $4, $5, $6, $7, $8) AS stats(guild_id, joins, leaves, bans, \
+                messages, verification_passes, verification_failures) \
+             ON CONFLICT ON CONSTRAINT guild_daily_stats_pkey DO UPDATE SET \
+                joins = guild_daily_stats.joins + excluded.joins, \
+                leaves = guild_daily_stats.leaves + excluded.leaves, \
+                bans = guild_daily_stats.bans + excluded.bans, \
+                messages = guild_daily_stats.messages + excluded.messages, \
+                verification_passes = \
+                    guild_daily_stats.verification_passes + excluded.verification_passes, \
+                verification_failures = \
+                    guild_daily_stats.verification_failures + excluded.verification_failures",
+        )
+        .bind(day)
+        .bind(guild_ids)
+        .bind(column(DailyStat::Joins))
+        .bind(column(DailyStat::Leaves))
+        .bind(column(DailyStat::Bans))
+        .bind(column(DailyStat::Messages))
+        .bind(column(DailyStat::VerificationPasses))
+        .bind(column(DailyStat::VerificationFailures))
+    }
+
+    /// Constructs a query to fetch a guild's stats for every day from `start` to `end`,
+    /// inclusive, oldest first. Days without any recorded events are omitted.
+    pub fn fetch<'a>(guild_id: GuildId, start: NaiveDate, end: NaiveDate) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "SELECT * FROM guild_daily_stats \
+             WHERE guild_id = $1 AND day BETWEEN $2 AND $3 \
+             ORDER BY day",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(start)
+        .bind(end)
+    }
+}
@@ -0,0 +1,119 @@
+use crate::models::{SqlQuery, SqlQueryAs};
+use hourai::models::id::*;
+
+/// The maximum length of a tag or alias name, in characters.
+pub const MAX_NAME_LENGTH: usize = 64;
+/// The maximum length of a tag's response, in characters. Matches Discord's message limit.
+pub const MAX_RESPONSE_LENGTH: usize = 2000;
+/// The maximum length of the command an alias expands to, in characters.
+pub const MAX_ALIAS_CONTENT_LENGTH: usize = 500;
+/// The maximum number of tags a single guild can have.
+pub const MAX_TAGS_PER_GUILD: i64 = 500;
+/// The maximum number of aliases a single guild can have.
+pub const MAX_ALIASES_PER_GUILD: i64 = 100;
+
+/// Normalizes a tag or alias name so that lookups are case insensitive.
+pub fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// A custom per-guild command that replies with a fixed response.
+#[derive(Debug, sqlx::FromRow)]
+pub struct Tag {
+    pub guild_id: i64,
+    pub tag: String,
+    pub response: String,
+}
+
+impl Tag {
+    pub fn guild_id(&self) -> GuildId {
+        GuildId(self.guild_id as u64)
+    }
+
+    /// Constructs a query to fetch a single tag. The name must already be normalized.
+    pub fn fetch<'a>(guild_id: GuildId, tag: impl Into<String>) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as("SELECT * FROM tags WHERE guild_id = $1 AND tag = $2")
+            .bind(guild_id.0 as i64)
+            .bind(tag.into())
+    }
+
+    /// Constructs a query to fetch all of a guild's tags, sorted by name.
+    pub fn fetch_guild<'a>(guild_id: GuildId) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as("SELECT * FROM tags WHERE guild_id = $1 ORDER BY tag")
+            .bind(guild_id.0 as i64)
+    }
+
+    /// Constructs a query to count the number of tags a guild has.
+    pub fn count_guild<'a>(guild_id: GuildId) -> SqlQueryAs<'a, (i64,)> {
+        sqlx::query_as("SELECT count(*) FROM tags WHERE guild_id = $1").bind(guild_id.0 as i64)
+    }
+
+    /// Constructs a query to create or overwrite the tag.
+    pub fn insert(&self) -> SqlQuery {
+        sqlx::query(
+            "INSERT INTO tags (guild_id, tag, response) VALUES ($1, $2, $3) \
+             ON CONFLICT ON CONSTRAINT tags_pkey DO UPDATE SET response = excluded.response",
+        )
+        .bind(self.guild_id)
+        .bind(self.tag.clone())
+        .bind(self.response.clone())
+    }
+
+    /// Constructs a query to delete a single tag. The name must already be normalized.
+    pub fn delete<'a>(guild_id: GuildId, tag: impl Into<String>) -> SqlQuery<'a> {
+        sqlx::query("DELETE FROM tags WHERE guild_id = $1 AND tag = $2")
+            .bind(guild_id.0 as i64)
+            .bind(tag.into())
+    }
+}
+
+/// A per-guild command name that expands to another command before it is run.
+#[derive(Debug, sqlx::FromRow)]
+pub struct Alias {
+    pub guild_id: i64,
+    pub name: String,
+    /// The command, without prefix, that the alias expands to.
+    pub content: String,
+}
+
+impl Alias {
+    pub fn guild_id(&self) -> GuildId {
+        GuildId(self.guild_id as u64)
+    }
+
+    /// Constructs a query to fetch a single alias. The name must already be normalized.
+    pub fn fetch<'a>(guild_id: GuildId, name: impl Into<String>) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as("SELECT * FROM aliases WHERE guild_id = $1 AND name = $2")
+            .bind(guild_id.0 as i64)
+            .bind(name.into())
+    }
+
+    /// Constructs a query to fetch all of a guild's aliases, sorted by name.
+    pub fn fetch_guild<'a>(guild_id: GuildId) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as("SELECT * FROM aliases WHERE guild_id = $1 ORDER BY name")
+            .bind(guild_id.0 as i64)
+    }
+
+    /// Constructs a query to count the number of aliases a guild has.
+    pub fn count_guild<'a>(guild_id: GuildId) -> SqlQueryAs<'a, (i64,)> {
+        sqlx::query_as("SELECT count(*) FROM aliases WHERE guild_id = $1").bind(guild_id.0 as i64)
+    }
+
+    /// Constructs a query to create or overwrite the alias.
+    pub fn insert(&self) -> SqlQuery {
+        sqlx::query(
+            "INSERT INTO aliases (guild_id, name, content) VALUES ($1, $2, $3) \
+             ON CONFLICT ON CONSTRAINT aliases_pkey DO UPDATE SET content = excluded.content",
+        )
+        .bind(self.guild_id)
+        .bind(self.name.clone())
+        .bind(self.content.clone())
+    }
+
+    /// Constructs a query to delete a single alias. The name must already be normalized.
+    pub fn delete<'a>(guild_id: GuildId, name: impl Into<String>) -> SqlQuery<'a> {
+        sqlx::query("DELETE FROM aliases WHERE guild_id = $1 AND name = $2")
+            .bind(guild_id.0 as i64)
+            .bind(name.into())
+    }
+}
@@ -0,0 +1,186 @@
+use crate::models::{SqlQuery, SqlQueryAs};
+use hourai::models::id::*;
+
+/// When a table's rows about a user are kept after they request erasure.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Retention {
+    /// The rows are deleted.
+    None,
+    /// The rows are records of moderation actions that must be retained. References to the user
+    /// as the moderator responsible for an action are still anonymized.
+    Always,
+    /// The rows are kept while the user has bans or ban history, as they are used to find banned
+    /// users evading their bans. They are deleted otherwise.
+    WhileBanned,
+}
+
+/// A table that stores data about individual users.
+///
+/// Every table that references a user ID must be listed in `USER_DATA_TABLES`, so that the data
+/// can be exported or erased when the user requests it.
+#[derive(Debug)]
+pub struct UserDataTable {
+    pub name: &'static str,
+    pub retention: Retention,
+    /// Selects the rows about the user as a JSON array. IDs are exported as strings, as they do
+    /// not fit in a double, and credentials are omitted.
+    export: &'static str,
+    /// Deletes or anonymizes the user's data, if there is any to remove.
+    erase: Option<&'static str>,
+}
+
+pub const USER_DATA_TABLES: &[UserDataTable] = &[
+    UserDataTable {
+        name: "usernames",
+        retention: Retention::WhileBanned,
+        export: "SELECT coalesce(json_agg(t), '[]')::text FROM ( \
+                    SELECT user_id::text, timestamp, name, discriminator \
+                    FROM usernames WHERE user_id = $1 ORDER BY timestamp \
+                 ) AS t",
+        erase: Some(
+            "DELETE FROM usernames WHERE user_id = $1 AND NOT EXISTS ( \
+                SELECT 1 FROM bans WHERE user_id = $1 \
+                UNION ALL SELECT 1 FROM ban_history WHERE user_id = $1 \
+             )",
+        ),
+    },
+    UserDataTable {
+        name: "members",
+        retention: Retention::None,
+        export: "SELECT coalesce(json_agg(t), '[]')::text FROM ( \
+                    SELECT guild_id::text, user_id::text, role_ids::text[], nickname, present, \
+                        last_seen, bot, premium_since \
+                    FROM members WHERE user_id = $1 ORDER BY guild_id \
+                 ) AS t",
+        erase: Some("DELETE FROM members WHERE user_id = $1"),
+    },
+    UserDataTable {
+        name: "oauth",
+        retention: Retention::None,
+        export: "SELECT coalesce(json_agg(t), '[]')::text FROM ( \
+                    SELECT user_id::text, expiration FROM oauth WHERE user_id = $1 \
+                 ) AS t",
+        erase: Some("DELETE FROM oauth WHERE user_id = $1"),
+    },
+    UserDataTable {
+        name: "avatar_hashes",
+        retention: Retention::WhileBanned,
+        export: "SELECT coalesce(json_agg(t), '[]')::text FROM ( \
+                    SELECT user_id::text, avatar, hash::text \
+                    FROM avatar_hashes WHERE user_id = $1 ORDER BY avatar \
+                 ) AS t",
+        erase: Some(
+            "DELETE FROM avatar_hashes WHERE user_id = $1 AND NOT EXISTS ( \
+                SELECT 1 FROM bans WHERE user_id = $1 \
+                UNION ALL SELECT 1 FROM ban_history WHERE user_id = $1 \
+             )",
+        ),
+    },
+    UserDataTable {
+        name: "bans",
+        retention: Retention::Always,
+        export: "SELECT coalesce(json_agg(t), '[]')::text FROM ( \
+                    SELECT guild_id::text, user_id::text, reason, avatar \
+                    FROM bans WHERE user_id = $1 ORDER BY guild_id \
+                 ) AS t",
+        erase: None,
+    },
+    UserDataTable {
+        name: "ban_history",
+        retention: Retention::Always,
+        export: "SELECT coalesce(json_agg(t), '[]')::text FROM ( \
+                    SELECT id, guild_id::text, user_id::text, banned_at, banned_by::text, \
+                        reason, unbanned_at, unbanned_by::text \
+                    FROM ban_history \
+                    WHERE user_id = $1 OR banned_by = $1 OR unbanned_by = $1 \
+                    ORDER BY banned_at, id \
+                 ) AS t",
+        erase: Some(
+            "UPDATE ban_history \
+             SET banned_by = nullif(banned_by, $1), unbanned_by = nullif(unbanned_by, $1) \
+             WHERE banned_by = $1 OR unbanned_by = $1",
+        ),
+    },
+    UserDataTable {
+        name: "cases",
+        retention: Retention::Always,
+        export: "SELECT coalesce(json_agg(t), '[]')::text FROM ( \
+                    SELECT guild_id::text, case_number, user_id::text, moderator_id::text, \
+                        encode(action, 'base64') AS action, reason, created_at \
+                    FROM cases WHERE user_id = $1 OR moderator_id = $1 \
+                    ORDER BY created_at, guild_id \
+                 ) AS t",
+        erase: Some("UPDATE cases SET moderator_id = NULL WHERE moderator_id = $1"),
+    },
+    UserDataTable {
+        name: "verification_results",
+        retention: Retention::Always,
+        export: "SELECT coalesce(json_agg(t), '[]')::text FROM ( \
+                    SELECT id, guild_id::text, user_id::text, created_at, approved, reasons, \
+                        config_version::text, moderator_id::text, appealed_at \
+                    FROM verification_results WHERE user_id = $1 OR moderator_id = $1 \
+                    ORDER BY created_at, id \
+                 ) AS t",
+        erase: Some("UPDATE verification_results SET moderator_id = NULL WHERE moderator_id = $1"),
+    },
+    UserDataTable {
+        name: "escalation_histories",
+        retention: Retention::Always,
+        export: "SELECT coalesce(json_agg(t), '[]')::text FROM ( \
+                    SELECT id, guild_id::text, subject_id::text, authorizer_id::text, \
+                        authorizer_name, display_name, timestamp, \
+                        encode(action, 'base64') AS action, level_delta \
+                    FROM escalation_histories WHERE subject_id = $1 OR authorizer_id = $1 \
+                    ORDER BY timestamp, id \
+                 ) AS t",
+        erase: Some(
+            "UPDATE escalation_histories SET authorizer_id = 0, authorizer_name = 'Deleted User' \
+             WHERE authorizer_id = $1",
+        ),
+    },
+    UserDataTable {
+        name: "pending_deescalations",
+        retention: Retention::Always,
+        export: "SELECT coalesce(json_agg(t), '[]')::text FROM ( \
+                    SELECT user_id::text, guild_id::text, expiration, amount, entry_id \
+                    FROM pending_deescalations WHERE user_id = $1 ORDER BY guild_id \
+                 ) AS t",
+        erase: None,
+    },
+];
+
+/// Constructs a query to check if a user has any bans or ban history, in which case the tables
+/// retained `WhileBanned` keep their rows about the user.
+pub fn has_ban_records<'a>(user_id: UserId) -> SqlQueryAs<'a, (bool,)> {
+    sqlx::query_as(
+        "SELECT EXISTS (SELECT 1 FROM bans WHERE user_id = $1) \
+            OR EXISTS (SELECT 1 FROM ban_history WHERE user_id = $1)",
+    )
+    .bind(user_id.0 as i64)
+}
+
+impl UserDataTable {
+    /// Whether the table's rows about the user are kept when they request erasure, given
+    /// whether they have any bans or ban history.
+    pub fn is_retained(&self, has_ban_records: bool) -> bool {
+        match self.retention {
+            Retention::None => false,
+            Retention::Always => true,
+            Retention::WhileBanned => has_ban_records,
+        }
+    }
+
+    /// Constructs a query to fetch all of the table's rows about a user, as a JSON array.
+    pub fn export<'a>(&self, user_id: UserId) -> SqlQueryAs<'a, (String,)> {
+        sqlx::query_as(self.export).bind(user_id.0 as i64)
+    }
+
+    /// Constructs a query to delete the user's rows from the table or, if the table is retained,
+    /// to anonymize references to the user as a moderator. Tables retained `WhileBanned` only
+    /// have the rows deleted if the user has no bans or ban history. Returns None if there is nothing to
+    /// remove.
+    pub fn erase<'a>(&self, user_id: UserId) -> Option<SqlQuery<'a>> {
+        self.erase
+            .map(|query| sqlx::query(query).bind(user_id.0 as i64))
+    }
+}
@@ -0,0 +1,187 @@
+use crate::models::SqlQueryAs;
+use chrono::{DateTime, Utc};
+use hourai::models::id::*;
+
+/// A decision on whether to verify a member of a guild, either made automatically by the guild's
+/// verifiers or by a moderator overriding them. Decisions are recorded by the Python bot, which
+/// runs verification when members join.
+#[derive(Debug, sqlx::FromRow)]
+pub struct VerificationResult {
+    pub id: i64,
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub approved: bool,
+    /// The reasons given for the decision: one per approval or rejection reason, or the
+    /// moderator's reason.
+    pub reasons: Vec<String>,
+    /// A fingerprint of the guild's verification config at the time of the decision, to tell
+    /// decisions made under different configs apart.
+    pub config_version: i64,
+    /// The moderator that made the decision. None for automatic decisions.
+    pub moderator_id: Option<i64>,
+    /// When the member appealed the decision, if they have.
+    pub appealed_at: Option<DateTime<Utc>>,
+}
+
+impl VerificationResult {
+    pub fn guild_id(&self) -> GuildId {
+        GuildId(self.guild_id as u64)
+    }
+
+    pub fn user_id(&self) -> UserId {
+        UserId(self.user_id as u64)
+    }
+
+    pub fn moderator_id(&self) -> Option<UserId> {
+        self.moderator_id.map(|id| UserId(id as u64))
+    }
+
+    pub fn is_override(&self) -> bool {
+        self.moderator_id.is_some()
+    }
+
+    /// Constructs a query to fetch the most recent decision about a member.
+    pub fn fetch_latest<'a>(guild_id: GuildId, user_id: UserId) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "SELECT * FROM verification_results WHERE guild_id = $1 AND user_id = $2 \
+             ORDER BY created_at DESC, id DESC LIMIT 1",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(user_id.0 as i64)
+    }
+
+    /// Constructs a query to fetch the decisions about a member, most recent first.
+    pub fn fetch_guild_user<'a>(
+        guild_id: GuildId,
+        user_id: UserId,
+        limit: i64,
+    ) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "SELECT * FROM verification_results WHERE guild_id = $1 AND user_id = $2 \
+             ORDER BY created_at DESC, id DESC LIMIT $3",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(user_id.0 as i64)
+        .bind(limit)
+    }
+
+    /// Constructs a query to fetch the rejections a user can still appeal, most recent first:
+    /// the latest decision in each guild, if it was a rejection made after `since` that has not
+    /// been appealed yet.
+    pub fn fetch_appealable<'a>(user_id: UserId, since: DateTime<Utc>) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "SELECT * FROM ( \
+                SELECT DISTINCT ON (guild_id) * FROM verification_results \
+                WHERE user_id = $1 \
+                ORDER BY guild_id, created_at DESC, id DESC \
+             ) AS latest \
+             WHERE NOT approved AND appealed_at IS NULL AND created_at >= $2 \
+             ORDER BY created_at DESC",
+        )
+        .bind(user_id.0 as i64)
+        .bind(since)
+    }
+
+    /// Constructs a query to mark a decision as appealed, returning the updated result. Returns
+    /// nothing if it was already appealed, so that each decision can only be appealed once.
+    pub fn mark_appealed<'a>(id: i64) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "UPDATE verification_results SET appealed_at = now() \
+             WHERE id = $1 AND appealed_at IS NULL RETURNING *",
+        )
+        .bind(id)
+    }
+}
+
+/// A job giving a guild's verification role to its existing members.
+#[derive(Debug, sqlx::FromRow)]
+pub struct VerificationPropagation {
+    pub guild_id: i64,
+    pub role_id: i64,
+    pub channel_id: i64,
+    pub message_id: i64,
+    /// The last member processed. Members are processed in user ID order.
+    pub last_member_id: i64,
+    /// The number of members that lacked the role when the job started.
+    pub total: i64,
+    pub granted: i64,
+    pub started_at: DateTime<Utc>,
+}
+
+impl VerificationPropagation {
+    pub fn guild_id(&self) -> GuildId {
+        GuildId(self.guild_id as u64)
+    }
+
+    pub fn role_id(&self) -> RoleId {
+        RoleId(self.role_id as u64)
+    }
+
+    pub fn channel_id(&self) -> ChannelId {
+        ChannelId(self.channel_id as u64)
+    }
+
+    pub fn message_id(&self) -> MessageId {
+        MessageId(self.message_id as u64)
+    }
+
+    pub fn last_member_id(&self) -> UserId {
+        UserId(self.last_member_id as u64)
+    }
+
+    /// Constructs a query to start a job, returning it. Returns nothing if the guild already has
+    /// a job in progress.
+    pub fn start<'a>(
+        guild_id: GuildId,
+        role_id: RoleId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        total: i64,
+    ) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "INSERT INTO verification_propagations \
+                (guild_id, role_id, channel_id, message_id, total) \
+             VALUES ($1, $2, $3, $4, $5) \
+             ON CONFLICT ON CONSTRAINT verification_propagations_pkey DO NOTHING \
+             RETURNING *",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(role_id.0 as i64)
+        .bind(channel_id.0 as i64)
+        .bind(message_id.0 as i64)
+        .bind(total)
+    }
+
+    /// Constructs a query to fetch the jobs in progress in any of the given guilds.
+    pub fn fetch_guilds<'a>(guild_ids: &[GuildId]) -> SqlQueryAs<'a, Self> {
+        let guild_ids: Vec<i64> = guild_ids.iter().map(|id| id.0 as i64).collect();
+        sqlx::query_as("SELECT * FROM verification_propagations WHERE guild_id = ANY($1)")
+            .bind(guild_ids)
+    }
+
+    /// Constructs a query to record a job's progress, returning the updated job. Returns
+    /// nothing if the job was cancelled.
+    pub fn record_progress<'a>(
+        guild_id: GuildId,
+        last_member_id: UserId,
+        granted: i64,
+    ) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as(
+            "UPDATE verification_propagations \
+             SET last_member_id = $2, granted = granted + $3 \
+             WHERE guild_id = $1 \
+             RETURNING *",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(last_member_id.0 as i64)
+        .bind(granted)
+    }
+
+    /// Constructs a query to end a guild's job, whether it finished or was cancelled, returning
+    /// it. Returns nothing if there was no job in progress.
+    pub fn finish<'a>(guild_id: GuildId) -> SqlQueryAs<'a, Self> {
+        sqlx::query_as("DELETE FROM verification_propagations WHERE guild_id = $1 RETURNING *")
+            .bind(guild_id.0 as i64)
+    }
+}
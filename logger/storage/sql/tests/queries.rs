@@ -0,0 +1,1222 @@
+//! Applies the migrations to a live Postgres database and runs every query against the resulting
+//! schema. These are ignored by default: point `DATABASE_URL` at a scratch database and run
+//! `cargo test -- --ignored` to run them.
+//!
+//! Every test runs inside of a transaction that is rolled back, so the database is left
+//! untouched aside from the applied migrations. Queries are run through a `SqlPool` with a
+//! read-only connection to the same database as its replica, so queries routed to the replica
+//! that write fail.
+
+use chrono::{Duration, TimeZone, Utc};
+use hourai::models::id::*;
+use hourai::proto::action::Action;
+use hourai_sql::actions::PendingAction;
+use hourai_sql::activity::{ActivityResolution, GuildActivity};
+use hourai_sql::ban_history::BanHistory;
+use hourai_sql::cases::Case;
+use hourai_sql::search::NameMatch;
+use hourai_sql::stats::{DailyStat, GuildDailyStats};
+use hourai_sql::tags::{self, Alias, Tag};
+use hourai_sql::user_data::{self, USER_DATA_TABLES};
+use hourai_sql::verification::{VerificationPropagation, VerificationResult};
+use hourai_sql::*;
+
+const GUILD: GuildId = GuildId(1 << 22);
+const OTHER_GUILD: GuildId = GuildId(2 << 22);
+const USER: UserId = UserId(1000);
+const OTHER_USER: UserId = UserId(1001);
+
+async fn connect() -> SqlPool {
+    let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set to run these tests");
+    let primary = postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&url)
+        .await
+        .expect("Failed to connect to the test database");
+    hourai_sql::migrate(&primary)
+        .await
+        .expect("Failed to apply migrations");
+    let replica = postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .after_connect(|conn| {
+            Box::pin(async move {
+                conn.execute("SET default_transaction_read_only = on")
+                    .await?;
+                Ok(())
+            })
+        })
+        .connect(&url)
+        .await
+        .expect("Failed to connect to the test database");
+    SqlPool::new("test", primary, vec![replica], 2)
+}
+
+fn ban(guild_id: GuildId, user_id: UserId, avatar: &str) -> Ban {
+    Ban {
+        guild_id: guild_id.0 as i64,
+        user_id: user_id.0 as i64,
+        reason: Some("Spam".to_owned()),
+        avatar: Some(avatar.to_owned()),
+    }
+}
+
+fn username(user_id: UserId, name: &str, discriminator: i32) -> Username {
+    Username {
+        user_id: user_id.0 as i64,
+        timestamp: Utc::now(),
+        name: name.to_owned(),
+        discriminator: Some(discriminator),
+        normalized_name: None,
+    }
+}
+
+fn member(guild_id: GuildId, user_id: UserId, role_ids: Vec<i64>) -> Member {
+    Member {
+        guild_id: guild_id.0 as i64,
+        user_id: user_id.0 as i64,
+        role_ids,
+        nickname: Some("Nick".to_owned()),
+        bot: false,
+        premium_since: None,
+        pending: false,
+        normalized_nickname: None,
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_migrations_are_idempotent() {
+    let pool = connect().await;
+    hourai_sql::migrate(pool.primary()).await.unwrap();
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_sql_pool_routing() {
+    let pool = connect().await;
+    // Not run in a transaction, as those always run against the primary.
+    let guild_id = GuildId(3 << 22);
+
+    // Only reads that opt into the replicas are sent to them.
+    let read_only = "SELECT current_setting('transaction_read_only')";
+    let (setting,): (String,) = query_as(read_only).fetch_one(&pool).await.unwrap();
+    assert_eq!(setting, "off");
+    let (setting,): (String,) = query_as(read_only).fetch_one(pool.replica()).await.unwrap();
+    assert_eq!(setting, "on");
+
+    // Reads see the writes made just before them.
+    member(guild_id, USER, vec![10])
+        .insert()
+        .execute(&pool)
+        .await
+        .unwrap();
+    let fetched = Member::fetch(guild_id, USER)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(fetched.role_ids, vec![10]);
+    Member::clear_guild(guild_id).execute(&pool).await.unwrap();
+    let fetched = Member::fetch(guild_id, USER)
+        .fetch_optional(&pool)
+        .await
+        .unwrap();
+    assert!(fetched.is_none());
+
+    // Writes fail if they are sent to the replica.
+    assert!(member(guild_id, USER, vec![10])
+        .insert()
+        .execute(pool.replica())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_username_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    username(USER, "Alice", 1)
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    // Duplicates are ignored.
+    username(USER, "Alice", 1)
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    Username::bulk_insert(vec![
+        username(USER, "Alice", 1),
+        username(OTHER_USER, "Bob", 2),
+    ])
+    .execute(&mut txn)
+    .await
+    .unwrap();
+
+    let names = Username::fetch(USER, None)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(names.len(), 1);
+    assert_eq!(names[0].name, "Alice");
+    assert_eq!(names[0].discriminator, Some(1));
+
+    let names = Username::fetch(OTHER_USER, Some(1))
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(names.len(), 1);
+    assert_eq!(names[0].name, "Bob");
+
+    // A batch may contain multiple names for the same user.
+    let mut renamed = username(OTHER_USER, "Robert", 2);
+    renamed.timestamp = renamed.timestamp + Duration::seconds(1);
+    Username::bulk_insert(vec![username(OTHER_USER, "Bobby", 2), renamed])
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    let names = Username::fetch(OTHER_USER, None)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(names.len(), 3);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_ban_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    ban(GUILD, USER, "a")
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    Ban::bulk_insert(vec![
+        ban(GUILD, OTHER_USER, "b"),
+        ban(OTHER_GUILD, USER, "c"),
+    ])
+    .execute(&mut txn)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO admin_configs (id, source_bans, is_blocked) VALUES ($1, false, false)",
+    )
+    .bind(OTHER_GUILD.0 as i64)
+    .execute(&mut txn)
+    .await
+    .unwrap();
+
+    let bans = Ban::fetch_guild_bans(GUILD)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(bans.len(), 2);
+
+    // Guilds that opted out of sourcing bans are excluded.
+    let bans = Ban::fetch_user_bans(USER)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(bans.len(), 1);
+    assert_eq!(bans[0].guild_id(), GUILD);
+
+    Ban::clear_ban(GUILD, USER).execute(&mut txn).await.unwrap();
+    let bans = Ban::fetch_guild_bans(GUILD)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(bans.len(), 1);
+
+    Ban::clear_guild(GUILD).execute(&mut txn).await.unwrap();
+    Ban::clear_shard(0, 1).execute(&mut txn).await.unwrap();
+    let bans = Ban::fetch_guild_bans(OTHER_GUILD)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert!(bans.is_empty());
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_verification_ban_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    ban(GUILD, USER, "abcdef")
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    username(USER, "Spammer", 42)
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+
+    let bans = VerificationBan::fetch_by_name(GUILD, "SPAMMER")
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(bans.len(), 1);
+    assert_eq!(bans[0].user_id, USER.0 as i64);
+    assert_eq!(bans[0].name, "Spammer");
+    assert_eq!(bans[0].discriminator, "0042");
+
+    let bans = VerificationBan::fetch_by_avatar(GUILD, "ABCDEF")
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(bans.len(), 1);
+    assert_eq!(bans[0].reason.as_deref(), Some("Spam"));
+
+    let bans = VerificationBan::fetch_by_name(OTHER_GUILD, "spammer")
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert!(bans.is_empty());
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_normalized_name_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    ban(GUILD, USER, "abcdef")
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    let mut normalized = username(USER, "Ꭰiscord", 1);
+    normalized.normalized_name = Some("discord".to_owned());
+    Username::bulk_insert(vec![normalized, username(USER, "D\u{200B}iscord", 1)])
+        .execute(&mut txn)
+        .await
+        .unwrap();
+
+    let names = Username::fetch(USER, None)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(names.len(), 2);
+    assert!(names
+        .iter()
+        .any(|u| u.normalized_name.as_deref() == Some("discord")));
+
+    let unnormalized: Vec<(String,)> = Username::fetch_unnormalized(i64::MAX)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert!(unnormalized.contains(&("D\u{200B}iscord".to_owned(),)));
+    assert!(!unnormalized.contains(&("Ꭰiscord".to_owned(),)));
+
+    Username::bulk_set_normalized(vec![("D\u{200B}iscord".to_owned(), "discord".to_owned())])
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    let unnormalized: Vec<(String,)> = Username::fetch_unnormalized(i64::MAX)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert!(!unnormalized.contains(&("D\u{200B}iscord".to_owned(),)));
+
+    let bans = VerificationBan::fetch_by_normalized_name(GUILD, "discord")
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(bans.len(), 2);
+    assert!(bans.iter().all(|ban| ban.user_id == USER.0 as i64));
+
+    let bans = VerificationBan::fetch_by_normalized_name(OTHER_GUILD, "discord")
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert!(bans.is_empty());
+
+    let mut nicknamed = member(GUILD, USER, vec![]);
+    nicknamed.nickname = Some("D\u{200B}iscord".to_owned());
+    nicknamed.insert().execute(&mut txn).await.unwrap();
+    let unnormalized: Vec<(String,)> = Member::fetch_unnormalized_nicknames(i64::MAX)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert!(unnormalized.contains(&("D\u{200B}iscord".to_owned(),)));
+
+    Member::bulk_set_normalized_nicknames(vec![(
+        "D\u{200B}iscord".to_owned(),
+        "discord".to_owned(),
+    )])
+    .execute(&mut txn)
+    .await
+    .unwrap();
+    let member = Member::fetch(GUILD, USER)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(member.normalized_nickname.as_deref(), Some("discord"));
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_avatar_hash_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    ban(GUILD, USER, "abcdef")
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    ban(GUILD, OTHER_USER, "012345")
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+
+    let mut unhashed = AvatarHash::fetch_unhashed_bans(10)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    unhashed.sort();
+    assert_eq!(
+        unhashed,
+        vec![
+            (USER.0 as i64, "abcdef".to_owned()),
+            (OTHER_USER.0 as i64, "012345".to_owned())
+        ]
+    );
+
+    // Uses the top bit to check that hashes survive the round trip through a signed column.
+    let hash: u64 = 0xF000_0000_0000_00FF;
+    AvatarHash::new(USER, "abcdef", hash)
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    AvatarHash::new(OTHER_USER, "012345", !hash)
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+
+    let stored = AvatarHash::fetch(USER, "abcdef")
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(stored.hash(), hash);
+    assert!(AvatarHash::fetch_unhashed_bans(10)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Three bits off.
+    let bans = VerificationBan::fetch_by_avatar_hash(GUILD, hash ^ 0b111, 4)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(bans.len(), 1);
+    assert_eq!(bans[0].user_id, USER.0 as i64);
+
+    let bans = VerificationBan::fetch_by_avatar_hash(GUILD, hash ^ 0b111, 2)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert!(bans.is_empty());
+
+    let bans = VerificationBan::fetch_by_avatar_hash(OTHER_GUILD, hash, 4)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert!(bans.is_empty());
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_ban_history_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+    let moderator = UserId(2000);
+
+    BanHistory::record_ban(GUILD, USER, None, Some("Spam".to_owned()))
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    // Recording the same ban again fills in the missing details.
+    BanHistory::record_ban(GUILD, USER, Some(moderator), Some("Other".to_owned()))
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    BanHistory::record_unban(GUILD, USER, Some(moderator))
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    BanHistory::record_ban(GUILD, USER, None, None)
+        .execute(&mut txn)
+        .await
+        .unwrap();
+
+    let history = BanHistory::fetch_guild_user(GUILD, USER)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].banned_by(), Some(moderator));
+    assert_eq!(history[0].reason.as_deref(), Some("Spam"));
+    assert_eq!(history[0].unbanned_by(), Some(moderator));
+    assert!(!history[0].is_active());
+    assert!(history[1].is_active());
+
+    // USER was unbanned and OTHER_USER was banned while offline.
+    let bans = vec![ban(GUILD, OTHER_USER, "a")];
+    BanHistory::reconcile_bans(GUILD, &bans)
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    BanHistory::reconcile_unbans(GUILD, &bans)
+        .execute(&mut txn)
+        .await
+        .unwrap();
+
+    let history = BanHistory::fetch_user(USER)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert!(history.iter().all(|ban| !ban.is_active()));
+    let history = BanHistory::fetch_user(OTHER_USER)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(history.len(), 1);
+    assert!(history[0].is_active());
+    assert_eq!(history[0].reason.as_deref(), Some("Spam"));
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_case_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    let action = |guild_id: GuildId, user_id: UserId| {
+        let mut action = Action::new();
+        action.set_guild_id(guild_id.0);
+        action.set_user_id(user_id.0);
+        action.mut_ban();
+        action
+    };
+    let first = Case::create(action(GUILD, USER), Some(OTHER_USER), None)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    let second = Case::create(action(GUILD, OTHER_USER), None, Some("Spam".to_owned()))
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    let other = Case::create(action(OTHER_GUILD, USER), None, None)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(first.case_number, 1);
+    assert_eq!(first.moderator_id(), Some(OTHER_USER));
+    assert!(first.action().has_ban());
+    assert_eq!(second.case_number, 2);
+    assert_eq!(second.reason.as_deref(), Some("Spam"));
+    assert_eq!(other.case_number, 1);
+    assert_eq!(other.guild_id(), OTHER_GUILD);
+
+    assert!(first.modlog_message().is_none());
+    Case::set_modlog_message(GUILD, 1, ChannelId(10), MessageId(20))
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    let updated = Case::update_reason(GUILD, 1, "Raiding")
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(updated.reason.as_deref(), Some("Raiding"));
+    assert_eq!(
+        updated.modlog_message(),
+        Some((ChannelId(10), MessageId(20)))
+    );
+    assert!(Case::update_reason(GUILD, 3, "Raiding")
+        .fetch_optional(&mut txn)
+        .await
+        .unwrap()
+        .is_none());
+
+    let cases = Case::fetch_guild_user(GUILD, USER)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(cases.len(), 1);
+    assert_eq!(cases[0].case_number, 1);
+    let cases = Case::fetch_user(USER).fetch_all(&mut txn).await.unwrap();
+    assert_eq!(cases.len(), 2);
+    let case = Case::fetch(GUILD, 2).fetch_one(&mut txn).await.unwrap();
+    assert_eq!(case.user_id(), OTHER_USER);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_name_search_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    // Names are normalized by the logger, the normalized forms here are what
+    // `hourai_validation::normalize::name` produces for them. Cyrillic "о" and "а", and a
+    // leetspeak "1".
+    let mut name = username(USER, "Hоurаi_Bot1", 1);
+    name.normalized_name = Some("hourai_botl".to_owned());
+    name.insert().execute(&mut txn).await.unwrap();
+    let mut name = username(OTHER_USER, "Somebody", 2);
+    name.normalized_name = Some("sornebody".to_owned());
+    name.insert().execute(&mut txn).await.unwrap();
+    member(GUILD, USER, vec![])
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    let mut nicknamed = member(GUILD, OTHER_USER, vec![]);
+    nicknamed.nickname = Some("Ноurаі".to_owned());
+    nicknamed.normalized_nickname = Some("hourai".to_owned());
+    nicknamed.insert().execute(&mut txn).await.unwrap();
+
+    let matches = NameMatch::search_usernames("hourai_botl", 0.5, 10)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].user_id(), USER);
+    assert!((matches[0].similarity - 1.0).abs() < f32::EPSILON);
+
+    let matches = NameMatch::search_guild_members(GUILD, "hourai", 0.3, 10)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(matches.len(), 2);
+    assert_eq!(matches[0].user_id(), OTHER_USER);
+    assert_eq!(matches[0].name, "Ноurаі");
+    assert_eq!(matches[1].user_id(), USER);
+
+    ban(GUILD, USER, "a")
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    let bans = VerificationBan::fetch_by_similar_name(GUILD, "hourai bot", 0.5)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(bans.len(), 1);
+    assert_eq!(bans[0].user_id, USER.0 as i64);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_tag_and_alias_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    let name = tags::normalize_name("  Rules ");
+    assert_eq!(name, "rules");
+    for response in &["Be nice.", "Be nicer."] {
+        Tag {
+            guild_id: GUILD.0 as i64,
+            tag: name.clone(),
+            response: response.to_string(),
+        }
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    }
+    let tag = Tag::fetch(GUILD, "rules")
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(tag.response, "Be nicer.");
+    assert!(Tag::fetch(OTHER_GUILD, "rules")
+        .fetch_optional(&mut txn)
+        .await
+        .unwrap()
+        .is_none());
+    let (count,) = Tag::count_guild(GUILD).fetch_one(&mut txn).await.unwrap();
+    assert_eq!(count, 1);
+
+    Alias {
+        guild_id: GUILD.0 as i64,
+        name: "r".to_owned(),
+        content: "tag rules".to_owned(),
+    }
+    .insert()
+    .execute(&mut txn)
+    .await
+    .unwrap();
+    let aliases = Alias::fetch_guild(GUILD).fetch_all(&mut txn).await.unwrap();
+    assert_eq!(aliases.len(), 1);
+    assert_eq!(aliases[0].content, "tag rules");
+
+    let result = Tag::delete(GUILD, "rules").execute(&mut txn).await.unwrap();
+    assert_eq!(result.rows_affected(), 1);
+    let result = Alias::delete(GUILD, "r").execute(&mut txn).await.unwrap();
+    assert_eq!(result.rows_affected(), 1);
+    assert!(Tag::fetch_guild(GUILD)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap()
+        .is_empty());
+    let (count,) = Alias::count_guild(GUILD).fetch_one(&mut txn).await.unwrap();
+    assert_eq!(count, 0);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_member_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    member(GUILD, USER, vec![10, 20])
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    member(GUILD, OTHER_USER, vec![20])
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+
+    let fetched = Member::fetch(GUILD, USER)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(fetched.user_id(), USER);
+    assert_eq!(fetched.nickname.as_deref(), Some("Nick"));
+
+    let mut updated = member(GUILD, USER, vec![10, 20]);
+    updated.nickname = None;
+    Member::bulk_insert(vec![updated, member(OTHER_GUILD, USER, vec![])])
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    let fetched = Member::fetch(GUILD, USER)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(fetched.role_ids, vec![10, 20]);
+    assert_eq!(fetched.nickname, None);
+    let fetched = Member::fetch(OTHER_GUILD, USER)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert!(fetched.role_ids.is_empty());
+    Member::clear_guild(OTHER_GUILD)
+        .execute(&mut txn)
+        .await
+        .unwrap();
+
+    Member::set_present(GUILD, OTHER_USER, false)
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    let (count,) = Member::count_guild_members(GUILD, true)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+    let (count,) = Member::count_guild_members(GUILD, false)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+    let counts = Member::count_present_by_guild(&[GUILD, OTHER_GUILD])
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(counts, vec![(GUILD.0 as i64, 1)]);
+
+    let (guilds,) = Member::count_guilds().fetch_one(&mut txn).await.unwrap();
+    assert!(guilds >= 1);
+    let (members,) = Member::count_members().fetch_one(&mut txn).await.unwrap();
+    assert!(members >= 2);
+
+    Member::clear_role(GUILD, RoleId(20))
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    let fetched = Member::fetch(GUILD, USER)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(fetched.role_ids().collect::<Vec<_>>(), vec![RoleId(10)]);
+
+    Member::clear_present_shard(0, 1)
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    let (count,) = Member::count_guild_members(GUILD, true)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+
+    Member::clear_guild(GUILD).execute(&mut txn).await.unwrap();
+    let fetched = Member::fetch(GUILD, USER)
+        .fetch_optional(&mut txn)
+        .await
+        .unwrap();
+    assert!(fetched.is_none());
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_pending_action_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    let mut action = Action::new();
+    action.set_guild_id(GUILD.0);
+    action.set_user_id(USER.0);
+    PendingAction::schedule(action.clone(), Utc::now() - Duration::minutes(1))
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    PendingAction::schedule(action, Utc::now() + Duration::hours(1))
+        .execute(&mut txn)
+        .await
+        .unwrap();
+
+    let expired = PendingAction::fetch_expired()
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(expired.len(), 1);
+    expired[0].delete().execute(&mut txn).await.unwrap();
+    let expired = PendingAction::fetch_expired()
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert!(expired.is_empty());
+}
+
+/// Records a decision the way the Python bot does.
+async fn record_result(
+    txn: &mut sqlx::Transaction<'_, Postgres>,
+    guild_id: GuildId,
+    user_id: UserId,
+    approved: bool,
+    moderator_id: Option<UserId>,
+    reason: &str,
+) -> VerificationResult {
+    sqlx::query_as(
+        "INSERT INTO verification_results \
+            (guild_id, user_id, approved, reasons, config_version, moderator_id) \
+         VALUES ($1, $2, $3, $4, 42, $5) RETURNING *",
+    )
+    .bind(guild_id.0 as i64)
+    .bind(user_id.0 as i64)
+    .bind(approved)
+    .bind(vec![reason.to_owned()])
+    .bind(moderator_id.map(|id| id.0 as i64))
+    .fetch_one(txn)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_verification_result_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    let rejected = record_result(&mut txn, GUILD, USER, false, None, "Rejected: New account").await;
+    assert!(!rejected.approved);
+    assert!(!rejected.is_override());
+    record_result(
+        &mut txn,
+        OTHER_GUILD,
+        USER,
+        true,
+        None,
+        "Approved: Has Nitro",
+    )
+    .await;
+
+    // Only the rejection can be appealed, and only once.
+    let appealable = VerificationResult::fetch_appealable(USER, Utc::now() - Duration::days(1))
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(appealable.len(), 1);
+    assert_eq!(appealable[0].guild_id(), GUILD);
+    let appealed = VerificationResult::mark_appealed(rejected.id)
+        .fetch_optional(&mut txn)
+        .await
+        .unwrap();
+    assert!(appealed.unwrap().appealed_at.is_some());
+    let appealed = VerificationResult::mark_appealed(rejected.id)
+        .fetch_optional(&mut txn)
+        .await
+        .unwrap();
+    assert!(appealed.is_none());
+
+    let overridden = record_result(
+        &mut txn,
+        GUILD,
+        USER,
+        true,
+        Some(OTHER_USER),
+        "Appeal accepted",
+    )
+    .await;
+    assert_eq!(overridden.moderator_id(), Some(OTHER_USER));
+    let latest = VerificationResult::fetch_latest(GUILD, USER)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(latest.id, overridden.id);
+    assert_eq!(latest.reasons, vec!["Appeal accepted".to_owned()]);
+    let history = VerificationResult::fetch_guild_user(GUILD, USER, 10)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(history.len(), 2);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_verification_propagation_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+    let role = RoleId(5);
+
+    member(GUILD, USER, vec![])
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    member(GUILD, OTHER_USER, vec![])
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    member(GUILD, UserId(1002), vec![role.0 as i64])
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    let mut pending = member(GUILD, UserId(1003), vec![]);
+    pending.pending = true;
+    pending.insert().execute(&mut txn).await.unwrap();
+
+    let (total,) = hourai_sql::Member::count_missing_role(GUILD, role)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(total, 2);
+    let batch = hourai_sql::Member::fetch_missing_role(GUILD, role, UserId(0), 1)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(batch, vec![(USER.0 as i64,)]);
+    let batch = hourai_sql::Member::fetch_missing_role(GUILD, role, USER, 10)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(batch, vec![(OTHER_USER.0 as i64,)]);
+
+    let job = VerificationPropagation::start(GUILD, role, ChannelId(1), MessageId(2), total)
+        .fetch_optional(&mut txn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(job.last_member_id(), UserId(0));
+    // Only one job can run in a guild at a time.
+    let duplicate = VerificationPropagation::start(GUILD, role, ChannelId(1), MessageId(3), total)
+        .fetch_optional(&mut txn)
+        .await
+        .unwrap();
+    assert!(duplicate.is_none());
+
+    let job = VerificationPropagation::record_progress(GUILD, USER, 1)
+        .fetch_optional(&mut txn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(job.last_member_id(), USER);
+    assert_eq!(job.granted, 1);
+    let jobs = VerificationPropagation::fetch_guilds(&[GUILD, OTHER_GUILD])
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(jobs.len(), 1);
+
+    let finished = VerificationPropagation::finish(GUILD)
+        .fetch_optional(&mut txn)
+        .await
+        .unwrap();
+    assert!(finished.is_some());
+    // Progress on a cancelled job is not recorded.
+    let job = VerificationPropagation::record_progress(GUILD, OTHER_USER, 1)
+        .fetch_optional(&mut txn)
+        .await
+        .unwrap();
+    assert!(job.is_none());
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_guild_activity_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    let start = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+    let samples = |messages: i32| -> Vec<GuildActivity> {
+        (0..12)
+            .map(|i| {
+                let bucket = start + Duration::minutes(5 * i);
+                let mut activity =
+                    GuildActivity::new(GUILD, ActivityResolution::FiveMinutes, bucket);
+                activity.online_count = 10 + i as i32;
+                activity.member_count = 100;
+                activity.message_count = messages;
+                activity
+            })
+            .collect()
+    };
+    GuildActivity::bulk_insert(samples(1))
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    // Message counts for the same bucket are summed.
+    GuildActivity::bulk_insert(samples(2))
+        .execute(&mut txn)
+        .await
+        .unwrap();
+
+    let end = start + Duration::hours(1);
+    let points = GuildActivity::fetch(GUILD, ActivityResolution::FiveMinutes, start, end)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(points.len(), 12);
+    assert!(points.iter().all(|p| p.message_count == 3));
+
+    let points = GuildActivity::fetch(GUILD, ActivityResolution::Hourly, start, end)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(points.len(), 1);
+    assert_eq!(points[0].bucket, start);
+    assert_eq!(points[0].message_count, 36);
+    assert_eq!(points[0].member_count, 100);
+
+    GuildActivity::downsample(
+        ActivityResolution::FiveMinutes,
+        ActivityResolution::Hourly,
+        end,
+    )
+    .execute(&mut txn)
+    .await
+    .unwrap();
+    GuildActivity::delete_before(ActivityResolution::FiveMinutes, end)
+        .execute(&mut txn)
+        .await
+        .unwrap();
+
+    let points = GuildActivity::fetch(GUILD, ActivityResolution::FiveMinutes, start, end)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(points.len(), 1);
+    assert_eq!(points[0].message_count, 36);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_guild_daily_stats_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    let today = Utc::today().naive_utc();
+    let yesterday = today.pred();
+    GuildDailyStats::increment(GUILD, DailyStat::Joins, 1)
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    GuildDailyStats::increment(GUILD, DailyStat::Joins, 2)
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    GuildDailyStats::bulk_increment(
+        DailyStat::Messages,
+        today,
+        vec![(GUILD, 10), (OTHER_GUILD, 5)],
+    )
+    .execute(&mut txn)
+    .await
+    .unwrap();
+    GuildDailyStats::bulk_increment(DailyStat::Bans, yesterday, vec![(GUILD, 1)])
+        .execute(&mut txn)
+        .await
+        .unwrap();
+
+    let stats = GuildDailyStats::fetch(GUILD, yesterday, today)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(stats.len(), 2);
+    assert_eq!(stats[0].day, yesterday);
+    assert_eq!(stats[0].bans, 1);
+    assert_eq!(stats[0].joins, 0);
+    assert_eq!(stats[1].day, today);
+    assert_eq!(stats[1].joins, 3);
+    assert_eq!(stats[1].messages, 10);
+    assert_eq!(stats[1].leaves, 0);
+
+    let stats = GuildDailyStats::fetch(OTHER_GUILD, today, today)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].messages, 5);
+    assert!(GuildDailyStats::fetch(GUILD, yesterday, yesterday.pred())
+        .fetch_all(&mut txn)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_user_data_tables_cover_schema() {
+    let pool = connect().await;
+    let tables: Vec<(String,)> = sqlx::query_as(
+        "SELECT DISTINCT table_name::text FROM information_schema.columns \
+         WHERE table_schema = current_schema() AND column_name IN \
+            ('user_id', 'subject_id', 'authorizer_id', 'banned_by', 'moderator_id')",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert!(!tables.is_empty());
+    for (table,) in tables {
+        assert!(
+            USER_DATA_TABLES.iter().any(|t| t.name == table),
+            "{} stores user data, but is missing from USER_DATA_TABLES",
+            table
+        );
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_user_data_queries() {
+    let pool = connect().await;
+    let mut txn = pool.begin().await.unwrap();
+
+    username(USER, "Hourai", 1)
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    member(GUILD, USER, vec![1])
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    ban(GUILD, USER, "a")
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    BanHistory::record_ban(GUILD, OTHER_USER, Some(USER), None)
+        .execute(&mut txn)
+        .await
+        .unwrap();
+
+    let export = |name: &str| USER_DATA_TABLES.iter().find(|t| t.name == name).unwrap();
+    let (usernames,) = export("usernames")
+        .export(USER)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert!(usernames.contains("\"name\":\"Hourai\""));
+    // IDs are exported as strings.
+    assert!(usernames.contains(&format!("\"user_id\":\"{}\"", USER.0)));
+    let (history,) = export("ban_history")
+        .export(USER)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert!(history.contains(&format!("\"user_id\":\"{}\"", OTHER_USER.0)));
+    let (empty,) = export("members")
+        .export(OTHER_USER)
+        .fetch_one(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(empty, "[]");
+
+    // Only banned by OTHER_USER, who has ban history but no active bans.
+    let unbanned = UserId(1002);
+    username(OTHER_USER, "Somebody", 2)
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    username(unbanned, "Nobody", 3)
+        .insert()
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    for (user_id, expected) in &[(USER, true), (OTHER_USER, true), (unbanned, false)] {
+        let (banned,) = user_data::has_ban_records(*user_id)
+            .fetch_one(&mut txn)
+            .await
+            .unwrap();
+        assert_eq!(banned, *expected);
+        assert_eq!(export("usernames").is_retained(banned), *expected);
+        assert!(!export("members").is_retained(banned));
+        assert!(export("bans").is_retained(banned));
+    }
+
+    for user_id in &[USER, OTHER_USER, unbanned] {
+        for table in USER_DATA_TABLES {
+            if let Some(query) = table.erase(*user_id) {
+                query.execute(&mut txn).await.unwrap();
+            }
+        }
+    }
+
+    // Usernames are kept while the user has bans or ban history, so that ban evasion can still
+    // be detected.
+    for (user_id, expected) in &[(USER, 1), (OTHER_USER, 1), (unbanned, 0)] {
+        let names = Username::fetch(*user_id, None)
+            .fetch_all(&mut txn)
+            .await
+            .unwrap();
+        assert_eq!(names.len(), *expected);
+    }
+    assert!(Member::fetch(GUILD, USER)
+        .fetch_optional(&mut txn)
+        .await
+        .unwrap()
+        .is_none());
+    // Bans are retained, but the user is no longer named as a moderator.
+    let bans = Ban::fetch_user_bans(USER)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(bans.len(), 1);
+    let history = BanHistory::fetch_user(OTHER_USER)
+        .fetch_all(&mut txn)
+        .await
+        .unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].banned_by(), None);
+}